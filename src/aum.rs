@@ -1,93 +1,519 @@
-use rust_decimal::prelude::ToPrimitive;
+use std::collections::HashMap;
+
 use rust_decimal::Decimal;
+use tracing::warn;
 
+use crate::config::{MissingPricePolicy, SatoshiRounding};
 use crate::error::{AppError, AppResult};
-use crate::models::{AumCalculation, BinanceData, SpotContribution};
-use crate::pricing::PriceProvider;
+use crate::models::{
+    normalize_symbol, AumCalculation, BaseAmount, BinanceData, PriceSource, SkippedAsset,
+    SpotContribution, Valuation,
+};
+use crate::pricing::{self, PriceProvider};
+
+#[derive(Debug, Clone, Default)]
+pub struct AumOptions {
+    pub missing_price_policy: MissingPricePolicy,
+    /// Adds UM position notional to the total. PM `actualEquity` already
+    /// contains each position's unrealized PnL, so only `notional - pnl` is
+    /// added to avoid counting that PnL twice.
+    pub include_um_notional: bool,
+    /// Prices each key asset as its value asset (e.g. `BETH` as `ETH`); the
+    /// original asset is still reported in the contribution.
+    pub asset_aliases: HashMap<String, String>,
+    /// Spot contributions worth less than this (in the base asset) are left
+    /// out of the total and the contribution list; their sum is reported as
+    /// `dust_base`.
+    pub dust_threshold: Option<Decimal>,
+    /// Applied when converting the total to whole 1e-8 units.
+    pub satoshi_rounding: SatoshiRounding,
+    /// Assets allowed to use [`PriceProvider::base_to_asset_fallback`] when
+    /// the primary lookup has no price; any other unpriced asset is a
+    /// `MissingPrice`.
+    pub fallback_assets: Vec<String>,
+    /// Percent taken off each named spot asset's value (e.g. `LDO` → `20`)
+    /// for the net AUM; the gross total is unaffected.
+    pub haircuts: HashMap<String, Decimal>,
+    /// Fixed base-to-asset prices, used before asking the provider.
+    pub price_overrides: HashMap<String, Decimal>,
+    /// Fixed base/USD price used instead of [`PriceProvider::base_to_usd`].
+    pub base_usd_override: Option<Decimal>,
+}
+
+impl AumOptions {
+    fn pricing_asset<'a>(&'a self, asset_upper: &'a str) -> &'a str {
+        self.asset_aliases
+            .get(asset_upper)
+            .map(String::as_str)
+            .unwrap_or(asset_upper)
+    }
+
+    fn is_dust(&self, amount_base: Decimal) -> bool {
+        self.dust_threshold
+            .is_some_and(|threshold| amount_base > Decimal::ZERO && amount_base < threshold)
+    }
+}
 
 pub async fn calculate_aum<P: PriceProvider + Sync>(
     data: &BinanceData,
     prices: &P,
 ) -> AppResult<AumCalculation> {
-    let mut spot_total_btc = Decimal::ZERO;
+    calculate_aum_with_options(data, prices, &AumOptions::default()).await
+}
+
+/// Computes AUM in the base asset.
+///
+/// All arithmetic is `rust_decimal`: 96-bit mantissa, at most 28 significant
+/// digits. Quotients are rounded to that precision, so a total is exact to
+/// well beyond 1e-8 for any realistic balance. Sums and quotients outside the
+/// decimal range (e.g. a huge amount over a near-zero price) fail with
+/// [`AppError::Arithmetic`] instead of panicking.
+#[tracing::instrument(
+    name = "calculate",
+    skip_all,
+    fields(
+        spot_balances = data.spot_balances.len(),
+        positions = data.positions.len(),
+        liabilities = data.margin_liabilities.len(),
+    )
+)]
+pub async fn calculate_aum_with_options<P: PriceProvider + Sync>(
+    data: &BinanceData,
+    prices: &P,
+    options: &AumOptions,
+) -> AppResult<AumCalculation> {
+    let base_asset = prices.base_asset();
+    // Drop prices left over from an earlier calculation that failed midway.
+    prices.take_symbol_prices();
+    let mut spot_total_base = Decimal::ZERO;
     let mut contributions = Vec::with_capacity(data.spot_balances.len());
+    let mut skipped_assets = Vec::new();
+    let mut dust_base = Decimal::ZERO;
+    let mut dust_assets = Vec::new();
+    let mut fallback_assets = Vec::new();
+    let mut haircut_base = Decimal::ZERO;
 
     for spot in &data.spot_balances {
-        let asset_upper = spot.asset.to_uppercase();
-        let (btc_to_asset_price, amount_btc) = if asset_upper == "WBTC" {
-            (Decimal::ONE, spot.amount)
-        } else {
-            let btc_to_asset = prices.btc_to_asset(&asset_upper).await?;
-            if btc_to_asset.is_zero() {
-                return Err(AppError::MissingPrice(asset_upper));
+        let asset_upper = normalize_symbol(&spot.asset);
+        let pricing_asset = options.pricing_asset(&asset_upper);
+        let priced = value_in_base(prices, pricing_asset, spot.amount, options).await;
+        // Only a price read from a live ticker has a basis.
+        let price_basis = match &priced {
+            Ok((_, _, PriceSource::Exchange | PriceSource::Fallback))
+                if !is_pegged(base_asset, pricing_asset) =>
+            {
+                prices.price_basis()
+            }
+            _ => None,
+        };
+
+        let (base_to_asset_price, amount_base, price_source) = match priced {
+            Ok((_, amount_base, _)) if options.is_dust(amount_base) => {
+                dust_base = checked(dust_base.checked_add(amount_base), "dust_base")?;
+                dust_assets.push(spot.asset.clone());
+                continue;
             }
-            (btc_to_asset, spot.amount / btc_to_asset)
+            Ok((price, amount_base, source)) => {
+                if source == PriceSource::Fallback {
+                    fallback_assets.push(spot.asset.clone());
+                }
+                (price, amount_base, source)
+            }
+            Err(AppError::MissingPrice(asset))
+                if options.missing_price_policy != MissingPricePolicy::Fail =>
+            {
+                let reason = match options.missing_price_policy {
+                    MissingPricePolicy::Zero => "price unavailable; valued at zero",
+                    _ => "price unavailable; excluded from total",
+                };
+                warn!(asset = %asset, reason, "spot asset could not be priced");
+                skipped_assets.push(SkippedAsset {
+                    asset: spot.asset.clone(),
+                    amount: spot.amount,
+                    reason: reason.to_string(),
+                });
+                if options.missing_price_policy == MissingPricePolicy::Skip {
+                    continue;
+                }
+                (Decimal::ZERO, Decimal::ZERO, PriceSource::Unpriced)
+            }
+            Err(err) => return Err(err),
         };
 
-        spot_total_btc += amount_btc;
+        spot_total_base = checked(spot_total_base.checked_add(amount_base), "spot_total_base")?;
+        let haircut_pct = options.haircuts.get(&asset_upper).copied();
+        let amount_base_net = match haircut_pct {
+            Some(pct) => {
+                let haircut = checked(
+                    amount_base
+                        .checked_mul(pct)
+                        .and_then(|v| v.checked_div(Decimal::ONE_HUNDRED)),
+                    "haircut_base",
+                )?;
+                haircut_base = checked(haircut_base.checked_add(haircut), "haircut_base")?;
+                let net = checked(amount_base.checked_sub(haircut), "amount_base_net")?;
+                Some(BaseAmount::new(net))
+            }
+            None => None,
+        };
         contributions.push(SpotContribution {
             asset: spot.asset.clone(),
             amount: spot.amount,
-            btc_to_asset_price,
-            amount_btc,
+            base_to_asset_price,
+            amount_base: BaseAmount::new(amount_base),
+            priced_as: (pricing_asset != asset_upper).then(|| pricing_asset.to_string()),
+            price_source,
+            price_basis,
+            haircut_pct,
+            amount_base_net,
         });
     }
 
-    let btc_usd_price = prices.btc_to_usd().await?;
-    if btc_usd_price.is_zero() {
-        return Err(AppError::MissingPrice("BTC/USD".to_string()));
+    let base_usd_price = match options.base_usd_override {
+        Some(price) => price,
+        None => prices.base_to_usd().await?,
+    };
+    if base_usd_price.is_zero() {
+        return Err(AppError::MissingPrice(format!("{base_asset}/USD")));
     }
 
-    let pm_equity_btc = data.pm_account_actual_equity / btc_usd_price;
-    let aum_btc = pm_equity_btc + spot_total_btc;
+    let um_notional_usd = checked_sum(
+        data.positions.iter().map(|p| Some(p.notional)),
+        "um_notional_usd",
+    )?;
+    let um_notional_base_included = if options.include_um_notional {
+        let excess_usd = checked_sum(
+            data.positions.iter().map(|p| p.notional.checked_sub(p.pnl)),
+            "um_notional_usd",
+        )?;
+        checked(
+            excess_usd.checked_div(base_usd_price),
+            "um_notional_base_included",
+        )?
+    } else {
+        Decimal::ZERO
+    };
 
-    if aum_btc < Decimal::ZERO {
-        return Err(AppError::NegativeAum(aum_btc.to_string()));
+    // Liabilities are never skipped on a missing price: dropping one would
+    // overstate AUM, so any pricing failure here is fatal.
+    let mut liabilities_base = Decimal::ZERO;
+    for liability in &data.margin_liabilities {
+        let asset_upper = normalize_symbol(&liability.asset);
+        let pricing_asset = options.pricing_asset(&asset_upper);
+        let (_, amount_base, source) =
+            value_in_base(prices, pricing_asset, liability.amount, options).await?;
+        if source == PriceSource::Fallback {
+            fallback_assets.push(liability.asset.clone());
+        }
+        liabilities_base = checked(
+            liabilities_base.checked_add(amount_base),
+            "liabilities_base",
+        )?;
     }
 
-    let aum_wbtc_u8 = (aum_btc * Decimal::from(100_000_000i64))
-        .trunc()
-        .to_i128()
-        .ok_or_else(|| AppError::InvalidConfig {
-            field: "aum_wbtc_u8",
-            reason: "failed to convert to i128".to_string(),
+    let pm_equity_base = checked(
+        data.pm_account_actual_equity.checked_div(base_usd_price),
+        "pm_equity_base",
+    )?;
+    // Classic futures is a separate account, so its equity is never part of
+    // the PM `actualEquity` and is added on its own.
+    let classic_futures_equity_usd = data
+        .classic_futures
+        .as_ref()
+        .map_or(Decimal::ZERO, |account| account.equity_usd);
+    let classic_futures_equity_base = checked(
+        classic_futures_equity_usd.checked_div(base_usd_price),
+        "classic_futures_equity_base",
+    )?;
+    let aum_base_18dp = checked_sum(
+        [
+            Some(pm_equity_base),
+            Some(classic_futures_equity_base),
+            Some(spot_total_base),
+            Some(um_notional_base_included),
+            Some(-liabilities_base),
+        ],
+        "aum_base",
+    )?;
+
+    if aum_base_18dp < Decimal::ZERO {
+        return Err(AppError::NegativeAum(aum_base_18dp.to_string()));
+    }
+
+    let aum_base_18dp = BaseAmount::new(aum_base_18dp);
+    let aum_base_u8 = aum_base_18dp
+        .to_units(options.satoshi_rounding.strategy())
+        .ok_or(AppError::Arithmetic {
+            context: "aum_base_u8",
         })?;
 
-    let aum_wbtc = Decimal::from_i128_with_scale(aum_wbtc_u8, 8);
+    let aum_base = aum_base_u8.to_amount();
+    let quotes = prices.take_symbol_prices();
+    let aum_base_net = if options.haircuts.is_empty() {
+        None
+    } else {
+        let net = checked(
+            aum_base_18dp.value().checked_sub(haircut_base),
+            "aum_base_net",
+        )?;
+        Some(BaseAmount::new(net))
+    };
 
     Ok(AumCalculation {
-        aum_btc_18dp: aum_btc,
-        aum_wbtc_u8,
-        aum_wbtc,
-        spot_total_btc,
+        base_asset: base_asset.to_string(),
+        aum_base_18dp,
+        aum_base_u8,
+        aum_base,
+        spot_total_base: BaseAmount::new(spot_total_base),
         pm_equity_usd: data.pm_account_actual_equity,
-        btc_usd_price,
+        classic_futures_equity_usd,
+        base_usd_price,
+        base_usd_price_overridden: options.base_usd_override.is_some(),
+        um_notional_usd,
+        um_notional_base_included: BaseAmount::new(um_notional_base_included),
+        liabilities_base: BaseAmount::new(liabilities_base),
         spot_contributions: contributions,
+        partial: !skipped_assets.is_empty(),
+        skipped_assets,
+        dust_base: BaseAmount::new(dust_base),
+        dust_assets,
+        haircut_base: BaseAmount::new(haircut_base),
+        aum_base_net,
+        fallback_assets,
+        prices_as_of: quotes.values().map(|q| q.as_of).min(),
+        prices_as_of_latest: quotes.values().map(|q| q.as_of).max(),
+        live_fallback_symbols: quotes
+            .iter()
+            .filter(|(_, quote)| quote.live_fallback)
+            .map(|(symbol, _)| symbol.clone())
+            .collect(),
+        prices: quotes.into_iter().map(|(s, q)| (s, q.price)).collect(),
+        valuations: Vec::new(),
     })
 }
 
+/// Fills `calc.valuations` with the AUM in each of `currencies`, from the
+/// same balances. `USD` is priced as `usd_quote`; price overrides apply as
+/// they do to spot balances. The symbol prices looked up are added to
+/// `calc.prices`.
+pub async fn add_valuations<P: PriceProvider + Sync>(
+    calc: &mut AumCalculation,
+    currencies: &[String],
+    usd_quote: &str,
+    prices: &P,
+    options: &AumOptions,
+) -> AppResult<()> {
+    for currency in currencies {
+        let asset = if currency == "USD" {
+            usd_quote
+        } else {
+            currency
+        };
+        let rate = if asset == prices.base_asset() {
+            Decimal::ONE
+        } else if let Some(&price) = options.price_overrides.get(asset) {
+            price
+        } else {
+            prices.base_to_asset(asset).await?
+        };
+        calc.valuations.push(Valuation {
+            currency: currency.clone(),
+            rate,
+            aum: checked(calc.aum_base_18dp.value().checked_mul(rate), "valuations")?,
+        });
+    }
+    for (symbol, quote) in prices.take_symbol_prices() {
+        calc.prices_as_of = Some(
+            calc.prices_as_of
+                .map_or(quote.as_of, |as_of| as_of.min(quote.as_of)),
+        );
+        calc.prices_as_of_latest = Some(
+            calc.prices_as_of_latest
+                .map_or(quote.as_of, |as_of| as_of.max(quote.as_of)),
+        );
+        if quote.live_fallback && !calc.live_fallback_symbols.contains(&symbol) {
+            calc.live_fallback_symbols.push(symbol.clone());
+        }
+        calc.prices.entry(symbol).or_insert(quote.price);
+    }
+    Ok(())
+}
+
+/// Returns `(base_to_asset_price, amount_base, source)` for `amount` of
+/// `asset_upper`. An override wins over every other route; the wrapped base
+/// asset (`WBTC` for `BTC`) is pegged 1:1.
+async fn value_in_base<P: PriceProvider + Sync>(
+    prices: &P,
+    asset_upper: &str,
+    amount: Decimal,
+    options: &AumOptions,
+) -> AppResult<(Decimal, Decimal, PriceSource)> {
+    let (base_to_asset, source) = if let Some(&price) = options.price_overrides.get(asset_upper) {
+        (price, PriceSource::Override)
+    } else if is_pegged(prices.base_asset(), asset_upper) {
+        return Ok((Decimal::ONE, amount, PriceSource::Exchange));
+    } else {
+        match prices.base_to_asset(asset_upper).await {
+            Err(AppError::MissingPrice(_))
+                if options.fallback_assets.iter().any(|a| a == asset_upper) =>
+            {
+                let price = prices.base_to_asset_fallback(asset_upper).await?;
+                warn!(asset = asset_upper, "priced through the fallback route");
+                (price, PriceSource::Fallback)
+            }
+            other => (other?, PriceSource::Exchange),
+        }
+    };
+    if base_to_asset.is_zero() {
+        return Err(AppError::MissingPrice(asset_upper.to_string()));
+    }
+    let amount_base = checked(amount.checked_div(base_to_asset), "amount_base")?;
+    Ok((base_to_asset, amount_base, source))
+}
+
+/// The base asset itself and its wrapped form (`WBTC` for `BTC`), both
+/// worth exactly one base unit without a ticker.
+fn is_pegged(base_asset: &str, asset_upper: &str) -> bool {
+    asset_upper == base_asset || asset_upper.strip_prefix('W') == Some(base_asset)
+}
+
+/// Self-check of a finished calculation against the balances it came from:
+/// `aum_base` in USD must lie within `tolerance_pct` of PM and classic futures
+/// equity plus every fetched spot balance, less every margin liability, plus
+/// any included UM notional. Balances are repriced from `data` at the price
+/// the calculation used for their asset, never taken from its totals or
+/// contribution list, so a balance or term counted twice shows up as a
+/// deviation. Spot balances the calculation left out (dust, unpriced) are
+/// left out here too.
+pub fn check_bounds(
+    data: &BinanceData,
+    calc: &AumCalculation,
+    tolerance_pct: Decimal,
+) -> AppResult<()> {
+    let left_out = |asset: &str| {
+        calc.dust_assets.iter().any(|a| a == asset)
+            || calc.skipped_assets.iter().any(|s| s.asset == asset)
+    };
+    let mut spot_base = Decimal::ZERO;
+    for balance in data.spot_balances.iter().filter(|b| !left_out(&b.asset)) {
+        let Some(value) = reprice(calc, &balance.asset, balance.amount) else {
+            warn!(asset = %balance.asset, "no price to check the AUM bounds with");
+            return Ok(());
+        };
+        spot_base = checked(spot_base.checked_add(value), "expected_base")?;
+    }
+    let mut liabilities_base = Decimal::ZERO;
+    for liability in &data.margin_liabilities {
+        let Some(value) = reprice(calc, &liability.asset, liability.amount) else {
+            warn!(asset = %liability.asset, "no price to check the AUM bounds with");
+            return Ok(());
+        };
+        liabilities_base = checked(liabilities_base.checked_add(value), "expected_base")?;
+    }
+    let um_notional_usd = if calc.um_notional_base_included.is_zero() {
+        Decimal::ZERO
+    } else {
+        checked_sum(
+            data.positions.iter().map(|p| p.notional.checked_sub(p.pnl)),
+            "expected_usd",
+        )?
+    };
+    let classic_futures_equity_usd = data
+        .classic_futures
+        .as_ref()
+        .map_or(Decimal::ZERO, |account| account.equity_usd);
+    let net_base = checked(spot_base.checked_sub(liabilities_base), "expected_base")?;
+    let expected_usd = checked_sum(
+        [
+            Some(data.pm_account_actual_equity),
+            Some(classic_futures_equity_usd),
+            Some(um_notional_usd),
+            net_base.checked_mul(calc.base_usd_price),
+        ],
+        "expected_usd",
+    )?;
+    let aum_usd = checked(
+        calc.aum_base_18dp.value().checked_mul(calc.base_usd_price),
+        "aum_usd",
+    )?;
+    let Some(deviation_pct) = pricing::deviation_pct(aum_usd, expected_usd) else {
+        return Ok(());
+    };
+    if deviation_pct <= tolerance_pct {
+        return Ok(());
+    }
+    Err(AppError::AumOutOfBounds {
+        aum_usd: aum_usd.round_dp(2),
+        expected_usd: expected_usd.round_dp(2),
+        deviation_pct,
+        max_pct: tolerance_pct,
+    })
+}
+
+/// `amount` of `asset` in the base asset, at the price `calc` valued that
+/// asset at: its contribution's, or the raw `BASEASSET`/`ASSETBASE` quote.
+fn reprice(calc: &AumCalculation, asset: &str, amount: Decimal) -> Option<Decimal> {
+    let base = calc.base_asset.as_str();
+    let asset = normalize_symbol(asset);
+    if is_pegged(base, &asset) {
+        return Some(amount);
+    }
+    let contribution = calc
+        .spot_contributions
+        .iter()
+        .find(|c| normalize_symbol(&c.asset) == asset);
+    let base_to_asset = match contribution {
+        Some(c) => c.base_to_asset_price,
+        None => match calc.prices.get(&format!("{base}{asset}")) {
+            Some(&price) => price,
+            None => Decimal::ONE.checked_div(*calc.prices.get(&format!("{asset}{base}"))?)?,
+        },
+    };
+    amount.checked_div(base_to_asset)
+}
+
+fn checked(value: Option<Decimal>, context: &'static str) -> AppResult<Decimal> {
+    value.ok_or(AppError::Arithmetic { context })
+}
+
+/// Sums `values`, where `None` marks a term that already failed to compute.
+fn checked_sum(
+    values: impl IntoIterator<Item = Option<Decimal>>,
+    context: &'static str,
+) -> AppResult<Decimal> {
+    values
+        .into_iter()
+        .try_fold(Decimal::ZERO, |acc, value| acc.checked_add(value?))
+        .ok_or(AppError::Arithmetic { context })
+}
+
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
+
+    use chrono::Utc;
 
     use super::*;
-    use crate::models::{BinanceData, SpotBalance, UmPosition};
+    use crate::models::{
+        BaseUnits, BinanceData, ClassicFuturesAccount, MarginLiability, PriceBasis, SpotBalance,
+        UmPosition,
+    };
     use async_trait::async_trait;
 
     #[derive(Debug)]
     struct MockPriceProvider {
-        btc_usd: Decimal,
-        btc_to_asset: HashMap<String, Decimal>,
+        base_usd: Decimal,
+        base_to_asset: HashMap<String, Decimal>,
     }
 
     #[async_trait]
     impl PriceProvider for MockPriceProvider {
-        async fn btc_to_usd(&self) -> AppResult<Decimal> {
-            Ok(self.btc_usd)
+        async fn base_to_usd(&self) -> AppResult<Decimal> {
+            Ok(self.base_usd)
         }
 
-        async fn btc_to_asset(&self, asset: &str) -> AppResult<Decimal> {
-            self.btc_to_asset
+        async fn base_to_asset(&self, asset: &str) -> AppResult<Decimal> {
+            self.base_to_asset
                 .get(asset)
                 .cloned()
                 .ok_or_else(|| AppError::MissingPrice(asset.to_string()))
@@ -98,27 +524,71 @@ mod tests {
         Decimal::from(v)
     }
 
+    fn data(equity: Decimal, spot: &[(&str, Decimal)]) -> BinanceData {
+        BinanceData {
+            unimmr: Decimal::ZERO,
+            positions: vec![],
+            settlement_asset: "USDT".to_string(),
+            um_balance_usd: Decimal::ZERO,
+            spot_balances: spot
+                .iter()
+                .map(|&(asset, amount)| SpotBalance::new(asset, amount))
+                .collect(),
+            classic_futures: None,
+            pm_account_actual_equity: equity,
+            withdrawable_usd: Decimal::ZERO,
+            margin_liabilities: vec![],
+        }
+    }
+
     #[tokio::test]
     async fn computes_aum_with_pm_equity_only() {
         let data = BinanceData {
             unimmr: Decimal::ZERO,
             positions: vec![],
-            um_balance_usdt: Decimal::ZERO,
+            settlement_asset: "USDT".to_string(),
+            um_balance_usd: Decimal::ZERO,
             spot_balances: vec![],
+            classic_futures: None,
             pm_account_actual_equity: d(200_000),
-            withdrawable_usdt: Decimal::ZERO,
+            withdrawable_usd: Decimal::ZERO,
+            margin_liabilities: vec![],
         };
 
         let prices = MockPriceProvider {
-            btc_usd: d(100_000),
-            btc_to_asset: HashMap::new(),
+            base_usd: d(100_000),
+            base_to_asset: HashMap::new(),
         };
 
         let result = calculate_aum(&data, &prices)
             .await
             .expect("calc should work");
-        assert_eq!(result.aum_btc_18dp, Decimal::from_i128_with_scale(2, 0));
-        assert_eq!(result.aum_wbtc_u8, 200_000_000);
+        assert_eq!(result.aum_base_18dp, BaseAmount::new(Decimal::TWO));
+        assert_eq!(result.aum_base_u8, BaseUnits::new(200_000_000));
+    }
+
+    #[tokio::test]
+    async fn classic_futures_equity_adds_to_pm_equity() {
+        let data = BinanceData {
+            classic_futures: Some(ClassicFuturesAccount {
+                equity_usd: d(50_000),
+                wallet_balance_usd: d(40_000),
+                unrealized_pnl_usd: d(10_000),
+                balances: vec![],
+            }),
+            ..data(d(200_000), &[])
+        };
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: HashMap::new(),
+        };
+
+        let result = calculate_aum(&data, &prices)
+            .await
+            .expect("calc should work");
+        assert_eq!(result.pm_equity_usd, d(200_000));
+        assert_eq!(result.classic_futures_equity_usd, d(50_000));
+        assert_eq!(result.aum_base_18dp, BaseAmount::new(Decimal::new(25, 1)));
     }
 
     #[tokio::test]
@@ -129,29 +599,66 @@ mod tests {
                 symbol: "BTCUSDT".to_string(),
                 amount: Decimal::ONE,
                 pnl: Decimal::ZERO,
+                mark_price: d(100_000),
+                notional: d(100_000),
             }],
-            um_balance_usdt: Decimal::ZERO,
-            spot_balances: vec![SpotBalance {
-                asset: "ETH".to_string(),
-                amount: d(1),
-            }],
+            settlement_asset: "USDT".to_string(),
+            um_balance_usd: Decimal::ZERO,
+            spot_balances: vec![SpotBalance::new("ETH", d(1))],
+            classic_futures: None,
             pm_account_actual_equity: Decimal::ZERO,
-            withdrawable_usdt: Decimal::ZERO,
+            withdrawable_usd: Decimal::ZERO,
+            margin_liabilities: vec![],
         };
 
         let mut map = HashMap::new();
         map.insert("ETH".to_string(), d(50));
 
         let prices = MockPriceProvider {
-            btc_usd: d(100_000),
-            btc_to_asset: map,
+            base_usd: d(100_000),
+            base_to_asset: map,
         };
 
         let result = calculate_aum(&data, &prices)
             .await
             .expect("calc should work");
-        assert_eq!(result.aum_btc_18dp, Decimal::from_i128_with_scale(2, 2));
-        assert_eq!(result.aum_wbtc_u8, 2_000_000);
+        assert_eq!(result.aum_base_18dp, BaseAmount::new(Decimal::new(2, 2)));
+        assert_eq!(result.aum_base_u8, BaseUnits::new(2_000_000));
+    }
+
+    #[tokio::test]
+    async fn bounds_check_catches_a_double_counted_term() {
+        let data = data(d(200_000), &[("ETH", d(20))]);
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: HashMap::from([("ETH".to_string(), d(40))]),
+        };
+
+        let mut result = calculate_aum(&data, &prices)
+            .await
+            .expect("calc should work");
+        check_bounds(&data, &result, Decimal::ONE).expect("a correct total is in bounds");
+
+        // 2 BTC of equity + 0.5 BTC of spot, with the ETH contribution
+        // recorded twice and the totals summed from the contributions.
+        let duplicate = result.spot_contributions[0].clone();
+        result.spot_contributions.push(duplicate);
+        result.spot_total_base = BaseAmount::new(Decimal::ONE);
+        result.aum_base_18dp = BaseAmount::new(Decimal::new(3, 0));
+        match check_bounds(&data, &result, Decimal::ONE) {
+            Err(AppError::AumOutOfBounds {
+                aum_usd,
+                expected_usd,
+                deviation_pct,
+                ..
+            }) => {
+                assert_eq!(aum_usd, d(300_000));
+                assert_eq!(expected_usd, d(250_000));
+                assert_eq!(deviation_pct, d(20));
+            }
+            other => panic!("expected AumOutOfBounds, got {other:?}"),
+        }
+        assert!(check_bounds(&data, &result, d(25)).is_ok());
     }
 
     #[tokio::test]
@@ -159,15 +666,18 @@ mod tests {
         let data = BinanceData {
             unimmr: Decimal::ZERO,
             positions: vec![],
-            um_balance_usdt: Decimal::ZERO,
+            settlement_asset: "USDT".to_string(),
+            um_balance_usd: Decimal::ZERO,
             spot_balances: vec![],
+            classic_futures: None,
             pm_account_actual_equity: d(-1),
-            withdrawable_usdt: Decimal::ZERO,
+            withdrawable_usd: Decimal::ZERO,
+            margin_liabilities: vec![],
         };
 
         let prices = MockPriceProvider {
-            btc_usd: d(100_000),
-            btc_to_asset: HashMap::new(),
+            base_usd: d(100_000),
+            base_to_asset: HashMap::new(),
         };
 
         let err = calculate_aum(&data, &prices)
@@ -175,4 +685,440 @@ mod tests {
             .expect_err("negative aum must fail");
         assert!(matches!(err, AppError::NegativeAum(_)));
     }
+
+    #[tokio::test]
+    async fn oversized_aum_reports_overflow() {
+        let data = data(Decimal::MAX, &[]);
+
+        let prices = MockPriceProvider {
+            base_usd: Decimal::ONE,
+            base_to_asset: HashMap::new(),
+        };
+
+        let err = calculate_aum(&data, &prices)
+            .await
+            .expect_err("unit conversion must overflow");
+        assert!(matches!(
+            err,
+            AppError::Arithmetic {
+                context: "aum_base_u8"
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn out_of_range_quotient_is_an_arithmetic_error() {
+        let data = data(Decimal::ZERO, &[("SHIB", Decimal::from(10_000_000_000i64))]);
+
+        let mut map = HashMap::new();
+        map.insert("SHIB".to_string(), Decimal::new(1, 28));
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: map,
+        };
+
+        let err = calculate_aum(&data, &prices)
+            .await
+            .expect_err("quotient exceeds the decimal range");
+        assert!(matches!(
+            err,
+            AppError::Arithmetic {
+                context: "amount_base"
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_price_policy_controls_unpriced_assets() {
+        let data = data(Decimal::ZERO, &[("ETH", d(1)), ("NEW", d(10))]);
+
+        let mut map = HashMap::new();
+        map.insert("ETH".to_string(), d(50));
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: map,
+        };
+
+        let err = calculate_aum(&data, &prices)
+            .await
+            .expect_err("fail policy must propagate");
+        assert!(matches!(err, AppError::MissingPrice(_)));
+
+        let skip = AumOptions {
+            missing_price_policy: MissingPricePolicy::Skip,
+            ..AumOptions::default()
+        };
+        let result = calculate_aum_with_options(&data, &prices, &skip)
+            .await
+            .expect("skip policy should succeed");
+        assert!(result.partial);
+        assert_eq!(result.spot_contributions.len(), 1);
+        assert_eq!(result.skipped_assets[0].asset, "NEW");
+        assert_eq!(result.aum_base_u8, BaseUnits::new(2_000_000));
+
+        let zero = AumOptions {
+            missing_price_policy: MissingPricePolicy::Zero,
+            ..AumOptions::default()
+        };
+        let result = calculate_aum_with_options(&data, &prices, &zero)
+            .await
+            .expect("zero policy should succeed");
+        assert!(result.partial);
+        assert_eq!(result.spot_contributions.len(), 2);
+        assert_eq!(
+            result.spot_contributions[1].amount_base,
+            BaseAmount::new(Decimal::ZERO)
+        );
+        assert_eq!(
+            result.spot_contributions[1].price_source,
+            PriceSource::Unpriced
+        );
+        assert_eq!(result.aum_base_u8, BaseUnits::new(2_000_000));
+    }
+
+    #[tokio::test]
+    async fn um_notional_is_informational_unless_included() {
+        let data = BinanceData {
+            positions: vec![UmPosition {
+                symbol: "BTCUSDT".to_string(),
+                amount: Decimal::ONE,
+                pnl: d(10_000),
+                mark_price: d(100_000),
+                notional: d(100_000),
+            }],
+            ..data(d(100_000), &[])
+        };
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: HashMap::new(),
+        };
+
+        let result = calculate_aum(&data, &prices)
+            .await
+            .expect("calc should work");
+        assert_eq!(result.um_notional_usd, d(100_000));
+        assert_eq!(
+            result.um_notional_base_included,
+            BaseAmount::new(Decimal::ZERO)
+        );
+        assert_eq!(result.aum_base_u8, BaseUnits::new(100_000_000));
+
+        let options = AumOptions {
+            include_um_notional: true,
+            ..AumOptions::default()
+        };
+        let result = calculate_aum_with_options(&data, &prices, &options)
+            .await
+            .expect("calc should work");
+        assert_eq!(
+            result.um_notional_base_included,
+            BaseAmount::new(Decimal::new(9, 1))
+        );
+        assert_eq!(result.aum_base_u8, BaseUnits::new(190_000_000));
+    }
+
+    #[tokio::test]
+    async fn subtracts_margin_liabilities_before_negative_guard() {
+        let mut data = BinanceData {
+            margin_liabilities: vec![MarginLiability {
+                asset: "ETH".to_string(),
+                amount: d(25),
+            }],
+            ..data(d(200_000), &[])
+        };
+
+        let mut map = HashMap::new();
+        map.insert("ETH".to_string(), d(50));
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: map,
+        };
+
+        let result = calculate_aum(&data, &prices)
+            .await
+            .expect("calc should work");
+        assert_eq!(result.liabilities_base, BaseAmount::new(Decimal::new(5, 1)));
+        assert_eq!(result.aum_base_u8, BaseUnits::new(150_000_000));
+
+        data.margin_liabilities[0].amount = d(150);
+        let err = calculate_aum(&data, &prices)
+            .await
+            .expect_err("liabilities above equity must fail");
+        assert!(matches!(err, AppError::NegativeAum(_)));
+    }
+
+    #[tokio::test]
+    async fn aliased_assets_use_underlying_price() {
+        let data = data(Decimal::ZERO, &[("BETH", d(5))]);
+
+        let mut map = HashMap::new();
+        map.insert("ETH".to_string(), d(50));
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: map,
+        };
+
+        let options = AumOptions {
+            asset_aliases: HashMap::from([("BETH".to_string(), "ETH".to_string())]),
+            ..AumOptions::default()
+        };
+        let result = calculate_aum_with_options(&data, &prices, &options)
+            .await
+            .expect("alias should be priced");
+        let contribution = &result.spot_contributions[0];
+        assert_eq!(contribution.asset, "BETH");
+        assert_eq!(contribution.priced_as.as_deref(), Some("ETH"));
+        assert_eq!(result.aum_base_u8, BaseUnits::new(10_000_000));
+    }
+
+    #[tokio::test]
+    async fn price_overrides_win_over_the_provider() {
+        let data = data(Decimal::ZERO, &[("otc", d(400))]);
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: HashMap::new(),
+        };
+
+        let options = AumOptions {
+            price_overrides: HashMap::from([("OTC".to_string(), d(2_000))]),
+            ..AumOptions::default()
+        };
+        let result = calculate_aum_with_options(&data, &prices, &options)
+            .await
+            .expect("override should price the asset");
+        let contribution = &result.spot_contributions[0];
+        assert_eq!(contribution.price_source, PriceSource::Override);
+        assert_eq!(contribution.base_to_asset_price, d(2_000));
+        assert_eq!(result.aum_base_u8, BaseUnits::new(20_000_000));
+    }
+
+    #[tokio::test]
+    async fn price_basis_is_set_only_for_ticker_prices() {
+        let spot = ["ETH", "BTC", "WBTC", "OTC"].map(|asset| (asset, Decimal::ONE));
+        let data = data(Decimal::ZERO, &spot);
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: HashMap::from([("ETH".to_string(), d(25))]),
+        };
+        let options = AumOptions {
+            price_overrides: HashMap::from([("OTC".to_string(), d(2_000))]),
+            ..AumOptions::default()
+        };
+
+        let result = calculate_aum_with_options(&data, &prices, &options)
+            .await
+            .expect("every asset is priced");
+        let bases: Vec<_> = result
+            .spot_contributions
+            .iter()
+            .map(|c| c.price_basis)
+            .collect();
+        assert_eq!(bases, [Some(PriceBasis::Last), None, None, None]);
+    }
+
+    #[tokio::test]
+    async fn base_usd_override_replaces_the_provider_price() {
+        let data = data(d(80_000), &[("ETH", d(50))]);
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: HashMap::from([("ETH".to_string(), d(50))]),
+        };
+
+        let options = AumOptions {
+            base_usd_override: Some(d(80_000)),
+            ..AumOptions::default()
+        };
+        let result = calculate_aum_with_options(&data, &prices, &options)
+            .await
+            .expect("override should price the USD legs");
+        assert_eq!(result.base_usd_price, d(80_000));
+        assert!(result.base_usd_price_overridden);
+        // 80k USD at the override is 1 BTC, plus 1 BTC of ETH at its live price.
+        assert_eq!(result.aum_base_u8, BaseUnits::new(200_000_000));
+    }
+
+    #[tokio::test]
+    async fn live_fallback_quotes_are_listed() {
+        #[derive(Debug)]
+        struct DatedProvider;
+
+        #[async_trait]
+        impl PriceProvider for DatedProvider {
+            async fn base_to_usd(&self) -> AppResult<Decimal> {
+                Ok(d(100_000))
+            }
+
+            async fn base_to_asset(&self, asset: &str) -> AppResult<Decimal> {
+                Err(AppError::MissingPrice(asset.to_string()))
+            }
+
+            fn take_symbol_prices(&self) -> BTreeMap<String, pricing::Quote> {
+                let quote = |price, live_fallback| pricing::Quote {
+                    price: d(price),
+                    as_of: Utc::now(),
+                    live_fallback,
+                };
+                BTreeMap::from([
+                    ("BTCUSDT".to_string(), quote(100_000, true)),
+                    ("ETHBTC".to_string(), quote(1, false)),
+                ])
+            }
+        }
+
+        let result = calculate_aum(&data(d(200_000), &[]), &DatedProvider)
+            .await
+            .expect("calc should work");
+        assert_eq!(result.live_fallback_symbols, ["BTCUSDT"]);
+    }
+
+    #[tokio::test]
+    async fn valuations_use_one_snapshot_for_every_currency() {
+        let data = data(d(200_000), &[]);
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: HashMap::from([
+                ("USDT".to_string(), d(100_000)),
+                ("EUR".to_string(), d(90_000)),
+            ]),
+        };
+        let options = AumOptions::default();
+        let mut calc = calculate_aum_with_options(&data, &prices, &options)
+            .await
+            .unwrap();
+
+        let currencies = ["BTC", "USD", "EUR"].map(String::from);
+        add_valuations(&mut calc, &currencies, "USDT", &prices, &options)
+            .await
+            .unwrap();
+        let valuations: Vec<_> = calc
+            .valuations
+            .iter()
+            .map(|v| (v.currency.as_str(), v.aum))
+            .collect();
+        assert_eq!(
+            valuations,
+            [("BTC", d(2)), ("USD", d(200_000)), ("EUR", d(180_000))]
+        );
+    }
+
+    #[tokio::test]
+    async fn dust_is_excluded_but_reported() {
+        let data = data(Decimal::ZERO, &[("ETH", d(50)), ("SHIB", d(1_000))]);
+
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: HashMap::from([
+                ("ETH".to_string(), d(50)),
+                ("SHIB".to_string(), d(1_000_000_000)),
+            ]),
+        };
+
+        let options = AumOptions {
+            dust_threshold: Some(Decimal::new(1, 4)),
+            ..AumOptions::default()
+        };
+        let result = calculate_aum_with_options(&data, &prices, &options)
+            .await
+            .expect("calc should work");
+        assert_eq!(result.spot_contributions.len(), 1);
+        assert_eq!(result.aum_base_18dp, BaseAmount::new(Decimal::ONE));
+        assert_eq!(result.dust_assets, vec!["SHIB".to_string()]);
+        assert_eq!(result.dust_base, BaseAmount::new(Decimal::new(1, 6)));
+    }
+
+    #[tokio::test]
+    async fn satoshi_rounding_mode_is_applied() {
+        let data = data(Decimal::new(1_999_999_995, 9), &[]);
+        let prices = MockPriceProvider {
+            base_usd: Decimal::ONE,
+            base_to_asset: HashMap::new(),
+        };
+
+        for (rounding, expected) in [
+            (SatoshiRounding::Trunc, 199_999_999),
+            (SatoshiRounding::Round, 200_000_000),
+            (SatoshiRounding::Floor, 199_999_999),
+            (SatoshiRounding::Ceil, 200_000_000),
+        ] {
+            let options = AumOptions {
+                satoshi_rounding: rounding,
+                ..AumOptions::default()
+            };
+            let result = calculate_aum_with_options(&data, &prices, &options)
+                .await
+                .expect("calc should work");
+            assert_eq!(result.aum_base_u8, BaseUnits::new(expected), "{rounding:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn only_allowlisted_assets_use_fallback_pricing() {
+        struct FallbackOnly;
+
+        #[async_trait]
+        impl PriceProvider for FallbackOnly {
+            async fn base_to_usd(&self) -> AppResult<Decimal> {
+                Ok(d(100_000))
+            }
+
+            async fn base_to_asset(&self, asset: &str) -> AppResult<Decimal> {
+                Err(AppError::MissingPrice(asset.to_string()))
+            }
+
+            async fn base_to_asset_fallback(&self, _asset: &str) -> AppResult<Decimal> {
+                Ok(d(100_000))
+            }
+        }
+
+        let data = data(Decimal::ZERO, &[("PYUSD", d(50_000))]);
+
+        let err = calculate_aum(&data, &FallbackOnly)
+            .await
+            .expect_err("not allowlisted");
+        assert!(matches!(err, AppError::MissingPrice(asset) if asset == "PYUSD"));
+
+        let options = AumOptions {
+            fallback_assets: vec!["PYUSD".to_string()],
+            ..AumOptions::default()
+        };
+        let result = calculate_aum_with_options(&data, &FallbackOnly, &options)
+            .await
+            .expect("fallback should price it");
+        assert_eq!(result.aum_base_18dp, BaseAmount::new(Decimal::new(5, 1)));
+        assert_eq!(result.fallback_assets, vec!["PYUSD".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn haircut_reduces_net_but_not_gross_aum() {
+        let data = data(Decimal::ZERO, &[("BTC", d(1)), ("LDO", d(1_000))]);
+        let prices = MockPriceProvider {
+            base_usd: d(100_000),
+            base_to_asset: HashMap::from([
+                ("BTC".to_string(), d(1)),
+                ("LDO".to_string(), d(1_000)),
+            ]),
+        };
+        let options = AumOptions {
+            haircuts: HashMap::from([("LDO".to_string(), d(20))]),
+            ..AumOptions::default()
+        };
+
+        let result = calculate_aum_with_options(&data, &prices, &options)
+            .await
+            .expect("calc should work");
+        assert_eq!(result.aum_base_18dp, BaseAmount::new(d(2)));
+        assert_eq!(result.haircut_base, BaseAmount::new(Decimal::new(2, 1)));
+        assert_eq!(
+            result.aum_base_net,
+            Some(BaseAmount::new(Decimal::new(18, 1)))
+        );
+
+        let ldo = &result.spot_contributions[1];
+        assert_eq!(ldo.haircut_pct, Some(d(20)));
+        assert_eq!(
+            ldo.amount_base_net,
+            Some(BaseAmount::new(Decimal::new(8, 1)))
+        );
+        assert_eq!(result.spot_contributions[0].amount_base_net, None);
+    }
 }
@@ -0,0 +1,230 @@
+use async_trait::async_trait;
+use futures::future::try_join_all;
+use rust_decimal::Decimal;
+
+use crate::binance_client::{BinanceClient, FetchOptions};
+use crate::config::Selection;
+use crate::error::{AppError, AppResult};
+use crate::models::{BinanceData, SpotBalance};
+
+/// A venue whose balances feed the AUM. Each source returns its holdings in
+/// the `BinanceData` shape; several are combined with [`merge`] before
+/// `calculate_aum`.
+#[async_trait]
+pub trait BalanceSource: Send + Sync {
+    /// Short name used in logs, e.g. `binance`.
+    fn venue(&self) -> &str;
+
+    async fn fetch_balances(
+        &self,
+        um_positions: &Selection,
+        spot_assets: &Selection,
+        options: &FetchOptions,
+    ) -> AppResult<BinanceData>;
+}
+
+#[async_trait]
+impl BalanceSource for BinanceClient {
+    fn venue(&self) -> &str {
+        "binance"
+    }
+
+    async fn fetch_balances(
+        &self,
+        um_positions: &Selection,
+        spot_assets: &Selection,
+        options: &FetchOptions,
+    ) -> AppResult<BinanceData> {
+        self.fetch_aum_data_with_options(um_positions, spot_assets, options)
+            .await
+    }
+}
+
+/// Fetches every source concurrently and merges the results; the first
+/// failure fails the whole fetch, so a venue is never silently left out.
+pub async fn fetch_all(
+    sources: &[Box<dyn BalanceSource>],
+    um_positions: &Selection,
+    spot_assets: &Selection,
+    options: &FetchOptions,
+) -> AppResult<BinanceData> {
+    let parts = try_join_all(
+        sources
+            .iter()
+            .map(|source| source.fetch_balances(um_positions, spot_assets, options)),
+    )
+    .await?;
+    merge(parts).ok_or_else(|| AppError::InvalidConfig {
+        field: "balance sources",
+        reason: "no balance source to fetch from".to_string(),
+    })
+}
+
+/// Combines the holdings of several venues into one `BinanceData`; `None`
+/// when `parts` is empty.
+///
+/// Spot balances, liabilities and classic futures balances are summed per
+/// asset, in first-seen order; USD figures are summed and positions are
+/// concatenated. `unimmr` is a ratio, not an amount, so the lowest non-zero
+/// one is kept: the most constrained account. The settlement asset is the
+/// first part's.
+pub fn merge(parts: impl IntoIterator<Item = BinanceData>) -> Option<BinanceData> {
+    let mut parts = parts.into_iter();
+    let mut merged = parts.next()?;
+    for part in parts {
+        merged.unimmr = match (merged.unimmr.is_zero(), part.unimmr.is_zero()) {
+            (true, _) => part.unimmr,
+            (false, true) => merged.unimmr,
+            (false, false) => merged.unimmr.min(part.unimmr),
+        };
+        merged.positions.extend(part.positions);
+        merged.um_balance_usd += part.um_balance_usd;
+        merged.pm_account_actual_equity += part.pm_account_actual_equity;
+        merged.withdrawable_usd += part.withdrawable_usd;
+        for balance in part.spot_balances {
+            add_balance(&mut merged.spot_balances, balance);
+        }
+        for liability in part.margin_liabilities {
+            match merged
+                .margin_liabilities
+                .iter_mut()
+                .find(|l| l.asset == liability.asset)
+            {
+                Some(existing) => existing.amount += liability.amount,
+                None => merged.margin_liabilities.push(liability),
+            }
+        }
+        merged.classic_futures = match (merged.classic_futures, part.classic_futures) {
+            (Some(mut account), Some(other)) => {
+                account.equity_usd += other.equity_usd;
+                account.wallet_balance_usd += other.wallet_balance_usd;
+                account.unrealized_pnl_usd += other.unrealized_pnl_usd;
+                for balance in other.balances {
+                    add_balance(&mut account.balances, balance);
+                }
+                Some(account)
+            }
+            (account, other) => account.or(other),
+        };
+    }
+    Some(merged)
+}
+
+fn add_balance(balances: &mut Vec<SpotBalance>, balance: SpotBalance) {
+    let Some(existing) = balances.iter_mut().find(|b| b.asset == balance.asset) else {
+        balances.push(balance);
+        return;
+    };
+    existing.amount += balance.amount;
+    // A side without the split has no spot free/locked part to add.
+    let sum = |a: Option<Decimal>, b: Option<Decimal>| match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    };
+    existing.free = sum(existing.free, balance.free);
+    existing.locked = sum(existing.locked, balance.locked);
+    existing.earn = sum(existing.earn, balance.earn);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ClassicFuturesAccount, MarginLiability, UmPosition};
+
+    fn d(v: i64) -> Decimal {
+        Decimal::from(v)
+    }
+
+    fn data(unimmr: i64, equity: i64, spot: &[(&str, i64)]) -> BinanceData {
+        BinanceData {
+            unimmr: d(unimmr),
+            positions: vec![],
+            settlement_asset: "USDT".to_string(),
+            um_balance_usd: Decimal::ZERO,
+            spot_balances: spot
+                .iter()
+                .map(|&(asset, amount)| SpotBalance::new(asset, d(amount)))
+                .collect(),
+            classic_futures: None,
+            pm_account_actual_equity: d(equity),
+            withdrawable_usd: Decimal::ZERO,
+            margin_liabilities: vec![],
+        }
+    }
+
+    struct StaticSource(BinanceData);
+
+    #[async_trait]
+    impl BalanceSource for StaticSource {
+        fn venue(&self) -> &str {
+            "static"
+        }
+
+        async fn fetch_balances(
+            &self,
+            _um_positions: &Selection,
+            _spot_assets: &Selection,
+            _options: &FetchOptions,
+        ) -> AppResult<BinanceData> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn merge_sums_per_asset_and_keeps_the_lowest_unimmr() {
+        let mut first = data(5, 100, &[("BTC", 1), ("ETH", 2)]);
+        first.margin_liabilities = vec![MarginLiability {
+            asset: "USDT".to_string(),
+            amount: d(10),
+        }];
+        let mut second = data(3, 50, &[("ETH", 3), ("SOL", 4)]);
+        second.positions = vec![UmPosition {
+            symbol: "BTCUSDT".to_string(),
+            amount: Decimal::ONE,
+            pnl: Decimal::ZERO,
+            mark_price: d(100),
+            notional: d(100),
+        }];
+        second.margin_liabilities = first.margin_liabilities.clone();
+        second.classic_futures = Some(ClassicFuturesAccount {
+            equity_usd: d(7),
+            wallet_balance_usd: d(7),
+            unrealized_pnl_usd: Decimal::ZERO,
+            balances: vec![],
+        });
+
+        let merged = merge([first, second, data(0, 0, &[])]).unwrap();
+        assert_eq!(merged.unimmr, d(3));
+        assert_eq!(merged.pm_account_actual_equity, d(150));
+        let spot: Vec<_> = merged
+            .spot_balances
+            .iter()
+            .map(|b| (b.asset.as_str(), b.amount))
+            .collect();
+        assert_eq!(spot, [("BTC", d(1)), ("ETH", d(5)), ("SOL", d(4))]);
+        assert_eq!(merged.margin_liabilities.len(), 1);
+        assert_eq!(merged.margin_liabilities[0].amount, d(20));
+        assert_eq!(merged.positions.len(), 1);
+        assert_eq!(merged.classic_futures.map(|a| a.equity_usd), Some(d(7)));
+
+        assert!(merge(Vec::new()).is_none());
+    }
+
+    #[tokio::test]
+    async fn fetch_all_merges_every_source() {
+        let sources: Vec<Box<dyn BalanceSource>> = vec![
+            Box::new(StaticSource(data(5, 100, &[("BTC", 1)]))),
+            Box::new(StaticSource(data(4, 20, &[("BTC", 2)]))),
+        ];
+        let merged = fetch_all(
+            &sources,
+            &Selection::All,
+            &Selection::All,
+            &FetchOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(merged.pm_account_actual_equity, d(120));
+        assert_eq!(merged.spot_balances[0].amount, d(3));
+    }
+}
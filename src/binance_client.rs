@@ -1,26 +1,190 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use hmac::{Hmac, Mac};
-use reqwest::header::{HeaderMap, HeaderValue};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+use reqwest::StatusCode;
 use rust_decimal::Decimal;
 use serde::Deserialize;
-use sha2::Sha256;
+use tracing::{debug, warn};
 use url::form_urlencoded;
 
+use crate::clock::{SystemTimeSource, TimeSource};
+use crate::config::{Selection, SnapshotType};
 use crate::error::{AppError, AppResult};
 use crate::models::{
-    BinanceData, PmAccountBalanceApi, PmAccountInfoApi, SpotAccountInfoApi, SpotBalance,
+    AccountSnapshotApi, BinanceData, ClassicFuturesAccount, FapiAccountApi, FapiBalanceApi,
+    FuturesSnapshotDataApi, MarginAccountApi, MarginLiability, PmAccountBalanceApi,
+    PmAccountInfoApi, ServerTimeApi, SimpleEarnFlexiblePositionApi, SimpleEarnLockedPositionApi,
+    SimpleEarnPageApi, SpotAccountInfoApi, SpotBalance, SpotSnapshotDataApi, UmIncome, UmIncomeApi,
     UmPosition, UmPositionApi,
 };
-
-type HmacSha256 = Hmac<Sha256>;
+use crate::signing::{HmacSigner, Signer};
 
 #[derive(Debug, Clone)]
 pub struct BinanceClient {
     http: reqwest::Client,
-    api_secret: String,
+    signer: Arc<dyn Signer>,
     api_base_url: String,
     papi_base_url: String,
+    /// Classic USDⓂ futures API, read only with `include_classic_futures`.
+    fapi_base_url: String,
+    /// Local clock behind signed-request timestamps.
+    time_source: Arc<dyn TimeSource>,
+    /// Milliseconds to add to the local clock to match Binance server time.
+    time_offset_ms: Arc<AtomicI64>,
+    /// Latest `X-MBX-USED-WEIGHT-1M` value seen on any response.
+    used_weight_1m: Arc<AtomicU32>,
+    /// Binance requests sent by this client and its clones.
+    api_calls: Arc<AtomicU32>,
+    /// Response field renames applied before parsing, `received → expected`.
+    field_aliases: Arc<HashMap<String, String>>,
+    /// Client-side request cap shared by clones; see `with_max_rps`.
+    rate_limiter: Option<Arc<DefaultDirectRateLimiter>>,
+    /// Undecoded bodies of the `RAW_DUMP_ENDPOINTS`, keyed by endpoint, when
+    /// enabled by `with_raw_capture`; drained by `take_raw_responses`.
+    raw_responses: Option<Arc<Mutex<BTreeMap<String, serde_json::Value>>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ApiHost {
+    Spot,
+    PortfolioMargin,
+}
+
+const UM_POSITION_RISK_ENDPOINT: &str = "/papi/v1/um/positionRisk";
+const UM_INCOME_ENDPOINT: &str = "/papi/v1/um/income";
+const PM_ACCOUNT_ENDPOINT: &str = "/papi/v1/account";
+const PM_BALANCE_ENDPOINT: &str = "/papi/v1/balance";
+const SPOT_ACCOUNT_ENDPOINT: &str = "/api/v3/account";
+const TICKER_PRICE_ENDPOINT: &str = "/api/v3/ticker/price";
+const TICKER_24H_ENDPOINT: &str = "/api/v3/ticker/24hr";
+const BOOK_TICKER_ENDPOINT: &str = "/api/v3/ticker/bookTicker";
+const SERVER_TIME_ENDPOINT: &str = "/api/v3/time";
+const EARN_FLEXIBLE_ENDPOINT: &str = "/sapi/v1/simple-earn/flexible/position";
+const EARN_LOCKED_ENDPOINT: &str = "/sapi/v1/simple-earn/locked/position";
+const EARN_PAGE_SIZE: u64 = 100;
+/// Most records `/papi/v1/um/income` returns per request.
+const INCOME_PAGE_SIZE: usize = 1000;
+const MARGIN_ACCOUNT_ENDPOINT: &str = "/sapi/v1/margin/account";
+const ACCOUNT_SNAPSHOT_ENDPOINT: &str = "/sapi/v1/accountSnapshot";
+const KLINES_ENDPOINT: &str = "/api/v3/klines";
+const FAPI_ACCOUNT_ENDPOINT: &str = "/fapi/v2/account";
+const FAPI_BALANCE_ENDPOINT: &str = "/fapi/v2/balance";
+const DEFAULT_FAPI_BASE_URL: &str = "https://fapi.binance.com";
+
+const SPOT_PING_ENDPOINT: &str = "/api/v3/ping";
+const PAPI_PING_ENDPOINT: &str = "/papi/v1/ping";
+const WAIT_FOR_API_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const WAIT_FOR_API_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+const DEFAULT_USER_AGENT: &str = concat!("binance-aum-fetch/", env!("CARGO_PKG_VERSION"));
+
+const USED_WEIGHT_HEADER: &str = "x-mbx-used-weight-1m";
+
+const TIMESTAMP_OUTSIDE_RECV_WINDOW: i64 = -1021;
+/// `-1003` too much request weight, `-1015` too many new orders.
+const RATE_LIMIT_CODES: [i64; 2] = [-1003, -1015];
+/// `-1016` service shutting down, returned during system maintenance.
+const MAINTENANCE_CODE: i64 = -1016;
+
+const RAW_DUMP_ENDPOINTS: [(ApiHost, &str); 4] = [
+    (ApiHost::PortfolioMargin, UM_POSITION_RISK_ENDPOINT),
+    (ApiHost::PortfolioMargin, PM_ACCOUNT_ENDPOINT),
+    (ApiHost::PortfolioMargin, PM_BALANCE_ENDPOINT),
+    (ApiHost::Spot, SPOT_ACCOUNT_ENDPOINT),
+];
+
+/// Where a decimal came from, so parse failures can name the endpoint and
+/// the symbol/asset of the offending array element.
+#[derive(Debug, Clone, Copy)]
+struct ParseContext<'a> {
+    endpoint: &'static str,
+    subject: Option<(&'static str, &'a str)>,
+}
+
+impl<'a> ParseContext<'a> {
+    fn new(endpoint: &'static str) -> Self {
+        Self {
+            endpoint,
+            subject: None,
+        }
+    }
+
+    fn with_subject(self, kind: &'static str, name: &'a str) -> Self {
+        Self {
+            subject: Some((kind, name)),
+            ..self
+        }
+    }
+}
+
+/// HTTP settings for the client built by `new_with_options`.
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub timeout: Duration,
+    /// Overrides the default `binance-aum-fetch/<version>` user agent.
+    pub user_agent: Option<String>,
+    /// PEM root certificate trusted in addition to the built-in roots, e.g.
+    /// that of a TLS-intercepting proxy.
+    pub ca_cert: Option<PathBuf>,
+    /// Accepts any server certificate. Development only.
+    pub insecure_skip_tls_verify: bool,
+    /// Renames response object keys (`received → expected`, at any depth)
+    /// before parsing, to absorb Binance field-name drift without a release.
+    pub field_aliases: HashMap<String, String>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            user_agent: None,
+            ca_cert: None,
+            insecure_skip_tls_verify: false,
+            field_aliases: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    /// Adds Simple Earn flexible and locked positions to the spot balances.
+    pub include_earn: bool,
+    /// Fetches cross-margin borrowed principal and interest so they can be
+    /// subtracted from AUM.
+    pub include_margin_liabilities: bool,
+    /// Stablecoin whose UM wallet balance is reported as `um_balance_usd`.
+    pub settlement_asset: String,
+    /// Reports every configured spot asset, including zero balances.
+    pub show_zero_balances: bool,
+    /// Keeps each spot balance's `free` and `locked` parts next to the total.
+    pub split_free_locked: bool,
+    /// Also reads the classic USDⓂ futures account (`/fapi/v2`).
+    pub include_classic_futures: bool,
+    /// Reads the portfolio margin account. Without it (Binance.US) equity,
+    /// uniMMR and the withdrawable amount are reported as zero and only spot
+    /// balances are valued.
+    pub portfolio_margin: bool,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            include_earn: false,
+            include_margin_liabilities: false,
+            settlement_asset: "USDT".to_string(),
+            show_zero_balances: false,
+            split_free_locked: false,
+            include_classic_futures: false,
+            portfolio_margin: true,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -36,6 +200,52 @@ impl BinanceClient {
         api_base_url: String,
         papi_base_url: String,
         timeout: std::time::Duration,
+    ) -> AppResult<Self> {
+        let options = ClientOptions {
+            timeout,
+            ..ClientOptions::default()
+        };
+        Self::new_with_options(api_key, api_secret, api_base_url, papi_base_url, &options)
+    }
+
+    pub fn new_with_options(
+        api_key: String,
+        api_secret: String,
+        api_base_url: String,
+        papi_base_url: String,
+        options: &ClientOptions,
+    ) -> AppResult<Self> {
+        Self::with_signer_and_options(
+            api_key,
+            Arc::new(HmacSigner::new(api_secret)),
+            api_base_url,
+            papi_base_url,
+            options,
+        )
+    }
+
+    /// Like `new`, but signs requests with `signer` instead of holding the
+    /// API secret.
+    pub fn with_signer(
+        api_key: String,
+        signer: Arc<dyn Signer>,
+        api_base_url: String,
+        papi_base_url: String,
+        timeout: std::time::Duration,
+    ) -> AppResult<Self> {
+        let options = ClientOptions {
+            timeout,
+            ..ClientOptions::default()
+        };
+        Self::with_signer_and_options(api_key, signer, api_base_url, papi_base_url, &options)
+    }
+
+    pub fn with_signer_and_options(
+        api_key: String,
+        signer: Arc<dyn Signer>,
+        api_base_url: String,
+        papi_base_url: String,
+        options: &ClientOptions,
     ) -> AppResult<Self> {
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -46,17 +256,120 @@ impl BinanceClient {
             })?,
         );
 
-        let http = reqwest::Client::builder()
+        let mut builder = reqwest::Client::builder()
             .default_headers(headers)
-            .timeout(timeout)
-            .build()?;
+            .user_agent(options.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+            .timeout(options.timeout);
+        if let Some(path) = &options.ca_cert {
+            let pem = std::fs::read(path)?;
+            let invalid = |reason: String| AppError::InvalidConfig {
+                field: "CA_CERT",
+                reason: format!("{}: {reason}", path.display()),
+            };
+            let certs =
+                reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| invalid(e.to_string()))?;
+            if certs.is_empty() {
+                return Err(invalid("no PEM certificates found".to_string()));
+            }
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if options.insecure_skip_tls_verify {
+            warn!(
+                "TLS certificate verification is DISABLED; API keys and balances can be \
+                 intercepted. Use only for local development."
+            );
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        let http = builder.build()?;
 
-        Ok(Self {
+        Ok(Self::from_parts(http, signer, api_base_url, papi_base_url)
+            .with_field_aliases(options.field_aliases.clone()))
+    }
+
+    /// Builds a client on top of a caller-provided `reqwest::Client`, e.g. one
+    /// pointed at a mock server. The caller is responsible for setting the
+    /// `X-MBX-APIKEY` header and timeouts on `http`.
+    pub fn with_http_client(
+        http: reqwest::Client,
+        api_secret: String,
+        api_base_url: String,
+        papi_base_url: String,
+    ) -> Self {
+        Self::from_parts(
             http,
-            api_secret,
+            Arc::new(HmacSigner::new(api_secret)),
             api_base_url,
             papi_base_url,
-        })
+        )
+    }
+
+    fn from_parts(
+        http: reqwest::Client,
+        signer: Arc<dyn Signer>,
+        api_base_url: String,
+        papi_base_url: String,
+    ) -> Self {
+        Self {
+            http,
+            signer,
+            api_base_url,
+            papi_base_url,
+            fapi_base_url: DEFAULT_FAPI_BASE_URL.to_string(),
+            time_source: Arc::new(SystemTimeSource),
+            time_offset_ms: Arc::new(AtomicI64::new(0)),
+            used_weight_1m: Arc::new(AtomicU32::new(0)),
+            api_calls: Arc::new(AtomicU32::new(0)),
+            field_aliases: Arc::default(),
+            rate_limiter: None,
+            raw_responses: None,
+        }
+    }
+
+    /// Overrides the classic futures API base URL
+    /// (default `https://fapi.binance.com`).
+    pub fn with_fapi_base_url(mut self, fapi_base_url: String) -> Self {
+        self.fapi_base_url = fapi_base_url;
+        self
+    }
+
+    /// Takes signed-request timestamps from `time_source` instead of the
+    /// system clock.
+    pub fn with_time_source(mut self, time_source: Arc<dyn TimeSource>) -> Self {
+        self.time_source = time_source;
+        self
+    }
+
+    /// Sends at most `max_rps` Binance requests per second across this client
+    /// and its clones. Requests over the cap wait for a slot instead of
+    /// failing.
+    pub fn with_max_rps(mut self, max_rps: NonZeroU32) -> Self {
+        self.rate_limiter = Some(Arc::new(RateLimiter::direct(Quota::per_second(max_rps))));
+        self
+    }
+
+    /// Keeps the body of every response from the four endpoints behind
+    /// `fetch_aum_data`, as received, until `take_raw_responses`.
+    pub fn with_raw_capture(mut self) -> Self {
+        self.raw_responses = Some(Arc::default());
+        self
+    }
+
+    /// Drains the bodies captured since the last call; empty unless
+    /// `with_raw_capture` is on.
+    pub fn take_raw_responses(&self) -> BTreeMap<String, serde_json::Value> {
+        self.raw_responses
+            .as_ref()
+            .map(|raw| std::mem::take(&mut *raw.lock().unwrap_or_else(|e| e.into_inner())))
+            .unwrap_or_default()
+    }
+
+    /// Renames response fields before parsing; see
+    /// [`ClientOptions::field_aliases`].
+    pub fn with_field_aliases(mut self, field_aliases: HashMap<String, String>) -> Self {
+        self.field_aliases = Arc::new(field_aliases);
+        self
     }
 
     pub async fn fetch_aum_data(
@@ -64,66 +377,636 @@ impl BinanceClient {
         um_positions_list: &[String],
         spot_assets_list: &[String],
     ) -> AppResult<BinanceData> {
-        let (um_positions, pm_account_info, pm_account_balances, spot_account_info) = tokio::try_join!(
-            self.get_um_positions(),
-            self.get_pm_account_info(),
-            self.get_pm_account_balances(),
-            self.get_spot_account_info(),
+        self.fetch_aum_data_with_options(
+            &Selection::from(um_positions_list),
+            &Selection::from(spot_assets_list),
+            &FetchOptions::default(),
+        )
+        .await
+    }
+
+    #[tracing::instrument(
+        name = "fetch",
+        skip_all,
+        fields(
+            include_earn = options.include_earn,
+            include_margin_liabilities = options.include_margin_liabilities,
+            include_classic_futures = options.include_classic_futures,
+            portfolio_margin = options.portfolio_margin,
+        )
+    )]
+    pub async fn fetch_aum_data_with_options(
+        &self,
+        um_positions: &Selection,
+        spot_assets: &Selection,
+        options: &FetchOptions,
+    ) -> AppResult<BinanceData> {
+        let um = async {
+            if um_positions.is_empty() || !options.portfolio_margin {
+                Ok(Vec::new())
+            } else {
+                self.get_um_positions().await
+            }
+        };
+        let pm_account = async {
+            if options.portfolio_margin {
+                self.get_pm_account_info().await.map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+        let pm_balances = async {
+            if options.portfolio_margin {
+                self.get_pm_account_balances().await
+            } else {
+                Ok(Vec::new())
+            }
+        };
+        let spot = async {
+            if spot_assets.is_empty() {
+                Ok(SpotAccountInfoApi {
+                    balances: Vec::new(),
+                })
+            } else {
+                self.get_spot_account_info().await
+            }
+        };
+        let earn = async {
+            if options.include_earn && !spot_assets.is_empty() {
+                self.get_earn_balances().await
+            } else {
+                Ok(Vec::new())
+            }
+        };
+        let liabilities = async {
+            if options.include_margin_liabilities {
+                self.get_margin_liabilities().await
+            } else {
+                Ok(Vec::new())
+            }
+        };
+        let classic_futures = async {
+            if options.include_classic_futures {
+                self.get_classic_futures_account().await.map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+        let (
+            um_positions_api,
+            pm_account_info,
+            pm_account_balances,
+            spot_account_info,
+            earn_balances,
+            margin_liabilities,
+            classic_futures,
+        ) = tokio::try_join!(
+            um,
+            pm_account,
+            pm_balances,
+            spot,
+            earn,
+            liabilities,
+            classic_futures
         )?;
 
-        let positions = filter_positions(&um_positions, um_positions_list)?;
-        let spot_balances = filter_spot_balances(&spot_account_info, spot_assets_list)?;
+        let positions = filter_positions(&um_positions_api, um_positions)?;
+        let mut spot_balances = filter_spot_balances(
+            &spot_account_info,
+            spot_assets,
+            options.show_zero_balances,
+            options.split_free_locked,
+        )?;
+        merge_balances(
+            &mut spot_balances,
+            earn_balances,
+            spot_assets,
+            options.split_free_locked,
+        );
 
-        let um_balance_usdt = pm_account_balances
+        let um_balance_usd = pm_account_balances
             .iter()
-            .find(|b| b.asset == "USDT")
-            .map(|b| parse_decimal("umWalletBalance", &b.um_wallet_balance))
+            .find(|b| b.asset == options.settlement_asset)
+            .map(|b| {
+                let ctx = ParseContext::new(PM_BALANCE_ENDPOINT).with_subject("asset", &b.asset);
+                parse_decimal(ctx, "umWalletBalance", &b.um_wallet_balance)
+            })
             .transpose()?
             .unwrap_or(Decimal::ZERO);
 
+        let account_ctx = ParseContext::new(PM_ACCOUNT_ENDPOINT);
+        let pm_field = |field: &'static str, value: fn(&PmAccountInfoApi) -> &str| {
+            pm_account_info
+                .as_ref()
+                .map(|account| parse_decimal(account_ctx, field, value(account)))
+                .transpose()
+                .map(Option::unwrap_or_default)
+        };
         Ok(BinanceData {
-            unimmr: parse_decimal("uniMMR", &pm_account_info.uni_mmr)?,
+            unimmr: pm_field("uniMMR", |a| &a.uni_mmr)?,
             positions,
-            um_balance_usdt,
+            settlement_asset: options.settlement_asset.clone(),
+            um_balance_usd,
             spot_balances,
-            pm_account_actual_equity: parse_decimal(
-                "actualEquity",
-                &pm_account_info.actual_equity,
-            )?,
-            withdrawable_usdt: parse_decimal(
-                "virtualMaxWithdrawAmount",
-                &pm_account_info.virtual_max_withdraw_amount,
-            )?,
+            classic_futures,
+            pm_account_actual_equity: pm_field("actualEquity", |a| &a.actual_equity)?,
+            withdrawable_usd: pm_field("virtualMaxWithdrawAmount", |a| {
+                &a.virtual_max_withdraw_amount
+            })?,
+            margin_liabilities,
         })
     }
 
+    /// Fetches only the UM positions in `selection`.
+    pub async fn fetch_positions(&self, selection: &Selection) -> AppResult<Vec<UmPosition>> {
+        let positions = self.get_um_positions().await?;
+        filter_positions(&positions, selection)
+    }
+
+    /// Fetches only `virtualMaxWithdrawAmount` from `/papi/v1/account`.
+    pub async fn fetch_withdrawable_usd(&self) -> AppResult<Decimal> {
+        let account = self.get_pm_account_info().await?;
+        parse_decimal(
+            ParseContext::new(PM_ACCOUNT_ENDPOINT),
+            "virtualMaxWithdrawAmount",
+            &account.virtual_max_withdraw_amount,
+        )
+    }
+
+    /// Rebuilds AUM inputs from the daily account snapshot taken at the end
+    /// of `date` (UTC), returning the snapshot time alongside.
+    ///
+    /// A `FUTURES` snapshot maps the settlement asset's margin balance to
+    /// `pm_account_actual_equity` and its wallet balance to `um_balance_usd`;
+    /// other futures wallet assets in `spot_assets` are valued as balances.
+    pub async fn fetch_snapshot_data(
+        &self,
+        kind: SnapshotType,
+        date: NaiveDate,
+        um_positions: &Selection,
+        spot_assets: &Selection,
+        settlement_asset: &str,
+    ) -> AppResult<(DateTime<Utc>, BinanceData)> {
+        let start = date.and_time(chrono::NaiveTime::MIN).and_utc();
+        let end = start + Days::new(1);
+        let params = [
+            ("type", kind.as_api().to_string()),
+            ("startTime", start.timestamp_millis().to_string()),
+            ("endTime", (end.timestamp_millis() - 1).to_string()),
+        ];
+
+        let mut data = BinanceData {
+            unimmr: Decimal::ZERO,
+            positions: Vec::new(),
+            settlement_asset: settlement_asset.to_string(),
+            um_balance_usd: Decimal::ZERO,
+            spot_balances: Vec::new(),
+            classic_futures: None,
+            pm_account_actual_equity: Decimal::ZERO,
+            withdrawable_usd: Decimal::ZERO,
+            margin_liabilities: Vec::new(),
+        };
+        let update_time = match kind {
+            SnapshotType::Spot => {
+                let snapshot: AccountSnapshotApi<SpotSnapshotDataApi> = self
+                    .get_signed(&self.api_base_url, ACCOUNT_SNAPSHOT_ENDPOINT, &params)
+                    .await?;
+                let Some(latest) = snapshot
+                    .snapshot_vos
+                    .into_iter()
+                    .max_by_key(|s| s.update_time)
+                else {
+                    return Err(no_snapshot(kind, date));
+                };
+                let account = SpotAccountInfoApi {
+                    balances: latest.data.balances,
+                };
+                data.spot_balances = filter_spot_balances(&account, spot_assets, false, false)?;
+                latest.update_time
+            }
+            SnapshotType::Futures => {
+                let snapshot: AccountSnapshotApi<FuturesSnapshotDataApi> = self
+                    .get_signed(&self.api_base_url, ACCOUNT_SNAPSHOT_ENDPOINT, &params)
+                    .await?;
+                let Some(latest) = snapshot
+                    .snapshot_vos
+                    .into_iter()
+                    .max_by_key(|s| s.update_time)
+                else {
+                    return Err(no_snapshot(kind, date));
+                };
+                data.positions = filter_positions(&latest.data.position, um_positions)?;
+                for asset in &latest.data.assets {
+                    let ctx = ParseContext::new(ACCOUNT_SNAPSHOT_ENDPOINT)
+                        .with_subject("asset", &asset.asset);
+                    let margin_balance =
+                        parse_decimal(ctx, "marginBalance", &asset.margin_balance)?;
+                    if asset.asset == settlement_asset {
+                        data.pm_account_actual_equity = margin_balance;
+                        data.um_balance_usd =
+                            parse_decimal(ctx, "walletBalance", &asset.wallet_balance)?;
+                    } else if spot_assets.includes(&asset.asset) && !margin_balance.is_zero() {
+                        data.spot_balances
+                            .push(SpotBalance::new(asset.asset.clone(), margin_balance));
+                    }
+                }
+                latest.update_time
+            }
+        };
+
+        let taken_at = DateTime::from_timestamp_millis(update_time).unwrap_or(end);
+        Ok((taken_at, data))
+    }
+
+    /// Fetches every signed endpoint used by `fetch_aum_data` and returns the
+    /// undecoded response bodies, keyed by endpoint path.
+    pub async fn fetch_raw_responses(&self) -> Vec<(&'static str, AppResult<String>)> {
+        let mut out = Vec::with_capacity(RAW_DUMP_ENDPOINTS.len());
+        for (host, endpoint) in RAW_DUMP_ENDPOINTS {
+            let base_url = match host {
+                ApiHost::Spot => &self.api_base_url,
+                ApiHost::PortfolioMargin => &self.papi_base_url,
+            };
+            let body = match self.send_signed(base_url, endpoint, &[]).await {
+                Ok(response) => response_text(response).await,
+                Err(err) => Err(err),
+            };
+            out.push((endpoint, body));
+        }
+        out
+    }
+
+    /// Runs connectivity and credential checks, returning one outcome per
+    /// endpoint probed.
+    pub async fn verify(&self) -> Vec<(&'static str, AppResult<()>)> {
+        let (spot_ping, papi_ping, account) = tokio::join!(
+            self.get_public::<serde::de::IgnoredAny>(&self.api_base_url, SPOT_PING_ENDPOINT, &[]),
+            self.get_public::<serde::de::IgnoredAny>(&self.papi_base_url, PAPI_PING_ENDPOINT, &[]),
+            self.get_pm_account_info(),
+        );
+        vec![
+            (SPOT_PING_ENDPOINT, spot_ping.map(|_| ())),
+            (PAPI_PING_ENDPOINT, papi_ping.map(|_| ())),
+            (PM_ACCOUNT_ENDPOINT, account.map(|_| ())),
+        ]
+    }
+
+    /// Pings the spot API until it answers, retrying with exponential backoff
+    /// (0.5s doubling up to 10s) for at most `timeout`. Once `timeout` has
+    /// elapsed the last ping error is returned.
+    pub async fn wait_for_api(&self, timeout: Duration) -> AppResult<()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut backoff = WAIT_FOR_API_INITIAL_BACKOFF;
+        loop {
+            let ping = self
+                .get_public::<serde::de::IgnoredAny>(&self.api_base_url, SPOT_PING_ENDPOINT, &[])
+                .await;
+            let err = match ping {
+                Ok(_) => return Ok(()),
+                Err(err) => err,
+            };
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if remaining.is_zero() {
+                return Err(err);
+            }
+            let sleep = backoff.min(remaining);
+            warn!(
+                error = %err,
+                retry_in_ms = sleep.as_millis() as u64,
+                "binance api not reachable yet; retrying"
+            );
+            tokio::time::sleep(sleep).await;
+            backoff = (backoff * 2).min(WAIT_FOR_API_MAX_BACKOFF);
+        }
+    }
+
+    /// Like `verify`, for a spot-only account (Binance.US): probes the spot
+    /// API and the spot account instead of portfolio margin.
+    pub async fn verify_spot(&self) -> Vec<(&'static str, AppResult<()>)> {
+        let (spot_ping, account) = tokio::join!(
+            self.get_public::<serde::de::IgnoredAny>(&self.api_base_url, SPOT_PING_ENDPOINT, &[]),
+            self.get_spot_account_info(),
+        );
+        vec![
+            (SPOT_PING_ENDPOINT, spot_ping.map(|_| ())),
+            (SPOT_ACCOUNT_ENDPOINT, account.map(|_| ())),
+        ]
+    }
+
+    /// Public IP this client's requests leave from, as reported by the plain
+    /// text IP echo service at `url`.
+    pub async fn egress_ip(&self, url: &str) -> AppResult<String> {
+        let response = self.http.get(url).send().await?.error_for_status()?;
+        Ok(response.text().await?.trim().to_string())
+    }
+
+    /// Current prices of several symbols in one request, keyed by symbol.
+    /// An empty `symbols` returns every listed symbol.
+    pub async fn ticker_prices(&self, symbols: &[String]) -> AppResult<HashMap<String, Decimal>> {
+        let params = if symbols.is_empty() {
+            vec![]
+        } else {
+            vec![("symbols", array_param(symbols))]
+        };
+        let tickers: Vec<crate::models::SymbolPriceTickerApi> = self
+            .get_public(&self.api_base_url, TICKER_PRICE_ENDPOINT, &params)
+            .await?;
+        tickers
+            .into_iter()
+            .map(|ticker| {
+                let ctx =
+                    ParseContext::new(TICKER_PRICE_ENDPOINT).with_subject("symbol", &ticker.symbol);
+                let price = parse_decimal(ctx, "price", &ticker.price)?;
+                Ok((ticker.symbol, price))
+            })
+            .collect()
+    }
+
     pub async fn ticker_price(&self, symbol: &str) -> AppResult<Decimal> {
-        let endpoint = "/api/v3/ticker/price";
         let params = [("symbol", symbol.to_string())];
         let ticker: crate::models::PriceTickerApi = self
-            .get_public(&self.api_base_url, endpoint, &params)
+            .get_public(&self.api_base_url, TICKER_PRICE_ENDPOINT, &params)
+            .await?;
+        let ctx = ParseContext::new(TICKER_PRICE_ENDPOINT).with_subject("symbol", symbol);
+        parse_decimal(ctx, "price", &ticker.price)
+    }
+
+    /// Midpoint of the best bid and ask of `symbol`. A side with no orders
+    /// (quoted as zero) leaves the symbol without a price.
+    pub async fn book_ticker_mid(&self, symbol: &str) -> AppResult<Decimal> {
+        let params = [("symbol", symbol.to_string())];
+        let ticker: crate::models::BookTickerApi = self
+            .get_public(&self.api_base_url, BOOK_TICKER_ENDPOINT, &params)
+            .await?;
+        let ctx = ParseContext::new(BOOK_TICKER_ENDPOINT).with_subject("symbol", symbol);
+        let bid = parse_decimal(ctx, "bidPrice", &ticker.bid_price)?;
+        let ask = parse_decimal(ctx, "askPrice", &ticker.ask_price)?;
+        if bid.is_zero() || ask.is_zero() {
+            return Err(AppError::MissingPrice(symbol.to_string()));
+        }
+        Ok((bid + ask) / Decimal::TWO)
+    }
+
+    /// UM income records booked in `[from, to)`, so consecutive windows
+    /// never share a record. A full page is followed by asking again from
+    /// its last record's millisecond and skipping records already seen, so
+    /// records sharing that millisecond are neither lost nor counted twice.
+    pub async fn um_income(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> AppResult<Vec<UmIncome>> {
+        let end_time = to.timestamp_millis() - 1;
+        let mut start_time = from.timestamp_millis();
+        let mut seen = HashSet::new();
+        let mut records = Vec::new();
+        while start_time <= end_time {
+            let params = [
+                ("startTime", start_time.to_string()),
+                ("endTime", end_time.to_string()),
+                ("limit", INCOME_PAGE_SIZE.to_string()),
+            ];
+            let page: Vec<UmIncomeApi> = self
+                .get_signed(&self.papi_base_url, UM_INCOME_ENDPOINT, &params)
+                .await?;
+            let full = page.len() >= INCOME_PAGE_SIZE;
+            let last_time = page.iter().map(|row| row.time).max();
+            let before = records.len();
+            for row in page {
+                if !seen.insert((row.tran_id, row.income_type.clone())) {
+                    continue;
+                }
+                let ctx = ParseContext::new(UM_INCOME_ENDPOINT).with_subject("asset", &row.asset);
+                let amount = parse_decimal(ctx, "income", &row.income)?;
+                records.push(UmIncome {
+                    symbol: row.symbol,
+                    income_type: row.income_type,
+                    asset: row.asset,
+                    amount,
+                    time: DateTime::from_timestamp_millis(row.time).unwrap_or_default(),
+                });
+            }
+            let Some(last_time) = last_time.filter(|_| full) else {
+                break;
+            };
+            // A full page of records already seen means a single
+            // millisecond holds more than a page; asking again cannot move on.
+            if records.len() == before {
+                return Err(AppError::UnexpectedResponse {
+                    endpoint: UM_INCOME_ENDPOINT,
+                    reason: format!(
+                        "more than {INCOME_PAGE_SIZE} income records at {last_time}; \
+                         cannot page past them"
+                    ),
+                    body: String::new(),
+                });
+            }
+            start_time = last_time;
+        }
+        Ok(records)
+    }
+
+    /// Volume-weighted average price of `symbol` over the last 24 hours.
+    pub async fn weighted_avg_price_24h(&self, symbol: &str) -> AppResult<Decimal> {
+        let params = [("symbol", symbol.to_string())];
+        let ticker: crate::models::Ticker24hApi = self
+            .get_public(&self.api_base_url, TICKER_24H_ENDPOINT, &params)
+            .await?;
+        let ctx = ParseContext::new(TICKER_24H_ENDPOINT).with_subject("symbol", symbol);
+        parse_decimal(ctx, "weightedAvgPrice", &ticker.weighted_avg_price)
+    }
+
+    /// Close of the UTC daily kline opening on `date`; `None` when the symbol
+    /// has no kline for that day.
+    pub async fn daily_close(&self, symbol: &str, date: NaiveDate) -> AppResult<Option<Decimal>> {
+        let open_time = date
+            .and_time(chrono::NaiveTime::MIN)
+            .and_utc()
+            .timestamp_millis();
+        let params = [
+            ("symbol", symbol.to_string()),
+            ("interval", "1d".to_string()),
+            ("startTime", open_time.to_string()),
+            ("limit", "1".to_string()),
+        ];
+        let klines: Vec<Vec<serde_json::Value>> = self
+            .get_public(&self.api_base_url, KLINES_ENDPOINT, &params)
+            .await?;
+        let Some(kline) = klines
+            .first()
+            .filter(|k| k.first().and_then(|t| t.as_i64()) == Some(open_time))
+        else {
+            return Ok(None);
+        };
+        let close = kline.get(4).and_then(|c| c.as_str()).unwrap_or_default();
+        let ctx = ParseContext::new(KLINES_ENDPOINT).with_subject("symbol", symbol);
+        parse_decimal(ctx, "close", close).map(Some)
+    }
+
+    /// Measures the offset between the local clock and Binance server time and
+    /// applies it to the timestamp of subsequent signed requests.
+    pub async fn sync_time(&self) -> AppResult<i64> {
+        let server_time: ServerTimeApi = self
+            .get_public(&self.api_base_url, SERVER_TIME_ENDPOINT, &[])
             .await?;
-        parse_decimal("price", &ticker.price)
+        let offset = server_time.server_time - self.time_source.now_millis()?;
+        self.time_offset_ms.store(offset, Ordering::Relaxed);
+        debug!(
+            offset_ms = offset,
+            "synchronized clock with binance server time"
+        );
+        Ok(offset)
     }
 
+    /// Decoded in two steps so that an empty account (`[]`) is told apart
+    /// from a body of the wrong shape, which is reported with the raw body.
     async fn get_um_positions(&self) -> AppResult<Vec<UmPositionApi>> {
-        self.get_signed(&self.papi_base_url, "/papi/v1/um/positionRisk", &[])
-            .await
+        let body: serde_json::Value = self
+            .get_signed(&self.papi_base_url, UM_POSITION_RISK_ENDPOINT, &[])
+            .await?;
+        let unexpected = |reason: String, body: &serde_json::Value| AppError::UnexpectedResponse {
+            endpoint: UM_POSITION_RISK_ENDPOINT,
+            reason,
+            body: body.to_string(),
+        };
+        if !body.is_array() {
+            return Err(unexpected(
+                "expected an array of positions".to_string(),
+                &body,
+            ));
+        }
+        Vec::<UmPositionApi>::deserialize(&body).map_err(|err| unexpected(err.to_string(), &body))
     }
 
     async fn get_pm_account_info(&self) -> AppResult<PmAccountInfoApi> {
-        self.get_signed(&self.papi_base_url, "/papi/v1/account", &[])
+        self.get_signed(&self.papi_base_url, PM_ACCOUNT_ENDPOINT, &[])
             .await
     }
 
     async fn get_pm_account_balances(&self) -> AppResult<Vec<PmAccountBalanceApi>> {
-        self.get_signed(&self.papi_base_url, "/papi/v1/balance", &[])
+        self.get_signed(&self.papi_base_url, PM_BALANCE_ENDPOINT, &[])
             .await
     }
 
+    /// A -2015 here usually means the key lacks spot read permission even
+    /// though the portfolio margin endpoints accept it, so it is reported as
+    /// [`AppError::MissingPermission`].
     async fn get_spot_account_info(&self) -> AppResult<SpotAccountInfoApi> {
-        self.get_signed(&self.api_base_url, "/api/v3/account", &[])
+        self.get_signed(&self.api_base_url, SPOT_ACCOUNT_ENDPOINT, &[])
             .await
+            .map_err(|err| match err {
+                AppError::BinanceApiMessage { code: -2015, msg } => AppError::MissingPermission {
+                    permission: "spot read (\"Enable Reading\")",
+                    endpoint: SPOT_ACCOUNT_ENDPOINT,
+                    code: -2015,
+                    msg,
+                },
+                other => other,
+            })
+    }
+
+    /// Sums Simple Earn flexible and locked holdings per asset.
+    async fn get_earn_balances(&self) -> AppResult<Vec<SpotBalance>> {
+        let (flexible, locked) = tokio::try_join!(
+            self.get_all_earn_pages::<SimpleEarnFlexiblePositionApi>(EARN_FLEXIBLE_ENDPOINT),
+            self.get_all_earn_pages::<SimpleEarnLockedPositionApi>(EARN_LOCKED_ENDPOINT),
+        )?;
+
+        let mut balances = Vec::with_capacity(flexible.len() + locked.len());
+        for row in &flexible {
+            let ctx = ParseContext::new(EARN_FLEXIBLE_ENDPOINT).with_subject("asset", &row.asset);
+            balances.push(SpotBalance::new(
+                row.asset.clone(),
+                parse_decimal(ctx, "totalAmount", &row.total_amount)?,
+            ));
+        }
+        for row in &locked {
+            let ctx = ParseContext::new(EARN_LOCKED_ENDPOINT).with_subject("asset", &row.asset);
+            balances.push(SpotBalance::new(
+                row.asset.clone(),
+                parse_decimal(ctx, "amount", &row.amount)?,
+            ));
+        }
+        Ok(balances)
+    }
+
+    /// Reads the classic USDⓂ futures account: total equity, wallet balance
+    /// and unrealized PnL in USD, plus every nonzero wallet asset.
+    async fn get_classic_futures_account(&self) -> AppResult<ClassicFuturesAccount> {
+        let (account, balances): (FapiAccountApi, Vec<FapiBalanceApi>) = tokio::try_join!(
+            self.get_signed(&self.fapi_base_url, FAPI_ACCOUNT_ENDPOINT, &[]),
+            self.get_signed(&self.fapi_base_url, FAPI_BALANCE_ENDPOINT, &[]),
+        )?;
+
+        let ctx = ParseContext::new(FAPI_ACCOUNT_ENDPOINT);
+        let mut wallet_balances = Vec::new();
+        for balance in &balances {
+            let ctx =
+                ParseContext::new(FAPI_BALANCE_ENDPOINT).with_subject("asset", &balance.asset);
+            let amount = parse_decimal(ctx, "balance", &balance.balance)?;
+            if !amount.is_zero() {
+                wallet_balances.push(SpotBalance::new(balance.asset.clone(), amount));
+            }
+        }
+        Ok(ClassicFuturesAccount {
+            equity_usd: parse_decimal(ctx, "totalMarginBalance", &account.total_margin_balance)?,
+            wallet_balance_usd: parse_decimal(
+                ctx,
+                "totalWalletBalance",
+                &account.total_wallet_balance,
+            )?,
+            unrealized_pnl_usd: parse_decimal(
+                ctx,
+                "totalUnrealizedProfit",
+                &account.total_unrealized_profit,
+            )?,
+            balances: wallet_balances,
+        })
+    }
+
+    /// Returns every cross-margin asset with a nonzero borrowed+interest balance.
+    async fn get_margin_liabilities(&self) -> AppResult<Vec<MarginLiability>> {
+        let account: MarginAccountApi = self
+            .get_signed(&self.api_base_url, MARGIN_ACCOUNT_ENDPOINT, &[])
+            .await?;
+
+        let mut liabilities = Vec::new();
+        for asset in &account.user_assets {
+            let ctx =
+                ParseContext::new(MARGIN_ACCOUNT_ENDPOINT).with_subject("asset", &asset.asset);
+            let amount = parse_decimal(ctx, "borrowed", &asset.borrowed)?
+                + parse_decimal(ctx, "interest", &asset.interest)?;
+            if !amount.is_zero() {
+                liabilities.push(MarginLiability {
+                    asset: asset.asset.clone(),
+                    amount,
+                });
+            }
+        }
+        Ok(liabilities)
+    }
+
+    async fn get_all_earn_pages<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &'static str,
+    ) -> AppResult<Vec<T>> {
+        let mut rows = Vec::new();
+        let mut current = 1u64;
+        loop {
+            let params = [
+                ("current", current.to_string()),
+                ("size", EARN_PAGE_SIZE.to_string()),
+            ];
+            let page: SimpleEarnPageApi<T> = self
+                .get_signed(&self.api_base_url, endpoint, &params)
+                .await?;
+            let fetched = page.rows.len() as u64;
+            rows.extend(page.rows);
+            if fetched < EARN_PAGE_SIZE || rows.len() as u64 >= page.total {
+                return Ok(rows);
+            }
+            current += 1;
+        }
     }
 
     async fn get_public<T: serde::de::DeserializeOwned>(
@@ -140,8 +1023,11 @@ impl BinanceClient {
             self.http.get(format!("{url}?{query}"))
         };
 
+        self.throttle().await;
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
         let response = request.send().await?;
-        parse_response(response).await
+        self.record_used_weight(&response);
+        parse_response(response, &self.field_aliases).await
     }
 
     async fn get_signed<T: serde::de::DeserializeOwned>(
@@ -150,24 +1036,119 @@ impl BinanceClient {
         endpoint: &str,
         params: &[(&str, String)],
     ) -> AppResult<T> {
-        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let response = self.send_signed(base_url, endpoint, params).await?;
+        match self.parse_signed(endpoint, response).await {
+            Err(AppError::BinanceApiMessage {
+                code: TIMESTAMP_OUTSIDE_RECV_WINDOW,
+                ..
+            }) => {
+                warn!(
+                    endpoint,
+                    "request timestamp rejected; re-syncing clock and retrying"
+                );
+                self.sync_time().await?;
+                let response = self.send_signed(base_url, endpoint, params).await?;
+                self.parse_signed(endpoint, response).await
+            }
+            other => other,
+        }
+    }
+
+    /// `parse_response`, first recording the body when `endpoint` is
+    /// captured.
+    async fn parse_signed<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        response: reqwest::Response,
+    ) -> AppResult<T> {
+        let Some(raw) = self
+            .raw_responses
+            .as_ref()
+            .filter(|_| RAW_DUMP_ENDPOINTS.iter().any(|(_, e)| *e == endpoint))
+        else {
+            return parse_response(response, &self.field_aliases).await;
+        };
+        let mut value: serde_json::Value = serde_json::from_str(&response_text(response).await?)?;
+        raw.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(endpoint.to_string(), value.clone());
+        rename_fields(&mut value, &self.field_aliases);
+        Ok(serde_json::from_value(value)?)
+    }
+
+    async fn send_signed(
+        &self,
+        base_url: &str,
+        endpoint: &str,
+        params: &[(&str, String)],
+    ) -> AppResult<reqwest::Response> {
+        // Waits before signing, so the timestamp is fresh when sent.
+        self.throttle().await;
+        let query = self.signed_query(params)?;
+        let url = format!("{}{}?{}", base_url, endpoint, query);
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+        let response = self.http.get(url).send().await?;
+        self.record_used_weight(&response);
+        Ok(response)
+    }
+
+    async fn throttle(&self) {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.until_ready().await;
+        }
+    }
+
+    /// `params` plus `timestamp`, percent-encoded, then `signature`. The
+    /// signature covers the encoded string, which is sent unchanged: the URL
+    /// parser keeps percent-escapes as they are.
+    fn signed_query(&self, params: &[(&str, String)]) -> AppResult<String> {
+        let timestamp =
+            self.time_source.now_millis()? + self.time_offset_ms.load(Ordering::Relaxed);
         let timestamp_string = timestamp.to_string();
 
         let mut pairs: Vec<(&str, &str)> = params.iter().map(|(k, v)| (*k, v.as_str())).collect();
         pairs.push(("timestamp", &timestamp_string));
 
         let mut query = build_query(pairs);
-        let signature = sign_query(&query, &self.api_secret)?;
+        let signature = self.signer.sign(&query)?;
         if !query.is_empty() {
             query.push('&');
         }
         query.push_str("signature=");
         query.push_str(&signature);
+        Ok(query)
+    }
 
-        let url = format!("{}{}?{}", base_url, endpoint, query);
-        let response = self.http.get(url).send().await?;
-        parse_response(response).await
+    /// Binance requests sent so far, including failed and retried ones.
+    /// Differences between two readings give the cost of the work in between.
+    pub fn api_calls(&self) -> u32 {
+        self.api_calls.load(Ordering::Relaxed)
+    }
+
+    /// Request weight used in the current one-minute window, as last reported
+    /// by Binance. Zero until a response carrying the header is received.
+    pub fn used_weight_1m(&self) -> u32 {
+        self.used_weight_1m.load(Ordering::Relaxed)
     }
+
+    fn record_used_weight(&self, response: &reqwest::Response) {
+        let weight = response
+            .headers()
+            .get(USED_WEIGHT_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u32>().ok());
+        if let Some(weight) = weight {
+            self.used_weight_1m.store(weight, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Formats a list parameter the way Binance takes it, e.g.
+/// `symbols=["BTCUSDT","ETHUSDT"]`: a JSON array without spaces, which
+/// `build_query` then percent-encodes like any other value.
+fn array_param<S: AsRef<str>>(items: &[S]) -> String {
+    let items: Vec<&str> = items.iter().map(AsRef::as_ref).collect();
+    serde_json::Value::from(items).to_string()
 }
 
 fn build_query<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
@@ -178,88 +1159,297 @@ fn build_query<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> Strin
     serializer.finish()
 }
 
-fn sign_query(query: &str, secret: &str) -> AppResult<String> {
-    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).map_err(|_| AppError::Signature)?;
-    mac.update(query.as_bytes());
-    Ok(hex::encode(mac.finalize().into_bytes()))
-}
-
 async fn parse_response<T: serde::de::DeserializeOwned>(
     response: reqwest::Response,
+    field_aliases: &HashMap<String, String>,
 ) -> AppResult<T> {
+    let body = response_text(response).await?;
+    if field_aliases.is_empty() {
+        return Ok(serde_json::from_str(&body)?);
+    }
+    let mut value: serde_json::Value = serde_json::from_str(&body)?;
+    rename_fields(&mut value, field_aliases);
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Renames object keys in `value`, at any depth, per `aliases`. A key whose
+/// expected name is already present is left alone, so a fixed upstream
+/// response is not clobbered.
+fn rename_fields(value: &mut serde_json::Value, aliases: &HashMap<String, String>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (received, expected) in aliases {
+                if map.contains_key(expected) {
+                    continue;
+                }
+                if let Some(field) = map.remove(received) {
+                    map.insert(expected.clone(), field);
+                }
+            }
+            map.values_mut()
+                .for_each(|field| rename_fields(field, aliases));
+        }
+        serde_json::Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| rename_fields(item, aliases)),
+        _ => {}
+    }
+}
+
+async fn response_text(response: reqwest::Response) -> AppResult<String> {
     let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs);
     let body = response.text().await?;
 
     if !status.is_success() {
+        let rate_limited =
+            status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::IM_A_TEAPOT;
+        if is_maintenance(status, &body) {
+            return Err(AppError::Maintenance { msg: body });
+        }
         if let Ok(err) = serde_json::from_str::<BinanceErrorBody>(&body) {
+            if rate_limited || RATE_LIMIT_CODES.contains(&err.code) {
+                return Err(AppError::RateLimited {
+                    retry_after: retry_after.or_else(|| banned_until_hint(&err.msg)),
+                    msg: err.msg,
+                });
+            }
             return Err(AppError::BinanceApiMessage {
                 code: err.code,
                 msg: err.msg,
             });
         }
+        if rate_limited {
+            return Err(AppError::RateLimited {
+                retry_after,
+                msg: body,
+            });
+        }
         return Err(AppError::BinanceApi {
             status: status.as_u16(),
             body,
         });
     }
 
-    Ok(serde_json::from_str(&body)?)
+    Ok(body)
+}
+
+/// Code `-1016`, or a 503 whose body mentions maintenance.
+fn is_maintenance(status: StatusCode, body: &str) -> bool {
+    if let Ok(err) = serde_json::from_str::<BinanceErrorBody>(body) {
+        if err.code == MAINTENANCE_CODE {
+            return true;
+        }
+    }
+    status == StatusCode::SERVICE_UNAVAILABLE && body.to_ascii_lowercase().contains("maintenance")
+}
+
+/// Extracts the wait from messages like `IP banned until 1700000000000.`
+fn banned_until_hint(msg: &str) -> Option<Duration> {
+    let (_, rest) = msg.split_once("until ")?;
+    let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    let until_ms = digits.parse::<i64>().ok()?;
+    let remaining_ms = until_ms - SystemTimeSource.now_millis().ok()?;
+    Some(Duration::from_millis(remaining_ms.max(0) as u64))
 }
 
-fn parse_decimal(field: &'static str, value: &str) -> AppResult<Decimal> {
-    Decimal::from_str_exact(value).map_err(|_| AppError::DecimalParse {
-        field,
-        value: value.to_string(),
-    })
+/// Parses a Binance decimal string. Dust amounts sometimes come back in
+/// scientific notation (`1.5E-8`), which `from_str_exact` rejects.
+fn parse_decimal(ctx: ParseContext<'_>, field: &'static str, value: &str) -> AppResult<Decimal> {
+    Decimal::from_str_exact(value)
+        .or_else(|_| Decimal::from_scientific(value))
+        .map_err(|_| AppError::DecimalParse {
+            field,
+            value: value.to_string(),
+            subject: ctx.subject.map(|(kind, name)| format!("{kind} {name}")),
+            endpoint: ctx.endpoint,
+        })
 }
 
+fn no_snapshot(kind: SnapshotType, date: NaiveDate) -> AppError {
+    AppError::NoSnapshot {
+        kind: kind.as_api(),
+        date,
+    }
+}
+
+/// Keeps positions in `selection`; with `Selection::All` only nonzero
+/// positions are kept. Configured symbols absent from the response (e.g.
+/// delisted markets) are logged.
 fn filter_positions(
     positions: &[UmPositionApi],
-    required_symbols: &[String],
+    selection: &Selection,
 ) -> AppResult<Vec<UmPosition>> {
+    let missing = missing_symbols(positions, selection);
+    if !missing.is_empty() {
+        warn!(
+            symbols = ?missing,
+            "configured UM symbols missing from positionRisk; they may have been delisted"
+        );
+    }
+
     let mut filtered = Vec::new();
     for position in positions {
-        if required_symbols.contains(&position.symbol) {
+        if selection.includes(&position.symbol) {
+            let ctx = ParseContext::new(UM_POSITION_RISK_ENDPOINT)
+                .with_subject("symbol", &position.symbol);
+            let amount = parse_decimal(ctx, "positionAmt", &position.position_amt)?;
+            if selection.is_all() && amount.is_zero() {
+                continue;
+            }
+            let mark_price = parse_decimal(ctx, "markPrice", &position.mark_price)?;
             filtered.push(UmPosition {
                 symbol: position.symbol.clone(),
-                amount: parse_decimal("positionAmt", &position.position_amt)?,
-                pnl: parse_decimal("unrealizedProfit", &position.unrealized_profit)?,
+                amount,
+                pnl: parse_decimal(ctx, "unrealizedProfit", &position.unrealized_profit)?,
+                mark_price,
+                notional: amount * mark_price,
             });
         }
     }
     Ok(filtered)
 }
 
+/// Configured symbols that `positionRisk` did not return at all, as opposed
+/// to returning with a zero amount.
+fn missing_symbols(positions: &[UmPositionApi], selection: &Selection) -> Vec<String> {
+    match selection {
+        Selection::All => Vec::new(),
+        Selection::Only(symbols) => symbols
+            .iter()
+            .filter(|symbol| !positions.iter().any(|p| &p.symbol == *symbol))
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Keeps balances in `selection`; with `Selection::All` only nonzero
+/// balances are kept. With `keep_zero` every configured asset is reported,
+/// with a zero amount when the account holds none, so the report shape is
+/// stable across runs.
 fn filter_spot_balances(
     account_info: &SpotAccountInfoApi,
-    required_assets: &[String],
+    selection: &Selection,
+    keep_zero: bool,
+    split: bool,
 ) -> AppResult<Vec<SpotBalance>> {
     let mut filtered = Vec::new();
     for balance in &account_info.balances {
-        if required_assets.contains(&balance.asset) {
-            let free = parse_decimal("free", &balance.free)?;
-            let locked = parse_decimal("locked", &balance.locked)?;
+        if selection.includes(&balance.asset) {
+            let ctx =
+                ParseContext::new(SPOT_ACCOUNT_ENDPOINT).with_subject("asset", &balance.asset);
+            let free = parse_decimal(ctx, "free", &balance.free)?;
+            let locked = parse_decimal(ctx, "locked", &balance.locked)?;
+            if selection.is_all() && (free + locked).is_zero() {
+                continue;
+            }
             filtered.push(SpotBalance {
                 asset: balance.asset.clone(),
                 amount: free + locked,
+                free: split.then_some(free),
+                locked: split.then_some(locked),
+                earn: None,
             });
         }
     }
+
+    if let (true, Selection::Only(assets)) = (keep_zero, selection) {
+        for asset in assets {
+            if !filtered.iter().any(|b| &b.asset == asset) {
+                filtered.push(SpotBalance {
+                    asset: asset.clone(),
+                    amount: Decimal::ZERO,
+                    free: split.then_some(Decimal::ZERO),
+                    locked: split.then_some(Decimal::ZERO),
+                    earn: None,
+                });
+            }
+        }
+    }
     Ok(filtered)
 }
 
+/// Adds Simple Earn amounts into `balances` by asset, keeping only selected
+/// assets. With `split` they are also recorded as the balance's `earn` part,
+/// next to a zero `free`/`locked` for assets held only in Earn.
+fn merge_balances(
+    balances: &mut Vec<SpotBalance>,
+    earn: Vec<SpotBalance>,
+    selection: &Selection,
+    split: bool,
+) {
+    for item in earn {
+        if !selection.includes(&item.asset) || item.amount.is_zero() {
+            continue;
+        }
+        match balances.iter_mut().find(|b| b.asset == item.asset) {
+            Some(existing) => {
+                existing.amount += item.amount;
+                if split {
+                    existing.earn = Some(existing.earn.unwrap_or_default() + item.amount);
+                }
+            }
+            None if split => balances.push(SpotBalance {
+                free: Some(Decimal::ZERO),
+                locked: Some(Decimal::ZERO),
+                earn: Some(item.amount),
+                ..item
+            }),
+            None => balances.push(item),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
     #[test]
-    fn query_signing_is_stable() {
-        let signature = sign_query("timestamp=123", "secret").expect("signature should work");
+    fn array_params_are_signed_as_sent() {
+        let client = BinanceClient::with_http_client(
+            reqwest::Client::new(),
+            "test-secret".to_string(),
+            "http://127.0.0.1:9".to_string(),
+            "http://127.0.0.1:9".to_string(),
+        )
+        .with_time_source(Arc::new(crate::clock::FixedTimeSource(1_700_000_000_000)));
+        let symbols = ["BTCUSDT", "ETHUSDT"];
+        assert_eq!(array_param(&symbols), r#"["BTCUSDT","ETHUSDT"]"#);
+
+        let query = client
+            .signed_query(&[("symbols", array_param(&symbols))])
+            .unwrap();
         assert_eq!(
-            signature,
-            "49a8d551f916f1f7fd6956b49f3ea8c8e1f955490f8e19b5fb0bed82dbe6fd9b"
+            query,
+            "symbols=%5B%22BTCUSDT%22%2C%22ETHUSDT%22%5D&timestamp=1700000000000\
+             &signature=72c6fa905d83a9e4c53735281968beab701db4b5be6c80613def77ac8ef68c5d"
         );
+        let url = reqwest::Url::parse(&format!("http://127.0.0.1:9/api?{query}")).unwrap();
+        assert_eq!(url.query(), Some(query.as_str()));
+    }
+
+    #[test]
+    fn parses_scientific_notation() {
+        let ctx = ParseContext::new("/api/v3/account");
+        assert_eq!(
+            parse_decimal(ctx, "free", "1E-8").unwrap(),
+            Decimal::new(1, 8)
+        );
+        assert_eq!(
+            parse_decimal(ctx, "free", "1.23e5").unwrap(),
+            Decimal::from(123_000)
+        );
+        assert_eq!(
+            parse_decimal(ctx, "free", "0.00000001").unwrap(),
+            Decimal::new(1, 8)
+        );
+        assert!(parse_decimal(ctx, "free", "1e").is_err());
     }
 
     #[test]
@@ -269,11 +1459,206 @@ mod tests {
         ))
         .expect("spot payload should decode");
 
-        let out = filter_spot_balances(&payload, &["BTC".to_string(), "USDT".to_string()])
-            .expect("filter should work");
+        let selection = Selection::Only(vec!["BTC".to_string(), "USDT".to_string()]);
+        let out =
+            filter_spot_balances(&payload, &selection, false, false).expect("filter should work");
 
         assert_eq!(out.len(), 2);
         assert_eq!(out[0].asset, "BTC");
         assert_eq!(out[1].asset, "USDT");
+        assert_eq!(out[0].free, None);
+    }
+
+    #[test]
+    fn split_keeps_free_and_locked_next_to_the_total() {
+        let payload: SpotAccountInfoApi =
+            serde_json::from_str(r#"{"balances":[{"asset":"BTC","free":"1.5","locked":"0.25"}]}"#)
+                .unwrap();
+        let out = filter_spot_balances(&payload, &Selection::All, false, true).unwrap();
+        assert_eq!(out[0].amount, Decimal::new(175, 2));
+        assert_eq!(out[0].free, Some(Decimal::new(15, 1)));
+        assert_eq!(out[0].locked, Some(Decimal::new(25, 2)));
+    }
+
+    #[test]
+    fn field_aliases_rename_nested_keys_without_clobbering() {
+        let aliases = HashMap::from([("uniMmrRatio".to_string(), "uniMMR".to_string())]);
+        let mut value = serde_json::json!({
+            "uniMmrRatio": "5.0",
+            "accounts": [{"uniMmrRatio": "1"}, {"uniMmrRatio": "2", "uniMMR": "3"}],
+        });
+        rename_fields(&mut value, &aliases);
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "uniMMR": "5.0",
+                "accounts": [{"uniMMR": "1"}, {"uniMmrRatio": "2", "uniMMR": "3"}],
+            })
+        );
+    }
+
+    #[test]
+    fn decimal_parse_error_names_symbol_and_endpoint() {
+        let positions = vec![UmPositionApi {
+            symbol: "BTCUSDT".to_string(),
+            position_amt: "abc".to_string(),
+            mark_price: "65000".to_string(),
+            unrealized_profit: "0".to_string(),
+        }];
+
+        let err = filter_positions(&positions, &Selection::Only(vec!["BTCUSDT".to_string()]))
+            .expect_err("bad decimal must fail");
+        assert_eq!(
+            err.to_string(),
+            "failed to parse positionAmt `abc` for symbol BTCUSDT from /papi/v1/um/positionRisk"
+        );
+    }
+
+    #[test]
+    fn merges_earn_balances_into_required_spot_assets() {
+        let mut balances = vec![SpotBalance::new("USDT", Decimal::from(10))];
+        let earn = vec![
+            SpotBalance::new("USDT", Decimal::from(5)),
+            SpotBalance::new("ETH", Decimal::from(2)),
+            SpotBalance::new("AXS", Decimal::from(7)),
+        ];
+
+        let selection = Selection::Only(vec!["USDT".to_string(), "ETH".to_string()]);
+        merge_balances(&mut balances, earn, &selection, false);
+
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[0].amount, Decimal::from(15));
+        assert_eq!(balances[1].asset, "ETH");
+        assert_eq!(balances[1].amount, Decimal::from(2));
+    }
+
+    #[test]
+    fn earn_is_its_own_part_of_a_split_balance() {
+        let payload: SpotAccountInfoApi =
+            serde_json::from_str(r#"{"balances":[{"asset":"USDT","free":"1","locked":"0.5"}]}"#)
+                .expect("spot payload should decode");
+        let selection = Selection::Only(vec!["USDT".to_string(), "ETH".to_string()]);
+        let mut balances =
+            filter_spot_balances(&payload, &selection, false, true).expect("filter should work");
+        let earn = vec![
+            SpotBalance::new("USDT", Decimal::from(5)),
+            SpotBalance::new("ETH", Decimal::from(2)),
+        ];
+        merge_balances(&mut balances, earn, &selection, true);
+
+        let parts: Vec<_> = balances
+            .iter()
+            .map(|b| (b.asset.as_str(), b.amount, b.free, b.locked, b.earn))
+            .collect();
+        assert_eq!(
+            parts,
+            [
+                (
+                    "USDT",
+                    Decimal::new(65, 1),
+                    Some(Decimal::ONE),
+                    Some(Decimal::new(5, 1)),
+                    Some(Decimal::from(5))
+                ),
+                (
+                    "ETH",
+                    Decimal::from(2),
+                    Some(Decimal::ZERO),
+                    Some(Decimal::ZERO),
+                    Some(Decimal::from(2))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn show_zero_balances_keeps_every_configured_asset() {
+        let payload: SpotAccountInfoApi = serde_json::from_str(
+            r#"{"balances":[
+                {"asset":"BTC","free":"0.5","locked":"0"},
+                {"asset":"ETH","free":"0","locked":"0"}
+            ]}"#,
+        )
+        .expect("spot payload should decode");
+        let selection = Selection::Only(vec![
+            "BTC".to_string(),
+            "ETH".to_string(),
+            "SOL".to_string(),
+        ]);
+
+        let out =
+            filter_spot_balances(&payload, &selection, true, false).expect("filter should work");
+        let assets: Vec<&str> = out.iter().map(|b| b.asset.as_str()).collect();
+        assert_eq!(assets, vec!["BTC", "ETH", "SOL"]);
+        assert!(out[2].amount.is_zero());
+    }
+
+    #[test]
+    fn explicit_selection_keeps_reported_zero_balances_by_default() {
+        let payload: SpotAccountInfoApi = serde_json::from_str(
+            r#"{"balances":[
+                {"asset":"BTC","free":"0.5","locked":"0"},
+                {"asset":"ETH","free":"0","locked":"0"}
+            ]}"#,
+        )
+        .expect("spot payload should decode");
+        let selection = Selection::Only(vec![
+            "BTC".to_string(),
+            "ETH".to_string(),
+            "SOL".to_string(),
+        ]);
+
+        let out =
+            filter_spot_balances(&payload, &selection, false, false).expect("filter should work");
+        let assets: Vec<&str> = out.iter().map(|b| b.asset.as_str()).collect();
+        assert_eq!(assets, vec!["BTC", "ETH"]);
+    }
+
+    #[test]
+    fn all_selection_keeps_only_nonzero_holdings() {
+        let payload: SpotAccountInfoApi = serde_json::from_str(
+            r#"{"balances":[
+                {"asset":"BTC","free":"0.5","locked":"0"},
+                {"asset":"DUST","free":"0","locked":"0"},
+                {"asset":"ETH","free":"0","locked":"1"}
+            ]}"#,
+        )
+        .expect("spot payload should decode");
+        let out = filter_spot_balances(&payload, &Selection::All, false, false)
+            .expect("filter should work");
+        let assets: Vec<&str> = out.iter().map(|b| b.asset.as_str()).collect();
+        assert_eq!(assets, vec!["BTC", "ETH"]);
+
+        let positions = vec![
+            UmPositionApi {
+                symbol: "BTCUSDT".to_string(),
+                position_amt: "0.1".to_string(),
+                mark_price: "65000".to_string(),
+                unrealized_profit: "0".to_string(),
+            },
+            UmPositionApi {
+                symbol: "ETHUSDT".to_string(),
+                position_amt: "0".to_string(),
+                mark_price: "3000".to_string(),
+                unrealized_profit: "0".to_string(),
+            },
+        ];
+        let out = filter_positions(&positions, &Selection::All).expect("filter should work");
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].symbol, "BTCUSDT");
+    }
+
+    #[test]
+    fn absent_symbols_are_missing_but_zero_amounts_are_not() {
+        let positions = vec![UmPositionApi {
+            symbol: "ETHUSDT".to_string(),
+            position_amt: "0".to_string(),
+            mark_price: "3000".to_string(),
+            unrealized_profit: "0".to_string(),
+        }];
+        let selection = Selection::Only(vec!["ETHUSDT".to_string(), "LUNAUSDT".to_string()]);
+
+        assert_eq!(missing_symbols(&positions, &selection), vec!["LUNAUSDT"]);
+        assert!(missing_symbols(&positions, &Selection::All).is_empty());
     }
 }
@@ -0,0 +1,60 @@
+use std::fmt;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::AppResult;
+
+/// Supplies the `timestamp` of signed requests, in Unix milliseconds.
+///
+/// The server-time offset measured by `BinanceClient::sync_time` is added on
+/// top, so a source only needs to advance steadily, not be correct.
+pub trait TimeSource: fmt::Debug + Send + Sync {
+    fn now_millis(&self) -> AppResult<i64>;
+}
+
+/// Default source: the system wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now_millis(&self) -> AppResult<i64> {
+        Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64)
+    }
+}
+
+/// Reads the wall clock once, then advances with a monotonic clock, so later
+/// clock steps (NTP corrections, manual changes) do not move timestamps.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicTimeSource {
+    origin_millis: i64,
+    origin: Instant,
+}
+
+impl MonotonicTimeSource {
+    pub fn new() -> AppResult<Self> {
+        Ok(Self::starting_at(SystemTimeSource.now_millis()?))
+    }
+
+    /// Starts counting from `origin_millis` instead of the wall clock.
+    pub fn starting_at(origin_millis: i64) -> Self {
+        Self {
+            origin_millis,
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl TimeSource for MonotonicTimeSource {
+    fn now_millis(&self) -> AppResult<i64> {
+        Ok(self.origin_millis + self.origin.elapsed().as_millis() as i64)
+    }
+}
+
+/// Always returns the same timestamp; for tests and fixtures.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedTimeSource(pub i64);
+
+impl TimeSource for FixedTimeSource {
+    fn now_millis(&self) -> AppResult<i64> {
+        Ok(self.0)
+    }
+}
@@ -1,21 +1,411 @@
-use clap::{Parser, ValueEnum};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Duration;
 
+use rust_decimal::{Decimal, RoundingStrategy};
+use tracing::warn;
+
 use crate::error::{AppError, AppResult};
+use crate::models::{normalize_symbol, PriceBasis};
 
 const DEFAULT_UM_POSITIONS: &str = "BTCUSDT,ETHUSDT,SOLUSDT";
 const DEFAULT_SPOT_ASSETS: &str = "USDT,BTC,ETH,SOL";
 
+/// Quote currencies with spot pairs against the common base assets, besides
+/// `USD` itself.
+const SUPPORTED_QUOTE_CURRENCIES: [&str; 8] =
+    ["USDT", "USDC", "FDUSD", "TUSD", "DAI", "EUR", "TRY", "BRL"];
+
 #[derive(Debug, Clone, Copy, ValueEnum)]
 pub enum OutputFormat {
     Table,
     Json,
+    Logfmt,
+    /// The report rendered through `--template`.
+    Template,
+    /// Timestamp, AUM in the base and quote currency, and uniMMR only.
+    Summary,
+    /// The full report as binary MessagePack, with field names.
+    #[value(name = "msgpack")]
+    MessagePack,
+}
+
+/// Where a rendered report goes. Every sink renders the same report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sink {
+    Table,
+    /// JSON to stdout, or to the given file.
+    Json(Option<PathBuf>),
+    Logfmt,
+    /// `--template` rendered to stdout.
+    Template,
+    Summary,
+    /// MessagePack to the output stream, or to the given file.
+    MessagePack(Option<PathBuf>),
+    /// Serves the latest report on `/metrics`; loop mode only.
+    Prometheus(SocketAddr),
+}
+
+impl Sink {
+    /// Whether the sink writes to the `--output-stream` rather than a file or
+    /// socket.
+    pub fn is_stream(&self) -> bool {
+        matches!(
+            self,
+            Sink::Table
+                | Sink::Json(None)
+                | Sink::MessagePack(None)
+                | Sink::Logfmt
+                | Sink::Template
+                | Sink::Summary
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MissingPricePolicy {
+    #[default]
+    Fail,
+    Skip,
+    Zero,
+}
+
+/// What the loop does with a report whose prices jumped past
+/// `--max-price-deviation-between-runs`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PriceDeviationAction {
+    /// Fail the iteration; nothing is emitted to the sinks.
+    #[default]
+    Skip,
+    /// Log a warning and emit the report anyway.
+    Warn,
+}
+
+/// What happens to a report that fails the `--aum-bounds-tolerance-pct`
+/// self-check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum AumBoundsAction {
+    /// Log a warning and emit the report anyway.
+    #[default]
+    Warn,
+    /// Fail the report.
+    Fail,
+}
+
+/// Whether a failed loop iteration ends the loop.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LoopErrorPolicy {
+    /// Log the error and retry on the next interval, whatever it was.
+    Continue,
+    /// Stop on the first error.
+    FailFast,
+    /// Stop on errors a retry cannot fix (see [`AppError::is_permanent`]),
+    /// such as a revoked API key; retry the rest.
+    #[default]
+    Smart,
+}
+
+impl LoopErrorPolicy {
+    pub fn stops_on(self, err: &AppError) -> bool {
+        match self {
+            LoopErrorPolicy::Continue => false,
+            LoopErrorPolicy::FailFast => true,
+            LoopErrorPolicy::Smart => err.is_permanent(),
+        }
+    }
+}
+
+/// How `aum_base_u8` drops precision below 1e-8 of the base asset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SatoshiRounding {
+    /// Toward zero.
+    #[default]
+    Trunc,
+    /// To nearest, ties to even (banker's rounding).
+    Round,
+    Floor,
+    Ceil,
+}
+
+impl SatoshiRounding {
+    pub fn strategy(self) -> RoundingStrategy {
+        match self {
+            SatoshiRounding::Trunc => RoundingStrategy::ToZero,
+            SatoshiRounding::Round => RoundingStrategy::MidpointNearestEven,
+            SatoshiRounding::Floor => RoundingStrategy::ToNegativeInfinity,
+            SatoshiRounding::Ceil => RoundingStrategy::ToPositiveInfinity,
+        }
+    }
+}
+
+/// Number rendering in the table output; JSON is always plain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum NumberFormat {
+    #[default]
+    Plain,
+    /// Thousands separators, e.g. `1,234,567`.
+    Grouped,
+}
+
+/// Layout of JSON output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum JsonStyle {
+    /// Indented, for reading in a terminal.
+    #[default]
+    Pretty,
+    /// One object per line, for log ingestion and JSONL files.
+    Compact,
+}
+
+/// How `Decimal` amounts appear in JSON output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum DecimalJson {
+    /// Quoted, e.g. `"0.123456789012345678"`: exact in every parser.
+    #[default]
+    String,
+    /// Bare JSON numbers; parsers that read doubles (JavaScript) keep about
+    /// 15 significant digits.
+    Number,
+}
+
+/// Structure of JSON output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum JsonShape {
+    /// The report as one nested object.
+    #[default]
+    Nested,
+    /// One row per spot contribution and position, each tagged with the
+    /// report id and timestamp, for loading into a columnar store.
+    Flat,
+}
+
+/// Standard stream that rendered reports are written to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputStream {
+    #[default]
+    Stdout,
+    /// Keeps stdout free for another stream piped alongside.
+    Stderr,
+}
+
+impl OutputStream {
+    pub fn is_terminal(self) -> bool {
+        use std::io::IsTerminal;
+        match self {
+            OutputStream::Stdout => std::io::stdout().is_terminal(),
+            OutputStream::Stderr => std::io::stderr().is_terminal(),
+        }
+    }
+}
+
+/// When table and summary output use ANSI colors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal, unless `NO_COLOR` is set;
+    /// `CLICOLOR_FORCE` colors regardless.
+    #[default]
+    Auto,
+    /// Always color, e.g. when piping into `less -R`.
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color output going to a stream that `is_terminal` or not.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        self.enabled_with(is_terminal, |key| std::env::var(key).ok())
+    }
+
+    fn enabled_with(self, is_terminal: bool, env: impl Fn(&str) -> Option<String>) -> bool {
+        let set = |key| env(key).is_some_and(|v| !v.is_empty() && v != "0");
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto if env("NO_COLOR").is_some_and(|v| !v.is_empty()) => false,
+            ColorChoice::Auto => set("CLICOLOR_FORCE") || is_terminal,
+        }
+    }
+}
+
+/// Time range `--include-income` sums UM income over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncomeWindow {
+    /// Since the previous report; one loop interval back for the first.
+    SincePrevious,
+    /// From a fixed start; `to` defaults to each report's timestamp.
+    Range {
+        from: DateTime<Utc>,
+        to: Option<DateTime<Utc>>,
+    },
+}
+
+/// Order of spot contributions in rendered output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ContributionSort {
+    /// Order of the configured asset list.
+    Config,
+    /// Largest base-asset value first.
+    #[default]
+    Value,
+    /// Alphabetical by asset.
+    Asset,
+}
+
+/// Which UM symbols or spot assets to report. `All` includes every position or
+/// balance with a nonzero amount instead of a fixed list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection {
+    All,
+    Only(Vec<String>),
+}
+
+impl Selection {
+    pub fn includes(&self, item: &str) -> bool {
+        match self {
+            Selection::All => true,
+            Selection::Only(items) => items.iter().any(|i| i == item),
+        }
+    }
+
+    pub fn is_all(&self) -> bool {
+        matches!(self, Selection::All)
+    }
+
+    /// Selects nothing; the endpoint behind it can be skipped.
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Selection::Only(items) if items.is_empty())
+    }
+}
+
+impl From<&[String]> for Selection {
+    fn from(items: &[String]) -> Self {
+        Selection::Only(items.to_vec())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Subcommand)]
+pub enum Command {
+    /// Fetch Binance data and compute AUM (default)
+    #[default]
+    Run,
+    /// Print raw JSON bodies of every Binance endpoint without decoding them
+    Dump,
+    /// Check connectivity and that the API key is valid, without computing AUM
+    Verify,
+    /// Print the configured UM positions and their PnL, without pricing
+    Positions,
+    /// List every nonzero spot balance and open UM position, ready to copy
+    /// into `BINANCE_SPOT_ASSETS` and `BINANCE_UM_POSITIONS`
+    Discover,
+    /// Print the portfolio margin withdrawable amount and its base-asset value
+    Liquidity,
+    /// Compute AUM from Binance's end-of-day account snapshot for a past date
+    Snapshot {
+        /// UTC day of the snapshot, `YYYY-MM-DD`
+        #[arg(long)]
+        date: NaiveDate,
+        #[arg(long = "type", value_enum, ignore_case = true)]
+        kind: SnapshotType,
+    },
+    /// Run fetch and compute back to back and print the latency distribution
+    Bench {
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+        iterations: u32,
+    },
+}
+
+/// Account type of `/sapi/v1/accountSnapshot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SnapshotType {
+    Spot,
+    Futures,
+}
+
+impl SnapshotType {
+    pub fn as_api(self) -> &'static str {
+        match self {
+            SnapshotType::Spot => "SPOT",
+            SnapshotType::Futures => "FUTURES",
+        }
+    }
+}
+
+/// Clock behind the `timestamp` of signed requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TimeSourceKind {
+    /// The system wall clock.
+    #[default]
+    System,
+    /// The wall clock at startup, advanced by a monotonic clock; immune to
+    /// later clock steps.
+    Monotonic,
+}
+
+/// Which Binance venue the account lives on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Exchange {
+    /// Binance.com, with a portfolio margin (PAPI) account.
+    #[default]
+    Global,
+    /// Binance.US: spot only, no portfolio margin, futures, margin or earn.
+    Us,
+}
+
+impl Exchange {
+    fn default_api_base_url(self) -> &'static str {
+        match self {
+            Exchange::Global => "https://api.binance.com",
+            Exchange::Us => "https://api.binance.us",
+        }
+    }
+
+    fn default_usd_quote(self) -> &'static str {
+        match self {
+            Exchange::Global => "USDT",
+            Exchange::Us => "USD",
+        }
+    }
+
+    /// Whether the venue has portfolio margin and UM futures.
+    pub fn has_portfolio_margin(self) -> bool {
+        self == Exchange::Global
+    }
 }
 
 #[derive(Debug, Parser)]
 #[command(name = "binance_aum_fetch")]
 #[command(about = "Fetches Binance data and calculates/display AUM")]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// TOML file of settings keyed by environment variable name; the command
+    /// line and environment take precedence
+    #[arg(long, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    /// `[profiles.<name>]` table of the config file to apply over its
+    /// top-level settings
+    #[arg(long, env = "PROFILE")]
+    pub profile: Option<String>,
+
+    /// Binance venue; `us` switches the default base URL and USD quote to
+    /// Binance.US and values spot balances only
+    #[arg(long, env = "EXCHANGE", value_enum, ignore_case = true, default_value_t = Exchange::Global)]
+    pub exchange: Exchange,
+
     #[arg(long, env = "BINANCE_API_KEY")]
     pub binance_api_key: Option<String>,
 
@@ -28,53 +418,506 @@ pub struct Cli {
     #[arg(long, env = "BINANCE_SPOT_ASSETS", default_value = DEFAULT_SPOT_ASSETS)]
     pub binance_spot_assets: String,
 
+    /// UM position symbols to leave out after selection, e.g. with `all`
+    #[arg(long, env = "EXCLUDE_POSITIONS")]
+    pub exclude_positions: Option<String>,
+
+    /// Spot assets to leave out after selection, e.g. with `all`
+    #[arg(long, env = "EXCLUDE_ASSETS")]
+    pub exclude_assets: Option<String>,
+
+    /// Skip UM positions; `/papi/v1/um/positionRisk` is not called
+    #[arg(long, env = "NO_UM")]
+    pub no_um: bool,
+
+    /// Skip spot balances; `/api/v3/account` is not called
+    #[arg(long, env = "NO_SPOT")]
+    pub no_spot: bool,
+
     #[arg(long, env = "QUOTE_CURRENCY", default_value = "USD")]
     pub quote_currency: String,
 
+    /// Spot symbol quote used for `QUOTE_CURRENCY=USD`; Binance.com has no
+    /// literal `BTCUSD` pair, Binance.US does. Defaults to `USDT`, or `USD`
+    /// with `--exchange us`.
+    #[arg(long, env = "USD_QUOTE")]
+    pub usd_quote: Option<String>,
+
+    /// Ticker price assets are valued at: the last trade, or the midpoint of
+    /// the best bid and ask (`/api/v3/ticker/bookTicker`).
+    #[arg(long, env = "PRICE_BASIS", value_enum, default_value_t = PriceBasis::Last)]
+    pub price_basis: PriceBasis,
+
+    /// Asset the AUM is denominated in; its wrapped form (`W<asset>`) is pegged 1:1.
+    #[arg(long, env = "BASE_ASSET", default_value = "BTC")]
+    pub base_asset: String,
+
+    /// Stablecoin the UM wallet settles in, e.g. `USDC`.
+    #[arg(long, env = "SETTLEMENT_ASSET", default_value = "USDT")]
+    pub settlement_asset: String,
+
     #[arg(long, env = "OUTPUT_FORMAT", value_enum, default_value_t = OutputFormat::Table)]
     pub output_format: OutputFormat,
 
-    #[arg(long, default_value_t = true)]
+    /// Output sinks, repeatable: `table`, `json[:path]`, `msgpack[:path]`,
+    /// `logfmt`, `prometheus:addr`. Overrides `--output-format` when given.
+    #[arg(long = "output", env = "OUTPUT", value_delimiter = ',')]
+    pub outputs: Vec<String>,
+
+    /// Suppress output-stream sinks; file and metrics sinks and logging stay on
+    #[arg(long, env = "QUIET")]
+    pub quiet: bool,
+
+    /// How a fatal error is printed to stderr.
+    #[arg(long, env = "ERROR_FORMAT", value_enum, default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
+
+    #[arg(long, env = "DECIMAL_PLACES")]
+    pub decimal_places: Option<u32>,
+
+    #[arg(long, env = "NUMBER_FORMAT", value_enum, default_value_t = NumberFormat::Plain)]
+    pub number_format: NumberFormat,
+
+    #[arg(long, env = "JSON_STYLE", value_enum, default_value_t = JsonStyle::Pretty)]
+    pub json_style: JsonStyle,
+
+    #[arg(long, env = "JSON_SHAPE", value_enum, default_value_t = JsonShape::Nested)]
+    pub json_shape: JsonShape,
+
+    #[arg(long, env = "DECIMAL_JSON", value_enum, default_value_t = DecimalJson::String)]
+    pub decimal_json: DecimalJson,
+
+    /// Stream that table, JSON, logfmt, summary and template output go to;
+    /// JSON written to a file is unaffected
+    #[arg(long, env = "OUTPUT_STREAM", value_enum, default_value_t = OutputStream::Stdout)]
+    pub output_stream: OutputStream,
+
+    /// Print a step-by-step derivation of each report's AUM instead of the
+    /// configured outputs
+    #[arg(long, env = "EXPLAIN")]
+    pub explain: bool,
+
+    /// Print only the symbol prices that fed each report, as JSON
+    #[arg(long, env = "PRICES_ONLY")]
+    pub prices_only: bool,
+
+    /// MiniJinja template rendered with the report as context, e.g.
+    /// `{{ timestamp }} {{ calculation.aum_base }}`
+    #[arg(long, env = "TEMPLATE")]
+    pub template: Option<String>,
+
+    /// Symbol prefixed to quote-currency values in the table, e.g. `$`
+    #[arg(long, env = "CURRENCY_SYMBOL")]
+    pub currency_symbol: Option<char>,
+
+    /// ANSI colors in table and summary output; `auto` honors `NO_COLOR` and
+    /// `CLICOLOR_FORCE`
+    #[arg(long, env = "COLOR", value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Same as `--color never`
+    #[arg(long, conflicts_with = "color")]
+    pub no_color: bool,
+
+    #[arg(
+        long,
+        env = "MISSING_PRICE_POLICY",
+        value_enum,
+        default_value_t = MissingPricePolicy::Fail
+    )]
+    pub missing_price_policy: MissingPricePolicy,
+
+    #[arg(
+        long,
+        env = "SORT_CONTRIBUTIONS",
+        value_enum,
+        default_value_t = ContributionSort::Value
+    )]
+    pub sort_contributions: ContributionSort,
+
+    /// Sort positions by symbol and balances by asset before valuing and
+    /// serializing, so successive reports diff cleanly
+    #[arg(
+        long,
+        env = "STABLE_ORDER",
+        default_value_t = true,
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    pub stable_order: bool,
+
+    #[arg(
+        long,
+        env = "SATOSHI_ROUNDING",
+        value_enum,
+        default_value_t = SatoshiRounding::Trunc
+    )]
+    pub satoshi_rounding: SatoshiRounding,
+
+    /// Exclude spot balances worth less than this many base-asset units
+    #[arg(long, alias = "dust-threshold-btc", env = "DUST_THRESHOLD")]
+    pub dust_threshold: Option<Decimal>,
+
+    /// Assets that may be priced through the `<asset>/USD` two-hop fallback
+    /// when no direct pair with the base asset exists
+    #[arg(long, env = "FALLBACK_ASSETS")]
+    pub fallback_assets: Option<String>,
+
+    /// Fail when more spot balances than this are fetched, e.g. with
+    /// `BINANCE_SPOT_ASSETS=all` on an account full of dust
+    #[arg(long, env = "MAX_SPOT_ASSETS", default_value_t = 200)]
+    pub max_spot_assets: usize,
+
+    /// Instead of failing past `--max-spot-assets`, warn and keep the most
+    /// valuable balances at current `USD_QUOTE` prices
+    #[arg(long, env = "TRUNCATE_SPOT_ASSETS")]
+    pub truncate_spot_assets: bool,
+
+    /// Price assets as another asset, e.g. `BETH=ETH,WBETH=ETH`
+    #[arg(long, env = "ASSET_ALIASES")]
+    pub asset_alias: Option<String>,
+
+    /// Percent taken off named spot assets for the net AUM, e.g. `LDO:20,ARB:10`
+    #[arg(long, env = "HAIRCUTS")]
+    pub haircut: Option<String>,
+
+    /// JSON or TOML file of `ASSET = base-to-asset price` entries, used
+    /// instead of Binance prices for those assets
+    #[arg(long, env = "PRICE_OVERRIDES")]
+    pub price_overrides: Option<PathBuf>,
+
+    /// Also value the AUM in each of these currencies, e.g. `BTC,USD,EUR`;
+    /// accepts the same codes as `--quote-currency`
+    #[arg(long, env = "REPORT_CURRENCIES")]
+    pub report_currencies: Option<String>,
+
+    /// Fixed base/USD price used instead of Binance's for the USD legs of
+    /// the AUM; every other price stays live
+    #[arg(long, alias = "btc-usd-override", env = "BASE_USD_OVERRIDE")]
+    pub base_usd_override: Option<Decimal>,
+
+    /// Renames Binance response fields before parsing, as
+    /// `received=expected` pairs, e.g. `uniMmrRatio=uniMMR`. Case-sensitive
+    #[arg(long, env = "FIELD_ALIASES")]
+    pub field_alias: Option<String>,
+
+    /// Include Simple Earn flexible and locked positions in spot balances
+    #[arg(long, env = "INCLUDE_EARN")]
+    pub include_earn: bool,
+
+    /// Report every configured spot asset, including ones the account does not hold
+    #[arg(long, env = "SHOW_ZERO_BALANCES")]
+    pub show_zero_balances: bool,
+
+    /// Keep and show each spot balance's free and locked parts; the AUM
+    /// still uses their sum
+    #[arg(long, env = "SPLIT_FREE_LOCKED")]
+    pub split_free_locked: bool,
+
+    /// Subtract cross-margin borrowed principal and interest from AUM
+    #[arg(long, env = "INCLUDE_MARGIN_LIABILITIES")]
+    pub include_margin_liabilities: bool,
+
+    /// Add the equity of a classic USDⓂ futures account (`/fapi`) to AUM
+    #[arg(long, env = "INCLUDE_CLASSIC_FUTURES")]
+    pub include_classic_futures: bool,
+
+    /// Attach the raw bodies of the four account endpoints to each report
+    /// under `raw`, for audit
+    #[arg(long, env = "INCLUDE_RAW")]
+    pub include_raw: bool,
+
+    /// Attach realized PnL, funding fees and commissions from
+    /// `/papi/v1/um/income`, booked since the previous report
+    #[arg(long, env = "INCLUDE_INCOME")]
+    pub include_income: bool,
+
+    /// Sum income from this RFC 3339 time instead of the previous report;
+    /// implies `--include-income`
+    #[arg(long, alias = "from", env = "INCOME_FROM")]
+    pub income_from: Option<DateTime<Utc>>,
+
+    /// End of the `--income-from` window; defaults to each report's timestamp
+    #[arg(long, alias = "to", env = "INCOME_TO", requires = "income_from")]
+    pub income_to: Option<DateTime<Utc>>,
+
+    #[arg(
+        long,
+        env = "BINANCE_FAPI_BASE_URL",
+        default_value = "https://fapi.binance.com"
+    )]
+    pub binance_fapi_base_url: String,
+
+    /// Add UM position notional (net of unrealized PnL) to the AUM total
+    #[arg(long, env = "INCLUDE_UM_NOTIONAL")]
+    pub include_um_notional: bool,
+
+    #[arg(
+        long,
+        env = "ONCE",
+        default_value_t = true,
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
     pub once: bool,
 
-    #[arg(long, default_value_t = 30)]
-    pub interval: u64,
+    /// Loop interval: bare seconds or a duration such as `90s`, `5m`, `1h`
+    #[arg(long, env = "INTERVAL", default_value = "30", value_parser = parse_duration)]
+    pub interval: Duration,
+
+    /// Floor for `--interval` in loop mode; shorter intervals are raised to
+    /// it with a warning. `0` disables the floor
+    #[arg(long, env = "MIN_INTERVAL", default_value = "5", value_parser = parse_duration)]
+    pub min_interval: Duration,
+
+    /// Seconds to wait before retrying while Binance reports maintenance
+    #[arg(long, env = "MAINTENANCE_BACKOFF", default_value_t = 300)]
+    pub maintenance_backoff: u64,
+
+    /// Which failed iterations end loop mode with a non-zero exit
+    #[arg(
+        long,
+        env = "LOOP_ERROR_POLICY",
+        value_enum,
+        default_value_t = LoopErrorPolicy::Smart
+    )]
+    pub loop_error_policy: LoopErrorPolicy,
+
+    /// Randomize each loop sleep by up to ± this percent of the interval
+    #[arg(
+        long,
+        env = "INTERVAL_JITTER",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u8).range(0..=100)
+    )]
+    pub interval_jitter: u8,
+
+    /// Wait a random 0..=N seconds before the first loop iteration
+    #[arg(long, env = "START_DELAY", default_value_t = 0)]
+    pub start_delay: u64,
+
+    /// Before the first fetch, ping Binance with backoff for up to N seconds
+    /// until it answers; 0 disables the wait
+    #[arg(long, env = "WAIT_FOR_API", default_value_t = 0)]
+    pub wait_for_api: u64,
+
+    /// Fail a report whose prices were observed further than this from its
+    /// balances, e.g. `60` or `5m`
+    #[arg(long, env = "MAX_PRICE_AGE", value_parser = parse_duration)]
+    pub max_price_age: Option<Duration>,
+
+    /// Warn when the base/USD price deviates more than this percent from its
+    /// 24h weighted average price
+    #[arg(long, env = "PRICE_SANITY_PCT")]
+    pub price_sanity_pct: Option<Decimal>,
 
-    #[arg(long, default_value_t = 10)]
-    pub timeout: u64,
+    /// Fail the report instead of warning when `--price-sanity-pct` trips
+    #[arg(long, env = "STRICT_PRICE_SANITY", requires = "price_sanity_pct")]
+    pub strict_price_sanity: bool,
+
+    /// In loop mode, trip when any symbol price moves more than this percent
+    /// since the previous iteration
+    #[arg(long, env = "MAX_PRICE_DEVIATION_BETWEEN_RUNS")]
+    pub max_price_deviation_between_runs: Option<Decimal>,
 
     #[arg(
         long,
-        env = "BINANCE_API_BASE_URL",
-        default_value = "https://api.binance.com"
+        env = "PRICE_DEVIATION_ACTION",
+        value_enum,
+        default_value_t = PriceDeviationAction::Skip
     )]
-    pub binance_api_base_url: String,
+    pub price_deviation_action: PriceDeviationAction,
+
+    /// Check each computed AUM against PM equity plus spot value, less
+    /// liabilities, and trip when it deviates by more than this percent
+    #[arg(long, env = "AUM_BOUNDS_TOLERANCE_PCT")]
+    pub aum_bounds_tolerance_pct: Option<Decimal>,
+
+    #[arg(long, env = "AUM_BOUNDS_ACTION", value_enum, default_value_t = AumBoundsAction::Warn)]
+    pub aum_bounds_action: AumBoundsAction,
+
+    /// Clock for signed-request timestamps; the Binance server-time offset is
+    /// applied on top after a `-1021` rejection
+    #[arg(long, env = "TIME_SOURCE", value_enum, default_value_t = TimeSourceKind::System)]
+    pub time_source: TimeSourceKind,
+
+    /// Request timeout: bare seconds or a duration such as `500ms`, `1m`
+    #[arg(long, env = "TIMEOUT", default_value = "10", value_parser = parse_duration)]
+    pub timeout: Duration,
+
+    /// User-Agent sent to Binance; defaults to `binance-aum-fetch/<version>`
+    #[arg(long, env = "USER_AGENT")]
+    pub user_agent: Option<String>,
+
+    /// Extra PEM root certificate to trust, e.g. a TLS-intercepting proxy's
+    #[arg(long, env = "CA_CERT")]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Disable TLS certificate verification (development only)
+    #[arg(long, env = "INSECURE_SKIP_TLS_VERIFY")]
+    pub insecure_skip_tls_verify: bool,
 
+    /// IP echo service (e.g. `https://api.ipify.org`) queried by `verify`
+    /// when Binance rejects the key, to show the egress IP to whitelist
+    #[arg(long, env = "EGRESS_IP_URL")]
+    pub egress_ip_url: Option<String>,
+
+    /// Request weight allowed per minute, used to back off the loop interval
+    #[arg(long, env = "WEIGHT_LIMIT", default_value_t = 6000)]
+    pub weight_limit: u32,
+
+    /// Extend the loop interval when used weight reaches this percent of the limit
+    #[arg(long, env = "WEIGHT_BACKOFF_PCT", default_value_t = 80)]
+    pub weight_backoff_pct: u8,
+
+    /// Client-side cap on Binance requests per second; requests over it wait
+    #[arg(long, env = "MAX_RPS")]
+    pub max_rps: Option<NonZeroU32>,
+
+    /// Defaults to `https://api.binance.com`, or `https://api.binance.us`
+    /// with `--exchange us`
+    #[arg(long, env = "BINANCE_API_BASE_URL")]
+    pub binance_api_base_url: Option<String>,
+
+    /// Unused with `--exchange us`
     #[arg(
         long,
         env = "BINANCE_PAPI_BASE_URL",
         default_value = "https://papi.binance.com"
     )]
     pub binance_papi_base_url: String,
+
+    /// Number of recent loop-mode reports to summarize (min/max/mean)
+    #[arg(long, env = "HISTORY_WINDOW")]
+    pub history_window: Option<usize>,
+
+    /// Persist the latest report here and load it at startup for deltas
+    #[arg(long, env = "STATE_FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Previously saved JSON report to compare every report against
+    #[arg(long, env = "BASELINE")]
+    pub baseline: Option<PathBuf>,
+
+    #[arg(long, env = "HEALTH_ADDR")]
+    pub health_addr: Option<SocketAddr>,
+
+    #[arg(long, env = "HEALTH_FAILURE_THRESHOLD", default_value_t = 3)]
+    pub health_failure_threshold: u32,
+
+    /// Push metrics to this Prometheus Pushgateway after every report
+    #[arg(long, env = "PUSHGATEWAY_URL")]
+    pub pushgateway_url: Option<String>,
+
+    #[arg(long, env = "PUSH_JOB", default_value = "binance_aum_fetch")]
+    pub push_job: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct AppConfig {
+    pub exchange: Exchange,
     pub api_key: String,
     pub api_secret: String,
-    pub um_positions: Vec<String>,
-    pub spot_assets: Vec<String>,
+    pub um_positions: Selection,
+    pub spot_assets: Selection,
+    pub exclude_positions: Vec<String>,
+    pub exclude_assets: Vec<String>,
     pub quote_currency: String,
-    pub output_format: OutputFormat,
+    pub usd_quote: String,
+    pub price_basis: PriceBasis,
+    pub base_asset: String,
+    pub settlement_asset: String,
+    pub sinks: Vec<Sink>,
+    pub quiet: bool,
+    pub decimal_places: Option<u32>,
+    pub number_format: NumberFormat,
+    pub json_style: JsonStyle,
+    pub json_shape: JsonShape,
+    pub decimal_json: DecimalJson,
+    pub output_stream: OutputStream,
+    pub template: Option<String>,
+    pub prices_only: bool,
+    pub explain: bool,
+    pub currency_symbol: Option<char>,
+    pub color: ColorChoice,
+    pub missing_price_policy: MissingPricePolicy,
+    pub sort_contributions: ContributionSort,
+    pub stable_order: bool,
+    pub max_spot_assets: usize,
+    pub truncate_spot_assets: bool,
+    pub include_um_notional: bool,
+    pub asset_aliases: HashMap<String, String>,
+    pub field_aliases: HashMap<String, String>,
+    pub haircuts: HashMap<String, Decimal>,
+    pub price_overrides: HashMap<String, Decimal>,
+    pub report_currencies: Vec<String>,
+    pub base_usd_override: Option<Decimal>,
+    pub dust_threshold: Option<Decimal>,
+    pub satoshi_rounding: SatoshiRounding,
+    pub fallback_assets: Vec<String>,
+    pub include_earn: bool,
+    pub show_zero_balances: bool,
+    pub split_free_locked: bool,
+    pub include_margin_liabilities: bool,
+    pub include_classic_futures: bool,
+    pub income: Option<IncomeWindow>,
+    pub include_raw: bool,
     pub once: bool,
     pub interval: Duration,
+    pub maintenance_backoff: Duration,
+    pub loop_error_policy: LoopErrorPolicy,
+    pub interval_jitter_pct: u8,
+    pub start_delay: Duration,
+    pub wait_for_api: Duration,
     pub timeout: Duration,
+    pub max_price_age: Option<Duration>,
+    pub price_sanity_pct: Option<Decimal>,
+    pub strict_price_sanity: bool,
+    pub max_price_deviation_between_runs: Option<Decimal>,
+    pub price_deviation_action: PriceDeviationAction,
+    pub aum_bounds_tolerance_pct: Option<Decimal>,
+    pub aum_bounds_action: AumBoundsAction,
+    pub time_source: TimeSourceKind,
+    pub user_agent: Option<String>,
+    pub ca_cert: Option<PathBuf>,
+    pub insecure_skip_tls_verify: bool,
+    pub egress_ip_url: Option<String>,
+    pub weight_limit: u32,
+    pub weight_backoff_pct: u8,
+    pub max_rps: Option<NonZeroU32>,
     pub api_base_url: String,
     pub papi_base_url: String,
+    pub fapi_base_url: String,
+    pub history_window: Option<usize>,
+    pub state_file: Option<PathBuf>,
+    pub baseline: Option<PathBuf>,
+    pub health_addr: Option<SocketAddr>,
+    pub health_failure_threshold: u32,
+    pub pushgateway_url: Option<String>,
+    pub push_job: String,
 }
 
 impl AppConfig {
+    /// Quote asset of the base/USD spot symbol: `usd_quote` for `USD` and
+    /// for the base asset itself, else the quote currency.
+    pub fn spot_quote(&self) -> &str {
+        if self.quote_currency == "USD" || self.quote_currency == self.base_asset {
+            &self.usd_quote
+        } else {
+            &self.quote_currency
+        }
+    }
+
     pub fn from_cli(cli: Cli) -> AppResult<Self> {
+        if cli.profile.is_some() && cli.config.is_none() {
+            return Err(AppError::InvalidConfig {
+                field: "PROFILE",
+                reason: "a profile requires --config".to_string(),
+            });
+        }
+
         let api_key = cli
             .binance_api_key
             .filter(|v| !v.trim().is_empty())
@@ -84,32 +927,298 @@ impl AppConfig {
             .filter(|v| !v.trim().is_empty())
             .ok_or(AppError::MissingConfig("BINANCE_API_SECRET"))?;
 
-        let um_positions = parse_csv_symbols(&cli.binance_um_positions, "BINANCE_UM_POSITIONS")?;
-        let spot_assets = parse_csv_symbols(&cli.binance_spot_assets, "BINANCE_SPOT_ASSETS")?;
+        let percents = [
+            (cli.price_sanity_pct, "PRICE_SANITY_PCT"),
+            (
+                cli.max_price_deviation_between_runs,
+                "MAX_PRICE_DEVIATION_BETWEEN_RUNS",
+            ),
+            (cli.aum_bounds_tolerance_pct, "AUM_BOUNDS_TOLERANCE_PCT"),
+        ];
+        for (pct, field) in percents {
+            if pct.is_some_and(|pct| pct <= Decimal::ZERO) {
+                return Err(AppError::InvalidConfig {
+                    field,
+                    reason: "must be greater than 0".to_string(),
+                });
+            }
+        }
+
+        if !cli.exchange.has_portfolio_margin() {
+            let unsupported = [
+                (cli.include_earn, "INCLUDE_EARN"),
+                (cli.include_margin_liabilities, "INCLUDE_MARGIN_LIABILITIES"),
+                (cli.include_classic_futures, "INCLUDE_CLASSIC_FUTURES"),
+                (
+                    cli.include_income || cli.income_from.is_some(),
+                    "INCLUDE_INCOME",
+                ),
+            ];
+            if let Some(&(_, field)) = unsupported.iter().find(|(enabled, _)| *enabled) {
+                return Err(AppError::InvalidConfig {
+                    field,
+                    reason: "not available on Binance.US".to_string(),
+                });
+            }
+        }
+
+        let income = match (cli.income_from, cli.income_to) {
+            (Some(from), Some(to)) if from >= to => {
+                return Err(AppError::InvalidConfig {
+                    field: "INCOME_TO",
+                    reason: "must be after INCOME_FROM".to_string(),
+                })
+            }
+            (Some(from), to) => Some(IncomeWindow::Range { from, to }),
+            (None, _) => cli.include_income.then_some(IncomeWindow::SincePrevious),
+        };
+
+        // Binance.US has no futures, so there are no UM positions to select.
+        let um_positions = if cli.no_um || !cli.exchange.has_portfolio_margin() {
+            Selection::Only(Vec::new())
+        } else {
+            parse_selection(&cli.binance_um_positions, "BINANCE_UM_POSITIONS")?
+        };
+        let spot_assets = if cli.no_spot {
+            Selection::Only(Vec::new())
+        } else {
+            parse_selection(&cli.binance_spot_assets, "BINANCE_SPOT_ASSETS")?
+        };
+        let exclude_positions = match cli.exclude_positions.as_deref().map(str::trim) {
+            None | Some("") => Vec::new(),
+            Some(raw) => parse_csv_symbols(raw, "EXCLUDE_POSITIONS")?,
+        };
+        let exclude_assets = match cli.exclude_assets.as_deref().map(str::trim) {
+            None | Some("") => Vec::new(),
+            Some(raw) => parse_csv_symbols(raw, "EXCLUDE_ASSETS")?,
+        };
+
+        let asset_aliases = cli
+            .asset_alias
+            .as_deref()
+            .map(|raw| parse_key_value_map(raw, "ASSET_ALIASES"))
+            .transpose()?
+            .unwrap_or_default();
+
+        let haircuts = cli
+            .haircut
+            .as_deref()
+            .map(parse_haircuts)
+            .transpose()?
+            .unwrap_or_default();
+
+        let price_overrides = cli
+            .price_overrides
+            .as_deref()
+            .map(load_price_overrides)
+            .transpose()?
+            .unwrap_or_default();
+
+        let field_aliases = cli
+            .field_alias
+            .as_deref()
+            .map(|raw| parse_key_value_pairs(raw, "FIELD_ALIASES"))
+            .transpose()?
+            .unwrap_or_default();
+
+        let fallback_assets = match cli.fallback_assets.as_deref().map(str::trim) {
+            None | Some("") => Vec::new(),
+            Some(raw) => parse_csv_symbols(raw, "FALLBACK_ASSETS")?,
+        };
+
+        let base_asset = normalize_symbol(&cli.base_asset);
+        let quote_currency =
+            normalize_quote_currency(&cli.quote_currency, &base_asset, "QUOTE_CURRENCY")?;
+        let report_currencies = match cli.report_currencies.as_deref().map(str::trim) {
+            None | Some("") => Vec::new(),
+            Some(raw) => parse_csv_symbols(raw, "REPORT_CURRENCIES")?
+                .iter()
+                .map(|currency| {
+                    normalize_quote_currency(currency, &base_asset, "REPORT_CURRENCIES")
+                })
+                .collect::<AppResult<_>>()?,
+        };
+        if cli
+            .base_usd_override
+            .is_some_and(|price| price <= Decimal::ZERO)
+        {
+            return Err(AppError::InvalidConfig {
+                field: "BASE_USD_OVERRIDE",
+                reason: "must be greater than 0".to_string(),
+            });
+        }
+
+        let interval = if !cli.once && cli.interval < cli.min_interval {
+            warn!(
+                interval = ?cli.interval,
+                min_interval = ?cli.min_interval,
+                "loop interval is below --min-interval; using the minimum"
+            );
+            cli.min_interval
+        } else {
+            cli.interval
+        };
+
+        let sinks = if cli.outputs.is_empty() {
+            vec![match cli.output_format {
+                OutputFormat::Table => Sink::Table,
+                OutputFormat::Json => Sink::Json(None),
+                OutputFormat::Logfmt => Sink::Logfmt,
+                OutputFormat::Template => Sink::Template,
+                OutputFormat::Summary => Sink::Summary,
+                OutputFormat::MessagePack => Sink::MessagePack(None),
+            }]
+        } else {
+            cli.outputs
+                .iter()
+                .map(|raw| parse_sink(raw))
+                .collect::<AppResult<_>>()?
+        };
+
+        match cli.template.as_deref() {
+            Some(template) => minijinja::Environment::new()
+                .template_from_str(template)
+                .map(|_| ())
+                .map_err(|err| AppError::InvalidConfig {
+                    field: "TEMPLATE",
+                    reason: err.to_string(),
+                })?,
+            None if sinks.contains(&Sink::Template) => {
+                return Err(AppError::MissingConfig("TEMPLATE"));
+            }
+            None => {}
+        }
 
         Ok(Self {
+            exchange: cli.exchange,
             api_key,
             api_secret,
             um_positions,
             spot_assets,
-            quote_currency: cli.quote_currency.trim().to_uppercase(),
-            output_format: cli.output_format,
+            exclude_positions,
+            exclude_assets,
+            quote_currency,
+            usd_quote: normalize_symbol(
+                cli.usd_quote
+                    .as_deref()
+                    .unwrap_or(cli.exchange.default_usd_quote()),
+            ),
+            price_basis: cli.price_basis,
+            base_asset,
+            settlement_asset: normalize_symbol(&cli.settlement_asset),
+            sinks,
+            quiet: cli.quiet,
+            decimal_places: cli.decimal_places,
+            number_format: cli.number_format,
+            json_style: cli.json_style,
+            json_shape: cli.json_shape,
+            decimal_json: cli.decimal_json,
+            output_stream: cli.output_stream,
+            template: cli.template,
+            prices_only: cli.prices_only,
+            explain: cli.explain,
+            currency_symbol: cli.currency_symbol,
+            color: if cli.no_color {
+                ColorChoice::Never
+            } else {
+                cli.color
+            },
+            missing_price_policy: cli.missing_price_policy,
+            sort_contributions: cli.sort_contributions,
+            stable_order: cli.stable_order,
+            max_spot_assets: cli.max_spot_assets,
+            truncate_spot_assets: cli.truncate_spot_assets,
+            include_um_notional: cli.include_um_notional,
+            asset_aliases,
+            field_aliases,
+            haircuts,
+            price_overrides,
+            report_currencies,
+            base_usd_override: cli.base_usd_override,
+            dust_threshold: cli.dust_threshold,
+            satoshi_rounding: cli.satoshi_rounding,
+            fallback_assets,
+            include_earn: cli.include_earn,
+            show_zero_balances: cli.show_zero_balances,
+            split_free_locked: cli.split_free_locked,
+            include_margin_liabilities: cli.include_margin_liabilities,
+            include_classic_futures: cli.include_classic_futures,
+            income,
+            include_raw: cli.include_raw,
             once: cli.once,
-            interval: Duration::from_secs(cli.interval),
-            timeout: Duration::from_secs(cli.timeout),
-            api_base_url: trim_base_url(&cli.binance_api_base_url),
+            interval,
+            maintenance_backoff: Duration::from_secs(cli.maintenance_backoff),
+            loop_error_policy: cli.loop_error_policy,
+            interval_jitter_pct: cli.interval_jitter,
+            start_delay: Duration::from_secs(cli.start_delay),
+            wait_for_api: Duration::from_secs(cli.wait_for_api),
+            timeout: cli.timeout,
+            max_price_age: cli.max_price_age,
+            price_sanity_pct: cli.price_sanity_pct,
+            strict_price_sanity: cli.strict_price_sanity,
+            max_price_deviation_between_runs: cli.max_price_deviation_between_runs,
+            price_deviation_action: cli.price_deviation_action,
+            aum_bounds_tolerance_pct: cli.aum_bounds_tolerance_pct,
+            aum_bounds_action: cli.aum_bounds_action,
+            time_source: cli.time_source,
+            user_agent: cli.user_agent.filter(|ua| !ua.trim().is_empty()),
+            ca_cert: cli.ca_cert,
+            insecure_skip_tls_verify: cli.insecure_skip_tls_verify,
+            egress_ip_url: cli.egress_ip_url.filter(|url| !url.trim().is_empty()),
+            weight_limit: cli.weight_limit,
+            weight_backoff_pct: cli.weight_backoff_pct,
+            max_rps: cli.max_rps,
+            api_base_url: trim_base_url(
+                cli.binance_api_base_url
+                    .as_deref()
+                    .unwrap_or(cli.exchange.default_api_base_url()),
+            ),
             papi_base_url: trim_base_url(&cli.binance_papi_base_url),
+            fapi_base_url: trim_base_url(&cli.binance_fapi_base_url),
+            history_window: cli.history_window.filter(|n| *n > 0),
+            state_file: cli.state_file,
+            baseline: cli.baseline,
+            health_addr: cli.health_addr,
+            health_failure_threshold: cli.health_failure_threshold.max(1),
+            pushgateway_url: cli.pushgateway_url.as_deref().map(trim_base_url),
+            push_job: cli.push_job,
         })
     }
 }
 
+/// `all` selects everything; an empty value or `none` selects nothing, so
+/// the corresponding endpoint is not fetched at all.
+fn parse_selection(raw: &str, field: &'static str) -> AppResult<Selection> {
+    let trimmed = raw.trim();
+    if trimmed.eq_ignore_ascii_case("all") {
+        return Ok(Selection::All);
+    }
+    if trimmed.is_empty() || trimmed.eq_ignore_ascii_case("none") {
+        return Ok(Selection::Only(Vec::new()));
+    }
+    parse_csv_symbols(raw, field).map(Selection::Only)
+}
+
+/// Splits on commas, semicolons and whitespace alike, so lists pasted from
+/// other tools parse the same as `A,B`.
 fn parse_csv_symbols(raw: &str, field: &'static str) -> AppResult<Vec<String>> {
-    let values: Vec<String> = raw
-        .split(',')
-        .map(|v| v.trim())
+    let mut values: Vec<String> = Vec::new();
+    let mut duplicates: Vec<String> = Vec::new();
+    for value in raw
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
         .filter(|v| !v.is_empty())
-        .map(|v| v.to_uppercase())
-        .collect();
+        .map(normalize_symbol)
+    {
+        if values.contains(&value) {
+            duplicates.push(value);
+        } else {
+            values.push(value);
+        }
+    }
+
+    if !duplicates.is_empty() {
+        warn!(field, duplicates = ?duplicates, "ignoring duplicate configured values");
+    }
 
     if values.is_empty() {
         return Err(AppError::InvalidConfig {
@@ -121,6 +1230,382 @@ fn parse_csv_symbols(raw: &str, field: &'static str) -> AppResult<Vec<String>> {
     Ok(values)
 }
 
+/// Rejects quote currencies without spot pairs. `USD` is accepted and
+/// translated to `--usd-quote` by the price provider; so is the base asset
+/// itself, whose USD legs still go through `--usd-quote`.
+fn normalize_quote_currency(raw: &str, base_asset: &str, field: &'static str) -> AppResult<String> {
+    let quote = normalize_symbol(raw);
+    if quote == "USD" || quote == base_asset || SUPPORTED_QUOTE_CURRENCIES.contains(&quote.as_str())
+    {
+        return Ok(quote);
+    }
+    Err(AppError::InvalidConfig {
+        field,
+        reason: format!(
+            "unsupported quote currency `{quote}`; expected USD or one of {}",
+            SUPPORTED_QUOTE_CURRENCIES.join(", ")
+        ),
+    })
+}
+
+/// Bare integers are seconds; anything else is a `humantime` duration.
+fn parse_duration(raw: &str) -> Result<Duration, String> {
+    let raw = raw.trim();
+    match raw.parse::<u64>() {
+        Ok(secs) => Ok(Duration::from_secs(secs)),
+        Err(_) => humantime::parse_duration(raw).map_err(|err| err.to_string()),
+    }
+}
+
+fn parse_sink(raw: &str) -> AppResult<Sink> {
+    let invalid = |reason: String| AppError::InvalidConfig {
+        field: "OUTPUT",
+        reason,
+    };
+    let (kind, target) = match raw.trim().split_once(':') {
+        Some((kind, target)) => (kind, Some(target.trim())),
+        None => (raw.trim(), None),
+    };
+    match (kind.to_ascii_lowercase().as_str(), target) {
+        ("table", None) => Ok(Sink::Table),
+        ("logfmt", None) => Ok(Sink::Logfmt),
+        ("template", None) => Ok(Sink::Template),
+        ("summary", None) => Ok(Sink::Summary),
+        ("json", None) => Ok(Sink::Json(None)),
+        ("json", Some(path)) if !path.is_empty() => Ok(Sink::Json(Some(PathBuf::from(path)))),
+        ("msgpack", None) => Ok(Sink::MessagePack(None)),
+        ("msgpack", Some(path)) if !path.is_empty() => {
+            Ok(Sink::MessagePack(Some(PathBuf::from(path))))
+        }
+        ("prometheus", Some(addr)) => addr
+            .parse()
+            .map(Sink::Prometheus)
+            .map_err(|_| invalid(format!("invalid prometheus address `{addr}`"))),
+        _ => Err(invalid(format!("unknown output sink `{raw}`"))),
+    }
+}
+
+/// Parses `KEY=VALUE,KEY=VALUE` into a map of normalized symbols.
+fn parse_key_value_map(raw: &str, field: &'static str) -> AppResult<HashMap<String, String>> {
+    Ok(parse_key_value_pairs(raw, field)?
+        .into_iter()
+        .map(|(key, value)| (normalize_symbol(&key), normalize_symbol(&value)))
+        .collect())
+}
+
+/// Parses `ASSET:PCT,...` into asset → percent in `0..=100`.
+fn parse_haircuts(raw: &str) -> AppResult<HashMap<String, Decimal>> {
+    let invalid = |reason: String| AppError::InvalidConfig {
+        field: "HAIRCUTS",
+        reason,
+    };
+    let mut haircuts = HashMap::new();
+    for entry in raw.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let (asset, pct) = entry
+            .split_once(':')
+            .map(|(a, p)| (a.trim(), p.trim().trim_end_matches('%')))
+            .filter(|(a, _)| !a.is_empty())
+            .ok_or_else(|| invalid(format!("expected ASSET:PCT, got `{entry}`")))?;
+        let pct = Decimal::from_str(pct)
+            .ok()
+            .filter(|p| (Decimal::ZERO..=Decimal::ONE_HUNDRED).contains(p))
+            .ok_or_else(|| invalid(format!("`{entry}`: percent must be 0 to 100")))?;
+        haircuts.insert(normalize_symbol(asset), pct);
+    }
+    Ok(haircuts)
+}
+
+/// Reads an asset → base-to-asset price map from a TOML file (by extension)
+/// or a JSON object. Prices may be numbers or decimal strings.
+fn load_price_overrides(path: &Path) -> AppResult<HashMap<String, Decimal>> {
+    let invalid = |reason: String| AppError::InvalidConfig {
+        field: "PRICE_OVERRIDES",
+        reason: format!("{}: {reason}", path.display()),
+    };
+    let raw = std::fs::read_to_string(path)?;
+    let entries: Vec<(String, String)> = if path.extension().is_some_and(|ext| ext == "toml") {
+        let table: toml::Table = raw
+            .parse()
+            .map_err(|err: toml::de::Error| invalid(err.to_string()))?;
+        table
+            .into_iter()
+            .map(|(asset, value)| match value {
+                toml::Value::String(s) => Ok((asset, s)),
+                toml::Value::Integer(i) => Ok((asset, i.to_string())),
+                toml::Value::Float(f) => Ok((asset, f.to_string())),
+                _ => Err(invalid(format!("`{asset}` must be a number or string"))),
+            })
+            .collect::<AppResult<_>>()?
+    } else {
+        let map: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&raw).map_err(|err| invalid(err.to_string()))?;
+        map.into_iter()
+            .map(|(asset, value)| match value {
+                serde_json::Value::String(s) => Ok((asset, s)),
+                serde_json::Value::Number(n) => Ok((asset, n.to_string())),
+                _ => Err(invalid(format!("`{asset}` must be a number or string"))),
+            })
+            .collect::<AppResult<_>>()?
+    };
+
+    entries
+        .into_iter()
+        .map(|(asset, price)| {
+            let price = Decimal::from_str(price.trim())
+                .or_else(|_| Decimal::from_scientific(price.trim()))
+                .ok()
+                .filter(|p| *p > Decimal::ZERO)
+                .ok_or_else(|| invalid(format!("`{asset}`: price must be a positive number")))?;
+            Ok((normalize_symbol(&asset), price))
+        })
+        .collect()
+}
+
+/// Parses `KEY=VALUE,...`, keeping the case of keys and values.
+fn parse_key_value_pairs(raw: &str, field: &'static str) -> AppResult<HashMap<String, String>> {
+    let mut map = HashMap::new();
+    for entry in raw.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        let (key, value) = entry
+            .split_once('=')
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .filter(|(k, v)| !k.is_empty() && !v.is_empty())
+            .ok_or_else(|| AppError::InvalidConfig {
+                field,
+                reason: format!("expected KEY=VALUE, got `{entry}`"),
+            })?;
+        map.insert(key.to_string(), value.to_string());
+    }
+    Ok(map)
+}
+
 fn trim_base_url(url: &str) -> String {
     url.trim().trim_end_matches('/').to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn auto_color_follows_env_conventions() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |key: &str| {
+                vars.iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        assert!(ColorChoice::Auto.enabled_with(true, env(&[])));
+        assert!(!ColorChoice::Auto.enabled_with(false, env(&[])));
+        assert!(!ColorChoice::Auto.enabled_with(true, env(&[("NO_COLOR", "1")])));
+        assert!(ColorChoice::Auto.enabled_with(true, env(&[("NO_COLOR", "")])));
+        assert!(ColorChoice::Auto.enabled_with(false, env(&[("CLICOLOR_FORCE", "1")])));
+        assert!(!ColorChoice::Auto.enabled_with(false, env(&[("CLICOLOR_FORCE", "0")])));
+        assert!(ColorChoice::Always.enabled_with(false, env(&[("NO_COLOR", "1")])));
+        assert!(!ColorChoice::Never.enabled_with(true, env(&[("CLICOLOR_FORCE", "1")])));
+    }
+
+    #[test]
+    fn csv_symbols_are_normalized_and_deduplicated() {
+        let out = parse_csv_symbols("btc, BTC,eth ,BTC", "FIELD").expect("list should parse");
+        assert_eq!(out, vec!["BTC".to_string(), "ETH".to_string()]);
+    }
+
+    #[test]
+    fn csv_symbols_accept_semicolons_and_whitespace() {
+        let expected = vec!["BTCUSDT".to_string(), "ETHUSDT".to_string()];
+        for raw in [
+            "BTCUSDT ETHUSDT",
+            "BTCUSDT;ETHUSDT",
+            " btcusdt;\tETHUSDT,\nbtcusdt ",
+        ] {
+            assert_eq!(
+                parse_csv_symbols(raw, "FIELD").unwrap(),
+                expected,
+                "{raw:?}"
+            );
+        }
+        assert!(parse_csv_symbols(" ; , ", "FIELD").is_err());
+    }
+
+    #[test]
+    fn quote_currency_is_validated() {
+        assert_eq!(
+            normalize_quote_currency(" usd ", "BTC", "QUOTE_CURRENCY").unwrap(),
+            "USD"
+        );
+        assert_eq!(
+            normalize_quote_currency("usdc", "BTC", "QUOTE_CURRENCY").unwrap(),
+            "USDC"
+        );
+        assert_eq!(
+            normalize_quote_currency("btc", "BTC", "QUOTE_CURRENCY").unwrap(),
+            "BTC"
+        );
+        let err =
+            normalize_quote_currency("USDD", "BTC", "QUOTE_CURRENCY").expect_err("typo must fail");
+        assert!(matches!(
+            err,
+            AppError::InvalidConfig {
+                field: "QUOTE_CURRENCY",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn loop_interval_is_raised_to_the_floor() {
+        let config = |args: &[&str]| {
+            let base = [
+                "binance_aum_fetch",
+                "--binance-api-key=key",
+                "--binance-api-secret=secret",
+            ];
+            AppConfig::from_cli(Cli::parse_from(base.iter().chain(args))).unwrap()
+        };
+        let secs = Duration::from_secs;
+        assert_eq!(config(&["--once=false", "--interval=0"]).interval, secs(5));
+        assert_eq!(
+            config(&["--once=false", "--interval=1", "--min-interval=0"]).interval,
+            secs(1)
+        );
+        assert_eq!(
+            config(&["--once=false", "--interval=1m"]).interval,
+            secs(60)
+        );
+        assert_eq!(config(&["--once", "--interval=0"]).interval, secs(0));
+    }
+
+    #[test]
+    fn us_exchange_defaults_to_binance_us_spot_only() {
+        let cli = Cli::parse_from([
+            "binance_aum_fetch",
+            "--binance-api-key=key",
+            "--binance-api-secret=secret",
+            "--exchange=us",
+        ]);
+        let config = AppConfig::from_cli(cli).unwrap();
+        assert_eq!(config.api_base_url, "https://api.binance.us");
+        assert_eq!(config.usd_quote, "USD");
+        assert!(config.um_positions.is_empty());
+
+        let cli = Cli::parse_from([
+            "binance_aum_fetch",
+            "--binance-api-key=key",
+            "--binance-api-secret=secret",
+            "--exchange=us",
+            "--include-earn",
+        ]);
+        let err = AppConfig::from_cli(cli).expect_err("earn is not on Binance.US");
+        assert!(matches!(
+            err,
+            AppError::InvalidConfig {
+                field: "INCLUDE_EARN",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn configured_assets_are_normalized() {
+        let aliases = parse_key_value_map(" beth = eth ,wbeth=Eth", "ASSET_ALIASES").unwrap();
+        assert_eq!(aliases.get("BETH").map(String::as_str), Some("ETH"));
+        assert_eq!(aliases.get("WBETH").map(String::as_str), Some("ETH"));
+        let haircuts = parse_haircuts(" ldo :20").unwrap();
+        assert_eq!(haircuts.get("LDO"), Some(&Decimal::from(20)));
+    }
+
+    #[test]
+    fn price_overrides_load_from_json_and_toml() {
+        let dir = std::env::temp_dir().join(format!("aum-overrides-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+
+        let json = dir.join("overrides.json");
+        std::fs::write(&json, r#"{"otc": 2000, "LOCKED": "12.5"}"#).unwrap();
+        let toml = dir.join("overrides.toml");
+        std::fs::write(&toml, "otc = 2000\nLOCKED = \"12.5\"\n").unwrap();
+        let expected = HashMap::from([
+            ("OTC".to_string(), Decimal::from(2000)),
+            ("LOCKED".to_string(), Decimal::new(125, 1)),
+        ]);
+        assert_eq!(load_price_overrides(&json).unwrap(), expected);
+        assert_eq!(load_price_overrides(&toml).unwrap(), expected);
+
+        std::fs::write(&json, r#"{" otc ": 2000, "LOCKED": "12.5"}"#).unwrap();
+        assert_eq!(load_price_overrides(&json).unwrap(), expected);
+
+        std::fs::write(&json, r#"{"OTC": 0}"#).unwrap();
+        assert!(load_price_overrides(&json).is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn durations_accept_bare_seconds_and_units() {
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h 30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert!(parse_duration("soon").is_err());
+    }
+
+    #[test]
+    fn output_sinks_parse_targets() {
+        assert_eq!(parse_sink("table").unwrap(), Sink::Table);
+        assert_eq!(parse_sink("json").unwrap(), Sink::Json(None));
+        assert_eq!(parse_sink("summary").unwrap(), Sink::Summary);
+        assert_eq!(
+            parse_sink("json:report.json").unwrap(),
+            Sink::Json(Some(PathBuf::from("report.json")))
+        );
+        assert_eq!(
+            parse_sink("msgpack:report.msgpack").unwrap(),
+            Sink::MessagePack(Some(PathBuf::from("report.msgpack")))
+        );
+        assert_eq!(
+            parse_sink("prometheus:0.0.0.0:9100").unwrap(),
+            Sink::Prometheus("0.0.0.0:9100".parse().unwrap())
+        );
+        assert!(parse_sink("prometheus").is_err());
+        assert!(parse_sink("table:out.txt").is_err());
+        assert!(parse_sink("csv").is_err());
+    }
+
+    #[test]
+    fn csv_symbols_reject_empty_list() {
+        let err = parse_csv_symbols(" , ,", "FIELD").expect_err("empty list must fail");
+        assert!(matches!(
+            err,
+            AppError::InvalidConfig { field: "FIELD", .. }
+        ));
+    }
+
+    #[test]
+    fn all_sentinel_selects_everything() {
+        assert_eq!(
+            parse_selection(" All ", "FIELD").expect("sentinel should parse"),
+            Selection::All
+        );
+        assert!(parse_selection(" none ", "FIELD")
+            .expect("none should parse")
+            .is_empty());
+        assert!(parse_selection("", "FIELD")
+            .expect("empty should parse")
+            .is_empty());
+        assert_eq!(
+            parse_selection("btc,eth", "FIELD").expect("list should parse"),
+            Selection::Only(vec!["BTC".to_string(), "ETH".to_string()])
+        );
+    }
+
+    #[test]
+    fn key_value_map_is_uppercased_and_validated() {
+        let map = parse_key_value_map("beth=eth, WBETH = ETH", "FIELD").expect("map should parse");
+        assert_eq!(map.get("BETH").map(String::as_str), Some("ETH"));
+        assert_eq!(map.get("WBETH").map(String::as_str), Some("ETH"));
+
+        assert!(parse_key_value_map("BETH", "FIELD").is_err());
+        assert!(parse_key_value_map("BETH=", "FIELD").is_err());
+    }
+}
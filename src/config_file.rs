@@ -0,0 +1,177 @@
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+use clap::CommandFactory;
+use toml::{Table, Value};
+
+use crate::config::Cli;
+use crate::error::{AppError, AppResult};
+
+/// Reads a TOML config file and exports its settings as environment
+/// variables, so they slot in below the command line and the real
+/// environment but above `.env` and built-in defaults.
+///
+/// Keys are environment variable names, matched case-insensitively. Top-level
+/// keys apply to every run; a `[profiles.<name>]` table selected with
+/// `profile` overrides them. Variables named in `process_env`, the ones set
+/// before `.env` was loaded, are left untouched; anything else, including a
+/// value from `.env`, is overwritten.
+///
+/// Must be called before any other thread is started.
+pub fn export(
+    path: &Path,
+    profile: Option<&str>,
+    process_env: &HashSet<OsString>,
+) -> AppResult<()> {
+    let raw = std::fs::read_to_string(path)?;
+    for (key, value) in settings(&raw, profile)? {
+        if !process_env.contains(OsStr::new(&key)) {
+            std::env::set_var(key, value);
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `raw` to `(ENV_NAME, value)` pairs with the profile merged over
+/// the top-level settings.
+fn settings(raw: &str, profile: Option<&str>) -> AppResult<Vec<(String, String)>> {
+    let mut table: Table = raw
+        .parse()
+        .map_err(|err: toml::de::Error| invalid(err.to_string()))?;
+    let profiles = match table.remove("profiles") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => return Err(invalid("`profiles` must be a table".to_string())),
+        None => Table::new(),
+    };
+
+    if let Some(name) = profile {
+        match profiles.get(name) {
+            Some(Value::Table(overrides)) => {
+                for (key, value) in overrides {
+                    table.insert(key.clone(), value.clone());
+                }
+            }
+            Some(_) => return Err(invalid(format!("profile `{name}` must be a table"))),
+            None => {
+                let known: Vec<_> = profiles.keys().map(String::as_str).collect();
+                return Err(AppError::InvalidConfig {
+                    field: "PROFILE",
+                    reason: format!("unknown profile `{name}`; defined: {}", known.join(", ")),
+                });
+            }
+        }
+    }
+
+    let known = env_names();
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            let name = key.to_uppercase();
+            if !known.contains(&name) {
+                return Err(invalid(format!(
+                    "unknown setting `{key}`; keys must be the environment variable of an option"
+                )));
+            }
+            Ok((name, env_value(&key, value)?))
+        })
+        .collect()
+}
+
+/// Environment variables the command line reads, minus the ones that choose
+/// the config file itself.
+fn env_names() -> HashSet<String> {
+    Cli::command()
+        .get_arguments()
+        .filter_map(|arg| arg.get_env())
+        .filter_map(|name| name.to_str())
+        .filter(|name| !matches!(*name, "CONFIG_FILE" | "PROFILE"))
+        .map(str::to_string)
+        .collect()
+}
+
+fn env_value(key: &str, value: Value) -> AppResult<String> {
+    match value {
+        Value::String(s) => Ok(s),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        Value::Array(items) => items
+            .into_iter()
+            .map(|item| env_value(key, item))
+            .collect::<AppResult<Vec<_>>>()
+            .map(|items| items.join(",")),
+        Value::Datetime(_) | Value::Table(_) => Err(invalid(format!(
+            "`{key}` must be a string, number, boolean or array"
+        ))),
+    }
+}
+
+fn invalid(reason: String) -> AppError {
+    AppError::InvalidConfig {
+        field: "CONFIG_FILE",
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const FILE: &str = r#"
+        binance_um_positions = ["BTCUSDT", "ETHUSDT"]
+        quote_currency = "USDT"
+
+        [profiles.staging]
+        binance_um_positions = "BTCUSDT"
+        binance_api_base_url = "https://testnet.binance.vision"
+    "#;
+
+    #[test]
+    fn profile_overrides_top_level_settings() {
+        let mut base = settings(FILE, None).unwrap();
+        base.sort();
+        assert_eq!(
+            base,
+            vec![
+                (
+                    "BINANCE_UM_POSITIONS".to_string(),
+                    "BTCUSDT,ETHUSDT".to_string()
+                ),
+                ("QUOTE_CURRENCY".to_string(), "USDT".to_string()),
+            ]
+        );
+
+        let mut staging = settings(FILE, Some("staging")).unwrap();
+        staging.sort();
+        assert_eq!(
+            staging,
+            vec![
+                (
+                    "BINANCE_API_BASE_URL".to_string(),
+                    "https://testnet.binance.vision".to_string()
+                ),
+                ("BINANCE_UM_POSITIONS".to_string(), "BTCUSDT".to_string()),
+                ("QUOTE_CURRENCY".to_string(), "USDT".to_string()),
+            ]
+        );
+
+        let err = settings(FILE, Some("production")).expect_err("unknown profile");
+        assert!(err.to_string().contains("defined: staging"));
+    }
+
+    #[test]
+    fn keys_must_name_an_option_environment_variable() {
+        assert!(settings("interval = \"5m\"\ncolor = \"never\"", None).is_ok());
+        for raw in [
+            "intervall = 5",
+            "profile = \"staging\"",
+            "[profiles.x]\nhome = \"/\"",
+        ] {
+            let profile = raw.contains("profiles").then_some("x");
+            let err = settings(raw, profile).expect_err(raw);
+            assert!(err.to_string().contains("unknown setting"), "{err}");
+        }
+    }
+}
@@ -1,3 +1,7 @@
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -11,21 +15,66 @@ pub enum AppError {
     #[error("http request failed: {0}")]
     Http(#[from] reqwest::Error),
 
-    #[error("failed to parse decimal from `{field}` value `{value}`")]
-    DecimalParse { field: &'static str, value: String },
+    #[error(
+        "failed to parse {field} `{value}`{} from {endpoint}",
+        subject_suffix(.subject)
+    )]
+    DecimalParse {
+        field: &'static str,
+        value: String,
+        subject: Option<String>,
+        endpoint: &'static str,
+    },
 
     #[error("failed to decode json payload: {0}")]
     Json(#[from] serde_json::Error),
 
+    #[error("failed to encode MessagePack: {0}")]
+    MessagePack(#[from] rmp_serde::encode::Error),
+
     #[error("binance api returned error status {status}: {body}")]
     BinanceApi { status: u16, body: String },
 
+    #[error("unexpected response shape from {endpoint}: {reason}; body: {body}")]
+    UnexpectedResponse {
+        endpoint: &'static str,
+        reason: String,
+        body: String,
+    },
+
     #[error("binance api error {code}: {msg}")]
     BinanceApiMessage { code: i64, msg: String },
 
+    #[error(
+        "{endpoint} rejected the API key (binance error {code}: {msg}); \
+         enable {permission} on the key, check its IP whitelist, or drop \
+         the assets that need it"
+    )]
+    MissingPermission {
+        permission: &'static str,
+        endpoint: &'static str,
+        code: i64,
+        msg: String,
+    },
+
+    #[error("rate limited by binance: {msg}")]
+    RateLimited {
+        retry_after: Option<Duration>,
+        msg: String,
+    },
+
+    #[error("binance is under maintenance: {msg}")]
+    Maintenance { msg: String },
+
+    #[error("pushgateway returned error status {status}: {body}")]
+    Pushgateway { status: u16, body: String },
+
     #[error("signature generation failed")]
     Signature,
 
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
     #[error("time error: {0}")]
     Time(#[from] std::time::SystemTimeError),
 
@@ -34,6 +83,199 @@ pub enum AppError {
 
     #[error("negative aum computed: {0}")]
     NegativeAum(String),
+
+    #[error("arithmetic overflow while computing {context}")]
+    Arithmetic { context: &'static str },
+
+    #[error(
+        "prices were observed {}s from the balances, above the {}s bound",
+        .age.as_secs(),
+        .max.as_secs()
+    )]
+    StalePrices { age: Duration, max: Duration },
+
+    #[error(
+        "{symbol} price {price} is {deviation_pct}% away from its 24h average {average}, \
+         above the {max_pct}% bound"
+    )]
+    PriceAnomaly {
+        symbol: String,
+        price: Decimal,
+        average: Decimal,
+        deviation_pct: Decimal,
+        max_pct: Decimal,
+    },
+
+    #[error(
+        "{symbol} moved {deviation_pct}% since the previous run ({previous} to {price}), \
+         above the {max_pct}% bound"
+    )]
+    PriceJump {
+        symbol: String,
+        previous: Decimal,
+        price: Decimal,
+        deviation_pct: Decimal,
+        max_pct: Decimal,
+    },
+
+    #[error(
+        "AUM of {aum_usd} USD deviates {deviation_pct}% from the {expected_usd} USD its \
+         parts add up to, above the {max_pct}% tolerance"
+    )]
+    AumOutOfBounds {
+        aum_usd: Decimal,
+        expected_usd: Decimal,
+        deviation_pct: Decimal,
+        max_pct: Decimal,
+    },
+
+    #[error(
+        "{count} spot balances fetched, above --max-spot-assets {max}; raise the limit, \
+         narrow BINANCE_SPOT_ASSETS or pass --truncate-spot-assets"
+    )]
+    TooManySpotAssets { count: usize, max: usize },
+
+    /// Binance has not published the snapshot (yet), or it is older than
+    /// the 30 days it keeps.
+    #[error("no {kind} account snapshot for {date}; Binance keeps the last 30 days")]
+    NoSnapshot { kind: &'static str, date: NaiveDate },
+
+    #[error("{0} verification check(s) failed")]
+    VerificationFailed(usize),
+}
+
+impl AppError {
+    /// Stable discriminant for machine-readable error output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::MissingConfig(_) => "missing_config",
+            AppError::InvalidConfig { .. } => "invalid_config",
+            AppError::Http(_) => "http",
+            AppError::DecimalParse { .. } => "decimal_parse",
+            AppError::Json(_) => "json",
+            AppError::MessagePack(_) => "msgpack",
+            AppError::BinanceApi { .. } => "binance_api",
+            AppError::UnexpectedResponse { .. } => "unexpected_response",
+            AppError::BinanceApiMessage { .. } => "binance_api_message",
+            AppError::MissingPermission { .. } => "missing_permission",
+            AppError::RateLimited { .. } => "rate_limited",
+            AppError::Maintenance { .. } => "maintenance",
+            AppError::Pushgateway { .. } => "pushgateway",
+            AppError::Signature => "signature",
+            AppError::Io(_) => "io",
+            AppError::Time(_) => "time",
+            AppError::MissingPrice(_) => "missing_price",
+            AppError::NegativeAum(_) => "negative_aum",
+            AppError::Arithmetic { .. } => "arithmetic",
+            AppError::StalePrices { .. } => "stale_prices",
+            AppError::PriceAnomaly { .. } => "price_anomaly",
+            AppError::PriceJump { .. } => "price_jump",
+            AppError::AumOutOfBounds { .. } => "aum_out_of_bounds",
+            AppError::TooManySpotAssets { .. } => "too_many_spot_assets",
+            AppError::NoSnapshot { .. } => "no_snapshot",
+            AppError::VerificationFailed(_) => "verification_failed",
+        }
+    }
+
+    /// Binance error code or HTTP status, when the error carries one.
+    pub fn code(&self) -> Option<i64> {
+        match self {
+            AppError::BinanceApiMessage { code, .. } | AppError::MissingPermission { code, .. } => {
+                Some(*code)
+            }
+            AppError::BinanceApi { status, .. } => Some(i64::from(*status)),
+            _ => None,
+        }
+    }
+
+    /// `{"error": {"kind", "message", "code"}}` for `--error-format json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": {
+                "kind": self.kind(),
+                "message": self.to_string(),
+                "code": self.code(),
+            }
+        })
+    }
+
+    /// Errors a retry cannot fix: bad configuration, or credentials Binance
+    /// rejects (`-2014`/`-2015` invalid key, `-1022` bad signature).
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            AppError::MissingConfig(_)
+            | AppError::InvalidConfig { .. }
+            | AppError::MissingPermission { .. }
+            | AppError::Signature => true,
+            AppError::BinanceApiMessage { code, .. } => matches!(code, -2014 | -2015 | -1022),
+            _ => false,
+        }
+    }
+
+    /// Actionable explanation for well-known Binance error codes.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            AppError::BinanceApiMessage {
+                code: -2014 | -2015,
+                ..
+            } => Some("invalid API key, or request IP not whitelisted for this key"),
+            _ => None,
+        }
+    }
 }
 
 pub type AppResult<T> = Result<T, AppError>;
+
+fn subject_suffix(subject: &Option<String>) -> String {
+    subject
+        .as_ref()
+        .map(|s| format!(" for {s}"))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_error_carries_kind_and_code() {
+        let err = AppError::BinanceApiMessage {
+            code: -2015,
+            msg: "Invalid API-key".to_string(),
+        };
+        let json = err.to_json();
+        assert_eq!(json["error"]["kind"], "binance_api_message");
+        assert_eq!(json["error"]["code"], -2015);
+        assert_eq!(
+            json["error"]["message"],
+            "binance api error -2015: Invalid API-key"
+        );
+
+        let json = AppError::MissingConfig("BINANCE_API_KEY").to_json();
+        assert_eq!(json["error"]["kind"], "missing_config");
+        assert!(json["error"]["code"].is_null());
+    }
+
+    #[test]
+    fn rejected_credentials_are_permanent() {
+        let api = |code| AppError::BinanceApiMessage {
+            code,
+            msg: String::new(),
+        };
+        assert!(api(-2015).is_permanent());
+        assert!(api(-1022).is_permanent());
+        assert!(!api(-1003).is_permanent());
+        assert!(!AppError::Maintenance { msg: String::new() }.is_permanent());
+    }
+
+    #[test]
+    fn missing_snapshot_is_not_a_config_error() {
+        let err = AppError::NoSnapshot {
+            kind: "SPOT",
+            date: NaiveDate::from_ymd_opt(2024, 6, 30).unwrap(),
+        };
+        assert_eq!(err.kind(), "no_snapshot");
+        // Today's snapshot may still be published, so a retry can succeed.
+        assert!(!err.is_permanent());
+    }
+}
@@ -0,0 +1,122 @@
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+use crate::error::AppResult;
+use crate::state::{ReportState, SharedReportState};
+
+pub async fn serve(
+    addr: SocketAddr,
+    state: SharedReportState,
+    failure_threshold: u32,
+) -> AppResult<()> {
+    serve_with(addr, state, move |path, state| {
+        let (status, body) = route(path, state, failure_threshold);
+        (status, body.to_string())
+    })
+    .await
+}
+
+/// Minimal HTTP/1.1 server answering each request from the current report
+/// state; shared by the health and metrics endpoints.
+pub(crate) async fn serve_with<F>(
+    addr: SocketAddr,
+    state: SharedReportState,
+    route: F,
+) -> AppResult<()>
+where
+    F: Fn(&str, &ReportState) -> (u16, String) + Copy + Send + 'static,
+{
+    let listener = TcpListener::bind(addr).await?;
+    info!(%addr, "http endpoint listening");
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, &state, route).await {
+                debug!(%peer, error = %err, "http connection failed");
+            }
+        });
+    }
+}
+
+async fn handle_connection<F>(
+    mut stream: TcpStream,
+    state: &SharedReportState,
+    route: F,
+) -> std::io::Result<()>
+where
+    F: Fn(&str, &ReportState) -> (u16, String),
+{
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/");
+
+    let (status, body) = {
+        let state = state.read().unwrap_or_else(|e| e.into_inner());
+        route(path, &state)
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        reason_phrase(status),
+        body.len(),
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn route(path: &str, state: &ReportState, failure_threshold: u32) -> (u16, &'static str) {
+    match path {
+        "/healthz" if state.has_succeeded => (200, "ok"),
+        "/healthz" => (503, "no successful report yet"),
+        "/readyz" if !state.has_succeeded => (503, "no successful report yet"),
+        "/readyz" if state.consecutive_failures >= failure_threshold => {
+            (503, "recent fetches failed")
+        }
+        "/readyz" => (200, "ok"),
+        _ => (404, "not found"),
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Service Unavailable",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_follow_report_state() {
+        let mut state = ReportState::default();
+        assert_eq!(route("/healthz", &state, 3).0, 503);
+        assert_eq!(route("/readyz", &state, 3).0, 503);
+
+        state.has_succeeded = true;
+        assert_eq!(route("/healthz", &state, 3).0, 200);
+        assert_eq!(route("/readyz", &state, 3).0, 200);
+
+        state.record_failure();
+        state.record_failure();
+        assert_eq!(route("/readyz", &state, 3).0, 200);
+
+        state.record_failure();
+        assert_eq!(route("/healthz", &state, 3).0, 200);
+        assert_eq!(route("/readyz", &state, 3).0, 503);
+
+        assert_eq!(route("/metrics", &state, 3).0, 404);
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+
+use rust_decimal::Decimal;
+
+use crate::models::{AumReport, HistorySummary, WindowStats};
+
+/// In-memory window over the most recent reports of a loop-mode run.
+#[derive(Debug, Clone)]
+pub struct ReportHistory {
+    window: usize,
+    samples: VecDeque<Sample>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    aum_base: Decimal,
+    unimmr: Decimal,
+}
+
+impl ReportHistory {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            samples: VecDeque::with_capacity(window.max(1)),
+        }
+    }
+
+    pub fn push(&mut self, report: &AumReport) {
+        self.push_sample(Sample {
+            aum_base: report.calculation.aum_base_18dp.value(),
+            unimmr: report.data.unimmr,
+        });
+    }
+
+    fn push_sample(&mut self, sample: Sample) {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn summary(&self) -> Option<HistorySummary> {
+        Some(HistorySummary {
+            samples: self.samples.len(),
+            aum_base: stats(self.samples.iter().map(|s| s.aum_base))?,
+            unimmr: stats(self.samples.iter().map(|s| s.unimmr))?,
+        })
+    }
+}
+
+fn stats(values: impl Iterator<Item = Decimal>) -> Option<WindowStats> {
+    let mut count = 0u32;
+    let mut sum = Decimal::ZERO;
+    let mut min: Option<Decimal> = None;
+    let mut max: Option<Decimal> = None;
+    for value in values {
+        count += 1;
+        sum += value;
+        min = Some(min.map_or(value, |m| m.min(value)));
+        max = Some(max.map_or(value, |m| m.max(value)));
+    }
+
+    Some(WindowStats {
+        min: min?,
+        max: max?,
+        mean: sum / Decimal::from(count),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_cover_only_the_window() {
+        let values = [1, 5, 3, 10].map(Decimal::from);
+        let mut history = ReportHistory::new(3);
+        assert!(history.summary().is_none());
+
+        for value in values {
+            history.push_sample(Sample {
+                aum_base: value,
+                unimmr: value,
+            });
+        }
+
+        let summary = history.summary().expect("window has samples");
+        assert_eq!(summary.samples, 3);
+        assert_eq!(summary.aum_base.min, Decimal::from(3));
+        assert_eq!(summary.aum_base.max, Decimal::from(10));
+        assert_eq!(summary.aum_base.mean, Decimal::from(6));
+    }
+}
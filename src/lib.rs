@@ -1,7 +1,17 @@
 pub mod aum;
+pub mod balance_source;
 pub mod binance_client;
+pub mod clock;
 pub mod config;
+pub mod config_file;
 pub mod error;
+pub mod health;
+pub mod history;
+pub mod metrics;
 pub mod models;
 pub mod output;
 pub mod pricing;
+pub mod rate_limit;
+pub mod signing;
+pub mod state;
+pub mod stream;
@@ -1,99 +1,443 @@
-use chrono::Utc;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Write};
+
+use chrono::NaiveDate;
 use clap::Parser;
-use tracing::{error, info};
+use tracing::{error, info, info_span, warn};
+use tracing_subscriber::fmt::format::FmtSpan;
+use uuid::Uuid;
 
-use binance_aum_fetch::aum::calculate_aum;
+use binance_aum_fetch::aum::{add_valuations, calculate_aum_with_options};
 use binance_aum_fetch::binance_client::BinanceClient;
-use binance_aum_fetch::config::{AppConfig, Cli, OutputFormat};
-use binance_aum_fetch::error::AppResult;
-use binance_aum_fetch::models::AumReport;
-use binance_aum_fetch::output;
-use binance_aum_fetch::pricing::BinancePriceProvider;
-
-#[tokio::main]
-async fn main() {
-    if let Err(err) = run().await {
+use binance_aum_fetch::config::{
+    AppConfig, Cli, Command, ErrorFormat, OutputStream, Selection, Sink, SnapshotType,
+};
+use binance_aum_fetch::config_file;
+use binance_aum_fetch::error::{AppError, AppResult};
+use binance_aum_fetch::health;
+use binance_aum_fetch::metrics::{self, Pushgateway};
+use binance_aum_fetch::models::{AumInputs, AumReport};
+use binance_aum_fetch::output::{self, JsonOptions, TableOptions};
+use binance_aum_fetch::pricing::PriceProvider;
+use binance_aum_fetch::state::{self, ReportState};
+use binance_aum_fetch::stream::{
+    aum_options, bench, build_client, check_price_age, enforce_max_spot_assets, exclude,
+    fetch_options, price_provider, run_loop, stable_order,
+};
+
+fn main() {
+    let process_env: HashSet<OsString> = std::env::vars_os().map(|(key, _)| key).collect();
+    dotenvy::dotenv().ok();
+    init_tracing();
+
+    let cli = Cli::parse();
+    let error_format = cli.error_format;
+    let result = load_config_file(cli, &process_env).and_then(|cli| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(run(cli))
+    });
+    if let Err(err) = result {
         error!(error = %err, "binance_aum_fetch failed");
-        eprintln!("error: {err}");
+        match error_format {
+            ErrorFormat::Text => eprintln!("error: {err}"),
+            ErrorFormat::Json => eprintln!("{}", err.to_json()),
+        }
         std::process::exit(1);
     }
 }
 
-async fn run() -> AppResult<()> {
-    dotenvy::dotenv().ok();
-    init_tracing();
+/// Exports the `--config` file's settings as environment variables and
+/// parses the command line again to pick them up. The file overrides `.env`
+/// but not `process_env`, the variables set before `.env` was loaded. Runs
+/// before the tokio runtime starts, while the process is still
+/// single-threaded, since setting environment variables is not thread-safe.
+fn load_config_file(cli: Cli, process_env: &HashSet<OsString>) -> AppResult<Cli> {
+    match &cli.config {
+        Some(path) => {
+            config_file::export(path, cli.profile.as_deref(), process_env)?;
+            Ok(Cli::parse())
+        }
+        None => Ok(cli),
+    }
+}
 
-    let cli = Cli::parse();
+async fn run(cli: Cli) -> AppResult<()> {
+    let command = cli.command.unwrap_or_default();
     let config = AppConfig::from_cli(cli)?;
 
-    let client = BinanceClient::new(
-        config.api_key.clone(),
-        config.api_secret.clone(),
-        config.api_base_url.clone(),
-        config.papi_base_url.clone(),
-        config.timeout,
-    )?;
+    match command {
+        Command::Run => run_reports(config).await,
+        Command::Dump => dump(&build_client(&config)?, &config).await,
+        Command::Verify => verify(&build_client(&config)?, &config).await,
+        Command::Discover => discover(&build_client(&config)?, &config).await,
+        Command::Positions => {
+            require_portfolio_margin(&config, "positions")?;
+            positions(&build_client(&config)?, &config).await
+        }
+        Command::Liquidity => {
+            require_portfolio_margin(&config, "liquidity")?;
+            liquidity(&build_client(&config)?, &config).await
+        }
+        Command::Snapshot { date, kind } => {
+            require_portfolio_margin(&config, "snapshot")?;
+            snapshot(&config, date, kind).await
+        }
+        Command::Bench { iterations } => {
+            let stream = config.output_stream;
+            let summary = bench(config, iterations).await?;
+            output::render_bench(&summary, &mut writer(stream))?;
+            Ok(())
+        }
+    }
+}
+
+/// Rejects subcommands that read portfolio margin or `/sapi` endpoints,
+/// which Binance.US does not have.
+fn require_portfolio_margin(config: &AppConfig, command: &str) -> AppResult<()> {
+    if config.exchange.has_portfolio_margin() {
+        return Ok(());
+    }
+    Err(AppError::InvalidConfig {
+        field: "EXCHANGE",
+        reason: format!("`{command}` is not available on Binance.US"),
+    })
+}
 
-    let price_provider = BinancePriceProvider::new(client.clone(), config.quote_currency.clone());
+async fn run_reports(config: AppConfig) -> AppResult<()> {
+    let pushgateway = config
+        .pushgateway_url
+        .as_deref()
+        .map(|url| Pushgateway::new(url, &config.push_job, config.timeout))
+        .transpose()?;
 
     info!("binance_aum_fetch started");
+    let state = ReportState::shared();
     if config.once {
-        let report = fetch_and_compute(&client, &price_provider, &config).await?;
-        render(&report, config.output_format)?;
-        return Ok(());
+        if config
+            .sinks
+            .iter()
+            .any(|sink| matches!(sink, Sink::Prometheus(_)))
+        {
+            warn!("prometheus output is only served in loop mode; ignoring it with --once");
+        }
+    } else {
+        for sink in &config.sinks {
+            if let Sink::Prometheus(addr) = *sink {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = metrics::serve(addr, state).await {
+                        error!(error = %err, "metrics endpoint stopped");
+                    }
+                });
+            }
+        }
+        if let Some(addr) = config.health_addr {
+            let state = state.clone();
+            let failure_threshold = config.health_failure_threshold;
+            tokio::spawn(async move {
+                if let Err(err) = health::serve(addr, state, failure_threshold).await {
+                    error!(error = %err, "health endpoint stopped");
+                }
+            });
+        }
     }
 
-    loop {
-        match fetch_and_compute(&client, &price_provider, &config).await {
+    // With `--once` a report that cannot be published fails the run.
+    let mut publish_failure = None;
+    run_loop(config.clone(), async |result| {
+        match result {
             Ok(report) => {
-                if let Err(render_err) = render(&report, config.output_format) {
-                    error!(error = %render_err, "failed to render report");
+                let span = info_span!("report", report_id = %report.report_id);
+                if let Err(publish_err) = publish(report, &config) {
+                    error!(parent: &span, error = %publish_err, "failed to publish report");
+                    publish_failure = Some(publish_err);
                 }
+                state
+                    .write()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record_success(report.clone());
             }
-            Err(err) => {
-                error!(error = %err, "failed to fetch/compute report");
-            }
+            Err(_) => state
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .record_failure(),
+        }
+
+        if let Some(pushgateway) = &pushgateway {
+            let encoded = metrics::encode(&state.read().unwrap_or_else(|e| e.into_inner()));
+            push_metrics(pushgateway, encoded).await;
         }
+    })
+    .await?;
+    match publish_failure {
+        Some(err) if config.once => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Hands one report to every configured sink.
+fn publish(report: &AumReport, config: &AppConfig) -> AppResult<()> {
+    persist(report, config);
+    render(report, config)
+}
+
+async fn push_metrics(pushgateway: &Pushgateway, metrics: String) {
+    if let Err(err) = pushgateway.push(metrics).await {
+        warn!(error = %err, "failed to push metrics to pushgateway");
+    }
+}
 
-        tokio::time::sleep(config.interval).await;
+fn persist(report: &AumReport, config: &AppConfig) {
+    if let Some(path) = &config.state_file {
+        if let Err(err) = state::save_report(path, report) {
+            warn!(path = %path.display(), error = %err, "failed to write state file");
+        }
     }
 }
 
-async fn fetch_and_compute(
-    client: &BinanceClient,
-    price_provider: &BinancePriceProvider,
-    config: &AppConfig,
-) -> AppResult<AumReport> {
+/// Fetches with every selection set to `all`, so nothing is filtered out.
+async fn discover(client: &BinanceClient, config: &AppConfig) -> AppResult<()> {
     let data = client
-        .fetch_aum_data(&config.um_positions, &config.spot_assets)
+        .fetch_aum_data_with_options(&Selection::All, &Selection::All, &fetch_options(config))
         .await?;
-    let calculation = calculate_aum(&data, price_provider).await?;
+    output::render_discover(
+        &data.spot_balances,
+        &data.positions,
+        &mut output_stream(config),
+    )?;
+    Ok(())
+}
+
+async fn positions(client: &BinanceClient, config: &AppConfig) -> AppResult<()> {
+    let positions = client.fetch_positions(&config.um_positions).await?;
+    let options = table_options(config);
+    output::render_positions(&positions, &options, &mut output_stream(config))?;
+    Ok(())
+}
+
+async fn liquidity(client: &BinanceClient, config: &AppConfig) -> AppResult<()> {
+    let prices = price_provider(client, config);
+    let (withdrawable_usd, base_usd_price) =
+        tokio::try_join!(client.fetch_withdrawable_usd(), prices.base_to_usd())?;
+    let withdrawable_base = withdrawable_usd
+        .checked_div(base_usd_price)
+        .ok_or_else(|| AppError::MissingPrice(format!("{}/USD", config.base_asset)))?;
+    output::render_liquidity(
+        withdrawable_usd,
+        withdrawable_base,
+        &config.base_asset,
+        &table_options(config),
+        &mut output_stream(config),
+    )?;
+    Ok(())
+}
 
-    Ok(AumReport {
-        timestamp: Utc::now(),
+async fn snapshot(config: &AppConfig, date: NaiveDate, kind: SnapshotType) -> AppResult<()> {
+    let client = build_client(config)?;
+    let (taken_at, mut data) = client
+        .fetch_snapshot_data(
+            kind,
+            date,
+            &config.um_positions,
+            &config.spot_assets,
+            &config.settlement_asset,
+        )
+        .await?;
+    stable_order(&mut data, config);
+    exclude(&mut data, &config.exclude_positions, &config.exclude_assets);
+    enforce_max_spot_assets(&client, config, &mut data).await?;
+    let prices = price_provider(&client, config).at_date(date);
+    let options = aum_options(config);
+    let mut calculation = calculate_aum_with_options(&data, &prices, &options).await?;
+    add_valuations(
+        &mut calculation,
+        &config.report_currencies,
+        &config.usd_quote,
+        &prices,
+        &options,
+    )
+    .await?;
+    let report = AumInputs {
+        fetched_at: taken_at,
         data,
         calculation,
-    })
+        api_calls: client.api_calls(),
+    }
+    .into_report(Uuid::new_v4(), None, None);
+    check_price_age(&report, config.max_price_age)?;
+    render(&report, config)
 }
 
-fn render(report: &AumReport, format: OutputFormat) -> AppResult<()> {
-    match format {
-        OutputFormat::Table => {
-            output::render_table(report);
-            Ok(())
+async fn dump(client: &BinanceClient, config: &AppConfig) -> AppResult<()> {
+    let responses = client.fetch_raw_responses().await;
+    let mut out = output_stream(config);
+    for (endpoint, body) in responses {
+        writeln!(out, "== {endpoint} ==")?;
+        match body {
+            Ok(body) => writeln!(out, "{body}")?,
+            Err(err) => writeln!(out, "error: {err}")?,
         }
-        OutputFormat::Json => {
-            println!("{}", serde_json::to_string_pretty(report)?);
-            Ok(())
+    }
+    Ok(())
+}
+
+async fn verify(client: &BinanceClient, config: &AppConfig) -> AppResult<()> {
+    let checks = if config.exchange.has_portfolio_margin() {
+        client.verify().await
+    } else {
+        client.verify_spot().await
+    };
+    let key_rejected = checks
+        .iter()
+        .any(|(_, outcome)| matches!(outcome, Err(err) if err.code() == Some(-2015)));
+    let egress_ip = match config.egress_ip_url.as_deref().filter(|_| key_rejected) {
+        Some(url) => client
+            .egress_ip(url)
+            .await
+            .inspect_err(|err| warn!(error = %err, "failed to look up egress IP"))
+            .ok(),
+        None => None,
+    };
+
+    let mut out = output_stream(config);
+    let mut failures = 0;
+    for (endpoint, outcome) in checks {
+        match outcome {
+            Ok(()) => writeln!(out, "ok      {endpoint}")?,
+            Err(err) => {
+                failures += 1;
+                match err.hint() {
+                    Some(hint) => writeln!(out, "FAILED  {endpoint}: {err} ({hint})")?,
+                    None => writeln!(out, "FAILED  {endpoint}: {err}")?,
+                }
+            }
+        }
+    }
+    if let Some(ip) = egress_ip {
+        writeln!(out, "egress IP: {ip} (compare with the key's IP whitelist)")?;
+    }
+
+    if failures > 0 {
+        return Err(AppError::VerificationFailed(failures));
+    }
+    Ok(())
+}
+
+/// Renders `report` to every configured sink, or only its symbol prices with
+/// `--prices-only` or its derivation with `--explain`. A failing sink does
+/// not stop the others; the last error is returned.
+fn render(report: &AumReport, config: &AppConfig) -> AppResult<()> {
+    if config.prices_only || config.explain {
+        if config.quiet {
+            return Ok(());
         }
+        let mut out = output_stream(config);
+        if config.explain {
+            output::render_explain(report, &table_options(config), &mut out)?;
+        } else {
+            writeln!(
+                out,
+                "{}",
+                output::to_json(&report.calculation.prices, config.json_style)?
+            )?;
+        }
+        return Ok(());
+    }
+    let mut result = Ok(());
+    for sink in &config.sinks {
+        if config.quiet && sink.is_stream() {
+            continue;
+        }
+        if let Err(err) = render_sink(report, sink, config) {
+            warn!(?sink, error = %err, "output sink failed");
+            result = Err(err);
+        }
+    }
+    result
+}
+
+fn render_sink(report: &AumReport, sink: &Sink, config: &AppConfig) -> AppResult<()> {
+    let mut out = output_stream(config);
+    match sink {
+        Sink::Table => output::render_table(report, &table_options(config), &mut out)?,
+        Sink::Json(path) => {
+            let options = json_options(config);
+            match path {
+                Some(path) => output::render_json(report, &options, &mut File::create(path)?)?,
+                None => output::render_json(report, &options, &mut out)?,
+            }
+        }
+        Sink::MessagePack(path) => {
+            let sort = config.sort_contributions;
+            match path {
+                Some(path) => output::render_msgpack(report, sort, &mut File::create(path)?)?,
+                None => output::render_msgpack(report, sort, &mut out)?,
+            }
+        }
+        Sink::Logfmt => output::render_logfmt(report, &config.quote_currency, &mut out)?,
+        Sink::Summary => output::render_summary(
+            report,
+            &config.quote_currency,
+            &table_options(config),
+            &mut out,
+        )?,
+        Sink::Template => {
+            let template = config.template.as_deref().unwrap_or_default();
+            writeln!(out, "{}", output::render_template(report, template)?)?;
+        }
+        // Served from the shared report state by `metrics::serve`.
+        Sink::Prometheus(_) => {}
+    }
+    Ok(())
+}
+
+/// The `--output-stream` that rendered output goes to.
+fn output_stream(config: &AppConfig) -> Box<dyn Write> {
+    writer(config.output_stream)
+}
+
+fn writer(stream: OutputStream) -> Box<dyn Write> {
+    match stream {
+        OutputStream::Stdout => Box::new(io::stdout().lock()),
+        OutputStream::Stderr => Box::new(io::stderr().lock()),
+    }
+}
+
+fn json_options(config: &AppConfig) -> JsonOptions {
+    JsonOptions {
+        sort: config.sort_contributions,
+        style: config.json_style,
+        shape: config.json_shape,
+        decimals: config.decimal_json,
+    }
+}
+
+fn table_options(config: &AppConfig) -> TableOptions {
+    TableOptions {
+        decimal_places: config.decimal_places,
+        sort_contributions: config.sort_contributions,
+        number_format: config.number_format,
+        currency_symbol: config.currency_symbol,
+        color: config.color.enabled(config.output_stream.is_terminal()),
     }
 }
 
+/// `LOG_SPAN_TIMINGS=true` logs each `report`, `fetch` and `calculate` span
+/// as it closes, with its busy and idle time.
 fn init_tracing() {
+    let span_events = match std::env::var("LOG_SPAN_TIMINGS").as_deref() {
+        Ok("1" | "true") => FmtSpan::CLOSE,
+        _ => FmtSpan::NONE,
+    };
     let _ = tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "binance_aum_fetch=info".into()),
         )
+        .with_span_events(span_events)
         .try_init();
 }
@@ -0,0 +1,194 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::error::{AppError, AppResult};
+use crate::health;
+use crate::models::AumReport;
+use crate::state::{ReportState, SharedReportState};
+
+/// Serves the latest report in Prometheus text exposition format on `/metrics`.
+pub async fn serve(addr: SocketAddr, state: SharedReportState) -> AppResult<()> {
+    health::serve_with(addr, state, route).await
+}
+
+/// Pushes the same gauges to a Prometheus Pushgateway, replacing the
+/// previous push for the job.
+#[derive(Debug, Clone)]
+pub struct Pushgateway {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl Pushgateway {
+    pub fn new(base_url: &str, job: &str, timeout: Duration) -> AppResult<Self> {
+        let http = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(Self {
+            http,
+            url: format!("{base_url}/metrics/job/{job}"),
+        })
+    }
+
+    /// Pushes metrics produced by [`encode`].
+    pub async fn push(&self, metrics: String) -> AppResult<()> {
+        let response = self
+            .http
+            .put(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(metrics)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(AppError::Pushgateway {
+                status: status.as_u16(),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn route(path: &str, state: &ReportState) -> (u16, String) {
+    match path {
+        "/metrics" => (200, encode(state)),
+        _ => (404, "not found".to_string()),
+    }
+}
+
+/// Renders the report state in Prometheus text exposition format.
+pub fn encode(state: &ReportState) -> String {
+    let mut out = String::new();
+    gauge(
+        &mut out,
+        "binance_aum_consecutive_failures",
+        "Consecutive failed fetches since the last success.",
+        &[(String::new(), state.consecutive_failures.to_string())],
+    );
+    if let Some(report) = &state.latest_report {
+        render_report(&mut out, report);
+    }
+    out
+}
+
+fn render_report(out: &mut String, report: &AumReport) {
+    let calc = &report.calculation;
+    let base = format!("base_asset=\"{}\"", calc.base_asset);
+    gauge(
+        out,
+        "binance_aum_base",
+        "Assets under management in the base asset.",
+        &[(
+            base.clone(),
+            calc.aum_base_18dp.value().normalize().to_string(),
+        )],
+    );
+    gauge(
+        out,
+        "binance_aum_base_u8",
+        "Assets under management in base-asset units of 1e-8.",
+        &[(base.clone(), calc.aum_base_u8.to_string())],
+    );
+    gauge(
+        out,
+        "binance_aum_base_usd_price",
+        "Price of the base asset in the quote currency.",
+        &[(base.clone(), calc.base_usd_price.normalize().to_string())],
+    );
+    gauge(
+        out,
+        "binance_aum_unimmr",
+        "Portfolio margin uniMMR.",
+        &[(String::new(), report.data.unimmr.normalize().to_string())],
+    );
+    gauge(
+        out,
+        "binance_aum_withdrawable_usd",
+        "Portfolio margin virtual max withdraw amount.",
+        &[(
+            String::new(),
+            report.data.withdrawable_usd.normalize().to_string(),
+        )],
+    );
+
+    let contributions: Vec<_> = calc
+        .spot_contributions
+        .iter()
+        .map(|c| {
+            (
+                format!("asset=\"{}\"", c.asset),
+                c.amount_base.value().normalize().to_string(),
+            )
+        })
+        .collect();
+    gauge(
+        out,
+        "binance_aum_spot_contribution_base",
+        "Spot balance value in the base asset, per asset.",
+        &contributions,
+    );
+
+    gauge(
+        out,
+        "binance_aum_report_api_calls",
+        "Binance requests made to produce the latest report.",
+        &[(String::new(), report.api_calls.to_string())],
+    );
+
+    gauge(
+        out,
+        "binance_aum_report_timestamp_seconds",
+        "Unix time of the latest successful report.",
+        &[(String::new(), report.timestamp.timestamp().to_string())],
+    );
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, samples: &[(String, String)]) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{name} {value}");
+        } else {
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_without_report_only_expose_failures() {
+        let mut state = ReportState::default();
+        state.record_failure();
+
+        let (status, body) = route("/metrics", &state);
+        assert_eq!(status, 200);
+        assert!(body.contains("binance_aum_consecutive_failures 1\n"));
+        assert!(!body.contains("binance_aum_base"));
+        assert_eq!(route("/healthz", &state).0, 404);
+    }
+
+    #[tokio::test]
+    async fn pushes_to_job_path() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/metrics/job/aum"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let pushgateway =
+            Pushgateway::new(&server.uri(), "aum", Duration::from_secs(5)).expect("client");
+        pushgateway
+            .push(encode(&ReportState::default()))
+            .await
+            .expect("push should succeed");
+    }
+}
@@ -1,12 +1,19 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
 use chrono::{DateTime, Utc};
-use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UmPositionApi {
     pub symbol: String,
     pub position_amt: String,
+    pub mark_price: String,
     #[serde(rename = "unrealizedProfit", alias = "unRealizedProfit")]
     pub unrealized_profit: String,
 }
@@ -41,64 +48,706 @@ pub struct SpotBalanceApi {
     pub locked: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimpleEarnPageApi<T> {
+    pub rows: Vec<T>,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimpleEarnFlexiblePositionApi {
+    pub asset: String,
+    pub total_amount: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimpleEarnLockedPositionApi {
+    pub asset: String,
+    pub amount: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginAccountApi {
+    pub user_assets: Vec<MarginAssetApi>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginAssetApi {
+    pub asset: String,
+    pub borrowed: String,
+    pub interest: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSnapshotApi<T> {
+    pub snapshot_vos: Vec<AccountSnapshotVoApi<T>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSnapshotVoApi<T> {
+    pub update_time: i64,
+    pub data: T,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpotSnapshotDataApi {
+    pub balances: Vec<SpotBalanceApi>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FuturesSnapshotDataApi {
+    pub assets: Vec<FuturesSnapshotAssetApi>,
+    pub position: Vec<UmPositionApi>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesSnapshotAssetApi {
+    pub asset: String,
+    pub margin_balance: String,
+    pub wallet_balance: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FapiAccountApi {
+    pub total_margin_balance: String,
+    pub total_wallet_balance: String,
+    pub total_unrealized_profit: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FapiBalanceApi {
+    pub asset: String,
+    pub balance: String,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct PriceTickerApi {
     pub price: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// One entry of a multi-symbol `/api/v3/ticker/price` response.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SymbolPriceTickerApi {
+    pub symbol: String,
+    pub price: String,
+}
+
+/// One record of `/papi/v1/um/income`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UmIncomeApi {
+    #[serde(default)]
+    pub symbol: String,
+    pub income_type: String,
+    pub income: String,
+    pub asset: String,
+    pub time: i64,
+    pub tran_id: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookTickerApi {
+    pub bid_price: String,
+    pub ask_price: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ticker24hApi {
+    pub weighted_avg_price: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerTimeApi {
+    pub server_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UmPosition {
     pub symbol: String,
     pub amount: Decimal,
     pub pnl: Decimal,
+    pub mark_price: Decimal,
+    pub notional: Decimal,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpotBalance {
     pub asset: String,
+    /// Everything held: `free + locked`, plus Simple Earn with `--include-earn`.
     pub amount: Decimal,
+    /// Spot `free` and `locked` as Binance reports them, kept with
+    /// `--split-free-locked`; informational, the AUM uses `amount`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub free: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked: Option<Decimal>,
+    /// Simple Earn part of `amount` with `--split-free-locked` and
+    /// `--include-earn`, so that `free + locked + earn == amount`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub earn: Option<Decimal>,
+}
+
+impl SpotBalance {
+    /// A balance without the `free`/`locked` split.
+    pub fn new(asset: impl Into<String>, amount: Decimal) -> Self {
+        Self {
+            asset: asset.into(),
+            amount,
+            free: None,
+            locked: None,
+            earn: None,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarginLiability {
+    pub asset: String,
+    /// Borrowed principal plus accrued interest.
+    pub amount: Decimal,
+}
+
+/// A classic USDⓂ futures account (`/fapi`), separate from the portfolio
+/// margin account. Its equity is not part of `pm_account_actual_equity`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassicFuturesAccount {
+    /// `totalMarginBalance`: wallet balance plus unrealized PnL, in USD.
+    pub equity_usd: Decimal,
+    pub wallet_balance_usd: Decimal,
+    pub unrealized_pnl_usd: Decimal,
+    /// Non-zero wallet balances per asset, from `/fapi/v2/balance`.
+    pub balances: Vec<SpotBalance>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BinanceData {
     pub unimmr: Decimal,
     pub positions: Vec<UmPosition>,
-    pub um_balance_usdt: Decimal,
+    /// Stablecoin `um_balance_usd` is read from.
+    #[serde(default = "default_settlement_asset")]
+    pub settlement_asset: String,
+    #[serde(rename = "um_balance_usdt", alias = "um_balance_usd")]
+    pub um_balance_usd: Decimal,
     pub spot_balances: Vec<SpotBalance>,
+    /// Present only with `--include-classic-futures`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub classic_futures: Option<ClassicFuturesAccount>,
     pub pm_account_actual_equity: Decimal,
-    pub withdrawable_usdt: Decimal,
+    #[serde(rename = "withdrawable_usdt", alias = "withdrawable_usd")]
+    pub withdrawable_usd: Decimal,
+    pub margin_liabilities: Vec<MarginLiability>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl BinanceData {
+    /// Sorts positions by symbol and balances and liabilities by asset, so
+    /// the report does not depend on the order the endpoints (or concurrent
+    /// fetches) returned them in.
+    pub fn sort_by_name(&mut self) {
+        self.positions.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        self.spot_balances.sort_by(|a, b| a.asset.cmp(&b.asset));
+        self.margin_liabilities
+            .sort_by(|a, b| a.asset.cmp(&b.asset));
+        if let Some(account) = self.classic_futures.as_mut() {
+            account.balances.sort_by(|a, b| a.asset.cmp(&b.asset));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpotContribution {
     pub asset: String,
     pub amount: Decimal,
-    pub btc_to_asset_price: Decimal,
-    pub amount_btc: Decimal,
+    #[serde(rename = "btc_to_asset_price", alias = "base_to_asset_price")]
+    pub base_to_asset_price: Decimal,
+    #[serde(rename = "amount_btc", alias = "amount_base")]
+    pub amount_base: BaseAmount,
+    /// Asset whose price was used, when an alias replaced `asset`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priced_as: Option<String>,
+    #[serde(default)]
+    pub price_source: PriceSource,
+    /// Live ticker price the exchange price was read from; unset for
+    /// overrides, pegged assets and daily closes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_basis: Option<PriceBasis>,
+    /// Haircut applied to this asset, in percent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub haircut_pct: Option<Decimal>,
+    /// `amount_base` after the haircut; set only when one applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount_base_net: Option<BaseAmount>,
+}
+
+/// Where a spot contribution's `base_to_asset_price` came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceSource {
+    /// A Binance ticker, or the 1:1 peg of the wrapped base asset.
+    #[default]
+    Exchange,
+    /// The two-hop route through the USD quote (`--fallback-assets`).
+    Fallback,
+    /// The `--price-overrides` file.
+    Override,
+    /// No price at all; valued at zero under `--missing-price-policy zero`.
+    Unpriced,
+}
+
+impl PriceSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PriceSource::Exchange => "exchange",
+            PriceSource::Fallback => "fallback",
+            PriceSource::Override => "override",
+            PriceSource::Unpriced => "unpriced",
+        }
+    }
+}
+
+/// Which Binance price a symbol is valued at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceBasis {
+    /// Last trade, from `/api/v3/ticker/price`.
+    #[default]
+    Last,
+    /// Midpoint of the best bid and ask, from `/api/v3/ticker/bookTicker`.
+    Mid,
+}
+
+impl PriceBasis {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PriceBasis::Last => "last",
+            PriceBasis::Mid => "mid",
+        }
+    }
+}
+
+/// The AUM expressed in one of `--report-currencies`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Valuation {
+    pub currency: String,
+    /// Units of `currency` per one base asset.
+    pub rate: Decimal,
+    pub aum: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedAsset {
+    pub asset: String,
+    pub amount: Decimal,
+    pub reason: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// An amount of the base asset (e.g. BTC). Serializes as a plain decimal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BaseAmount(Decimal);
+
+/// An amount of the base asset in integer units of 1e-8 (satoshis for BTC).
+/// Serializes as a plain integer.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct BaseUnits(i128);
+
+impl BaseAmount {
+    pub const fn new(value: Decimal) -> Self {
+        Self(value)
+    }
+
+    pub fn value(self) -> Decimal {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// Whole 1e-8 units, dropping finer precision with `strategy`; `None` on
+    /// overflow.
+    ///
+    /// The default strategy truncates toward zero rather than rounding, so
+    /// the reported unit total never exceeds the computed AUM.
+    pub fn to_units(self, strategy: RoundingStrategy) -> Option<BaseUnits> {
+        self.0
+            .checked_mul(Decimal::from(BaseUnits::PER_BASE))?
+            .round_dp_with_strategy(0, strategy)
+            .to_i128()
+            .map(BaseUnits)
+    }
+}
+
+impl BaseUnits {
+    pub const PER_BASE: i64 = 100_000_000;
+
+    pub const fn new(value: i128) -> Self {
+        Self(value)
+    }
+
+    pub fn value(self) -> i128 {
+        self.0
+    }
+
+    pub fn to_amount(self) -> BaseAmount {
+        BaseAmount(Decimal::from_i128_with_scale(self.0, 8))
+    }
+}
+
+impl std::ops::Sub for BaseAmount {
+    type Output = BaseAmount;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        BaseAmount(self.0 - rhs.0)
+    }
+}
+
+impl std::ops::Sub for BaseUnits {
+    type Output = BaseUnits;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        BaseUnits(self.0 - rhs.0)
+    }
+}
+
+impl fmt::Display for BaseAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Display for BaseUnits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AumCalculation {
-    pub aum_btc_18dp: Decimal,
-    pub aum_wbtc_u8: i128,
-    pub aum_wbtc: Decimal,
-    pub spot_total_btc: Decimal,
+    /// Reference asset the `*_base` figures are denominated in (e.g. `BTC`).
+    #[serde(default = "default_base_asset")]
+    pub base_asset: String,
+    #[serde(rename = "aum_btc_18dp", alias = "aum_base_18dp")]
+    pub aum_base_18dp: BaseAmount,
+    /// AUM in 8-decimal integer units of the wrapped base asset (WBTC sats).
+    #[serde(rename = "aum_wbtc_u8", alias = "aum_base_u8")]
+    pub aum_base_u8: BaseUnits,
+    #[serde(rename = "aum_wbtc", alias = "aum_base")]
+    pub aum_base: BaseAmount,
+    #[serde(rename = "spot_total_btc", alias = "spot_total_base")]
+    pub spot_total_base: BaseAmount,
     pub pm_equity_usd: Decimal,
-    pub btc_usd_price: Decimal,
+    /// Classic USDⓂ futures equity, counted on top of `pm_equity_usd`.
+    #[serde(default)]
+    pub classic_futures_equity_usd: Decimal,
+    #[serde(rename = "btc_usd_price", alias = "base_usd_price")]
+    pub base_usd_price: Decimal,
+    /// Set when `base_usd_price` came from `--base-usd-override`.
+    #[serde(default)]
+    pub base_usd_price_overridden: bool,
+    /// Sum of UM position notionals (`amount * mark_price`); informational.
+    pub um_notional_usd: Decimal,
+    /// Portion of UM notional added to `aum_base_18dp`; zero unless enabled.
+    #[serde(
+        rename = "um_notional_btc_included",
+        alias = "um_notional_base_included"
+    )]
+    pub um_notional_base_included: BaseAmount,
+    /// Cross-margin liabilities converted to the base asset and subtracted
+    /// from the total.
+    #[serde(rename = "liabilities_btc", alias = "liabilities_base")]
+    pub liabilities_base: BaseAmount,
     pub spot_contributions: Vec<SpotContribution>,
+    pub partial: bool,
+    pub skipped_assets: Vec<SkippedAsset>,
+    /// Value of spot balances below the dust threshold, excluded from the
+    /// total.
+    #[serde(default)]
+    pub dust_base: BaseAmount,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dust_assets: Vec<String>,
+    /// Sum of spot haircuts; `aum_base_18dp` is gross of it.
+    #[serde(default)]
+    pub haircut_base: BaseAmount,
+    /// AUM after haircuts; set only when haircuts are configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aum_base_net: Option<BaseAmount>,
+    /// Assets valued through the secondary pricing route.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_assets: Vec<String>,
+    /// Raw symbol prices that fed the calculation (e.g. `BTCUSDT`,
+    /// `ETHBTC`), as quoted by Binance before any inversion.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prices: BTreeMap<String, Decimal>,
+    /// Time of the oldest price in `prices`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prices_as_of: Option<DateTime<Utc>>,
+    /// Time of the newest price in `prices`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prices_as_of_latest: Option<DateTime<Utc>>,
+    /// Symbols in `prices` read from the current ticker because the
+    /// `snapshot` date had no daily close for them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub live_fallback_symbols: Vec<String>,
+    /// `aum_base` in each `--report-currencies` entry, in the listed order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub valuations: Vec<Valuation>,
+}
+
+fn default_base_asset() -> String {
+    "BTC".to_string()
+}
+
+fn default_settlement_asset() -> String {
+    "USDT".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AumDelta {
+    pub previous_timestamp: DateTime<Utc>,
+    #[serde(rename = "aum_btc_change", alias = "aum_base_change")]
+    pub aum_base_change: BaseAmount,
+    #[serde(rename = "aum_wbtc_u8_change", alias = "aum_base_u8_change")]
+    pub aum_base_u8_change: BaseUnits,
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl AumDelta {
+    pub fn between(previous: &AumReport, current: &AumReport) -> Self {
+        Self {
+            previous_timestamp: previous.timestamp,
+            aum_base_change: current.calculation.aum_base_18dp - previous.calculation.aum_base_18dp,
+            aum_base_u8_change: current.calculation.aum_base_u8 - previous.calculation.aum_base_u8,
+        }
+    }
+}
+
+/// Change versus a report saved earlier, e.g. at month start.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineComparison {
+    pub baseline_timestamp: DateTime<Utc>,
+    pub aum_base_change: BaseAmount,
+    /// `None` when the baseline AUM is zero.
+    pub aum_base_change_pct: Option<Decimal>,
+    pub aum_base_u8_change: BaseUnits,
+}
+
+impl BaselineComparison {
+    /// `None` when the reports are denominated in different base assets and
+    /// cannot be compared.
+    pub fn between(baseline: &AumReport, current: &AumReport) -> Option<Self> {
+        if baseline.calculation.base_asset != current.calculation.base_asset {
+            return None;
+        }
+        let before = baseline.calculation.aum_base_18dp.value();
+        let change = current.calculation.aum_base_18dp.value() - before;
+        Some(Self {
+            baseline_timestamp: baseline.timestamp,
+            aum_base_change: BaseAmount::new(change),
+            aum_base_change_pct: (!before.is_zero())
+                .then(|| (change / before * Decimal::ONE_HUNDRED).round_dp(4)),
+            aum_base_u8_change: current.calculation.aum_base_u8 - baseline.calculation.aum_base_u8,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowStats {
+    pub min: Decimal,
+    pub max: Decimal,
+    pub mean: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistorySummary {
+    pub samples: usize,
+    #[serde(rename = "aum_btc", alias = "aum_base")]
+    pub aum_base: WindowStats,
+    pub unimmr: WindowStats,
+}
+
+/// A UM income record: realized PnL, a funding fee, a commission, ...
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UmIncome {
+    pub symbol: String,
+    /// Binance `incomeType`, e.g. `REALIZED_PNL`.
+    pub income_type: String,
+    pub asset: String,
+    pub amount: Decimal,
+    pub time: DateTime<Utc>,
+}
+
+/// UM income booked in `[from, to)`, summed per income asset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncomeSummary {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub records: usize,
+    pub by_asset: BTreeMap<String, IncomeTotals>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IncomeTotals {
+    pub realized_pnl: Decimal,
+    /// Negative when funding was paid.
+    pub funding_fee: Decimal,
+    /// Negative: fees paid.
+    pub commission: Decimal,
+    /// Every other income type, e.g. transfers and insurance clear.
+    pub other: Decimal,
+}
+
+impl IncomeSummary {
+    pub fn from_records(from: DateTime<Utc>, to: DateTime<Utc>, records: &[UmIncome]) -> Self {
+        let mut by_asset = BTreeMap::<String, IncomeTotals>::new();
+        for record in records {
+            let totals = by_asset.entry(record.asset.clone()).or_default();
+            let bucket = match record.income_type.as_str() {
+                "REALIZED_PNL" => &mut totals.realized_pnl,
+                "FUNDING_FEE" => &mut totals.funding_fee,
+                "COMMISSION" => &mut totals.commission,
+                _ => &mut totals.other,
+            };
+            *bucket += record.amount;
+        }
+        Self {
+            from,
+            to,
+            records: records.len(),
+            by_asset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AumReport {
+    /// Unique per report; correlates the report across logs and sinks.
+    #[serde(default)]
+    pub report_id: Uuid,
     pub timestamp: DateTime<Utc>,
     pub data: BinanceData,
     pub calculation: AumCalculation,
+    /// Binance requests made to fetch and price this report.
+    #[serde(default)]
+    pub api_calls: u32,
+    /// Seconds between `timestamp` and the price observed furthest from it,
+    /// before or after.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_age_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<AumDelta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<HistorySummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline: Option<BaselineComparison>,
+    /// Realized UM income over the `--include-income` window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub income: Option<IncomeSummary>,
+    /// Upstream response bodies keyed by endpoint, with `--include-raw`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+/// One fetched snapshot and its valuation. Every sink in a run renders from
+/// the report built out of a single `AumInputs`, so nothing is fetched twice.
+#[derive(Debug, Clone)]
+pub struct AumInputs {
+    /// When the balances were read; the report's `timestamp`.
+    pub fetched_at: DateTime<Utc>,
+    pub data: BinanceData,
+    pub calculation: AumCalculation,
+    pub api_calls: u32,
+}
+
+impl AumInputs {
+    pub fn into_report(
+        self,
+        report_id: Uuid,
+        previous: Option<&AumReport>,
+        baseline: Option<&AumReport>,
+    ) -> AumReport {
+        let mut report = AumReport {
+            report_id,
+            timestamp: self.fetched_at,
+            data: self.data,
+            calculation: self.calculation,
+            api_calls: self.api_calls,
+            price_age_secs: None,
+            delta: None,
+            history: None,
+            baseline: None,
+            income: None,
+            raw: None,
+        };
+        let calc = &report.calculation;
+        report.price_age_secs = [calc.prices_as_of, calc.prices_as_of_latest]
+            .into_iter()
+            .flatten()
+            .map(|as_of| {
+                (report.timestamp - as_of)
+                    .abs()
+                    .to_std()
+                    .unwrap_or_default()
+                    .as_secs()
+            })
+            .max();
+        report.delta = previous.map(|p| AumDelta::between(p, &report));
+        report.baseline = baseline.and_then(|b| BaselineComparison::between(b, &report));
+        report
+    }
+}
+
+/// Latency distribution of a [`bench`](crate::stream::bench) run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchSummary {
+    pub iterations: u32,
+    pub errors: u32,
+    /// Latencies of the successful iterations, fastest first.
+    pub latencies: Vec<Duration>,
+}
+
+impl BenchSummary {
+    pub fn error_rate(&self) -> f64 {
+        f64::from(self.errors) / f64::from(self.iterations.max(1))
+    }
+
+    /// Nearest-rank percentile of the successful latencies.
+    pub fn percentile(&self, pct: u8) -> Option<Duration> {
+        let rank = (usize::from(pct) * self.latencies.len()).div_ceil(100);
+        self.latencies.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+/// Canonical form of an asset or symbol name: trimmed and uppercased, the
+/// way Binance spells them. Every configured or looked-up name goes through
+/// this, so `weth` in a config matches `WETH` at runtime.
+pub fn normalize_symbol(raw: &str) -> String {
+    raw.trim().to_uppercase()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn normalize_symbol_trims_and_uppercases() {
+        assert_eq!(normalize_symbol(" weth\t"), "WETH");
+        assert_eq!(normalize_symbol("BTCUSDT"), "BTCUSDT");
+    }
+
     #[test]
     fn deserialize_binance_mock_payloads() {
         let positions: Vec<UmPositionApi> =
@@ -121,4 +770,182 @@ mod tests {
                 .expect("spot account json should decode");
         assert!(!spot.balances.is_empty());
     }
+
+    #[test]
+    fn income_is_summed_per_asset_and_type() {
+        let at = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let record = |income_type: &str, asset: &str, amount: i64| UmIncome {
+            symbol: "BTCUSDT".to_string(),
+            income_type: income_type.to_string(),
+            asset: asset.to_string(),
+            amount: Decimal::from(amount),
+            time: at,
+        };
+        let records = [
+            record("REALIZED_PNL", "USDT", 100),
+            record("REALIZED_PNL", "USDT", -30),
+            record("FUNDING_FEE", "USDT", -5),
+            record("COMMISSION", "BNB", -1),
+            record("TRANSFER", "USDT", 7),
+        ];
+        let summary = IncomeSummary::from_records(at, at, &records);
+        assert_eq!(summary.records, 5);
+        assert_eq!(
+            summary.by_asset["USDT"],
+            IncomeTotals {
+                realized_pnl: Decimal::from(70),
+                funding_fee: Decimal::from(-5),
+                commission: Decimal::ZERO,
+                other: Decimal::from(7),
+            }
+        );
+        assert_eq!(summary.by_asset["BNB"].commission, Decimal::from(-1));
+    }
+
+    #[test]
+    fn sort_by_name_orders_positions_and_balances() {
+        let balance = |asset: &str| SpotBalance::new(asset, Decimal::ONE);
+        let position = |symbol: &str| UmPosition {
+            symbol: symbol.to_string(),
+            amount: Decimal::ONE,
+            pnl: Decimal::ZERO,
+            mark_price: Decimal::ONE,
+            notional: Decimal::ONE,
+        };
+        let mut data = BinanceData {
+            unimmr: Decimal::ZERO,
+            positions: vec![
+                position("SOLUSDT"),
+                position("BTCUSDT"),
+                position("ETHUSDT"),
+            ],
+            settlement_asset: "USDT".to_string(),
+            um_balance_usd: Decimal::ZERO,
+            spot_balances: vec![balance("USDT"), balance("BTC"), balance("ETH")],
+            classic_futures: None,
+            pm_account_actual_equity: Decimal::ZERO,
+            withdrawable_usd: Decimal::ZERO,
+            margin_liabilities: vec![],
+        };
+        let mut reversed = data.clone();
+        reversed.positions.reverse();
+        reversed.spot_balances.reverse();
+
+        data.sort_by_name();
+        reversed.sort_by_name();
+        assert_eq!(
+            serde_json::to_string(&data).unwrap(),
+            serde_json::to_string(&reversed).unwrap()
+        );
+        let assets: Vec<_> = data
+            .spot_balances
+            .iter()
+            .map(|b| b.asset.as_str())
+            .collect();
+        assert_eq!(assets, ["BTC", "ETH", "USDT"]);
+        assert_eq!(data.positions[0].symbol, "BTCUSDT");
+    }
+
+    #[test]
+    fn price_age_is_measured_from_the_furthest_price() {
+        let decoded: AumReport = serde_json::from_str(
+            r#"{
+                "timestamp": "2024-01-01T00:00:00Z",
+                "data": {
+                    "unimmr": "5", "positions": [], "um_balance_usdt": "0",
+                    "spot_balances": [], "pm_account_actual_equity": "0",
+                    "withdrawable_usdt": "0", "margin_liabilities": []
+                },
+                "calculation": {
+                    "aum_btc_18dp": "2", "aum_wbtc_u8": 200000000, "aum_wbtc": "2",
+                    "spot_total_btc": "0", "pm_equity_usd": "0", "btc_usd_price": "1",
+                    "um_notional_usd": "0", "um_notional_btc_included": "0",
+                    "liabilities_btc": "0", "spot_contributions": [],
+                    "partial": false, "skipped_assets": [],
+                    "prices_as_of": "2024-01-01T00:00:00Z"
+                }
+            }"#,
+        )
+        .expect("report should decode");
+
+        let inputs = AumInputs {
+            fetched_at: "2024-01-01T00:01:30Z".parse().unwrap(),
+            data: decoded.data,
+            calculation: decoded.calculation,
+            api_calls: 0,
+        };
+        let report = inputs.clone().into_report(Uuid::nil(), None, None);
+        assert_eq!(report.price_age_secs, Some(90));
+        let max = Some(Duration::from_secs(60));
+        assert!(crate::stream::check_price_age(&report, max).is_err());
+
+        // A snapshot balance from two days earlier, priced at the current
+        // ticker because its date had no daily close.
+        let snapshot = AumInputs {
+            fetched_at: "2023-12-30T00:01:30Z".parse().unwrap(),
+            calculation: AumCalculation {
+                prices_as_of: Some("2024-01-01T00:01:30Z".parse().unwrap()),
+                prices_as_of_latest: Some("2024-01-01T00:01:30Z".parse().unwrap()),
+                ..inputs.calculation
+            },
+            ..inputs
+        };
+        let report = snapshot.into_report(Uuid::nil(), None, None);
+        assert_eq!(report.price_age_secs, Some(2 * 86_400));
+        match crate::stream::check_price_age(&report, Some(Duration::from_secs(3_600))) {
+            Err(crate::error::AppError::StalePrices { age, .. }) => {
+                assert_eq!(age, Duration::from_secs(2 * 86_400))
+            }
+            other => panic!("expected StalePrices, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn baseline_in_legacy_schema_still_compares() {
+        // Written before the BTC-specific field names were generalized.
+        let baseline: AumReport = serde_json::from_str(
+            r#"{
+                "timestamp": "2024-01-01T00:00:00Z",
+                "data": {
+                    "unimmr": "5", "positions": [], "um_balance_usdt": "0",
+                    "spot_balances": [], "pm_account_actual_equity": "0",
+                    "withdrawable_usdt": "0", "margin_liabilities": []
+                },
+                "calculation": {
+                    "aum_btc_18dp": "2", "aum_wbtc_u8": 200000000, "aum_wbtc": "2",
+                    "spot_total_btc": "0", "pm_equity_usd": "0", "btc_usd_price": "1",
+                    "um_notional_usd": "0", "um_notional_btc_included": "0",
+                    "liabilities_btc": "0", "spot_contributions": [],
+                    "partial": false, "skipped_assets": []
+                }
+            }"#,
+        )
+        .expect("legacy baseline should decode");
+
+        let mut current = baseline.clone();
+        current.calculation.aum_base_18dp = BaseAmount::new(Decimal::new(25, 1));
+        current.calculation.aum_base_u8 = BaseUnits::new(250_000_000);
+
+        let cmp = BaselineComparison::between(&baseline, &current).expect("same base asset");
+        assert_eq!(cmp.aum_base_change, BaseAmount::new(Decimal::new(5, 1)));
+        assert_eq!(cmp.aum_base_change_pct, Some(Decimal::from(25)));
+        assert_eq!(cmp.aum_base_u8_change, BaseUnits::new(50_000_000));
+
+        current.calculation.base_asset = "ETH".to_string();
+        assert!(BaselineComparison::between(&baseline, &current).is_none());
+    }
+
+    #[test]
+    fn unit_conversion_truncates_toward_zero() {
+        let trunc = RoundingStrategy::ToZero;
+        assert_eq!(
+            BaseAmount::new(Decimal::new(1_999, 11)).to_units(trunc),
+            Some(BaseUnits::new(1))
+        );
+        assert_eq!(
+            BaseAmount::new(Decimal::new(-1_999, 11)).to_units(trunc),
+            Some(BaseUnits::new(-1))
+        );
+        assert_eq!(BaseAmount::new(Decimal::MAX).to_units(trunc), None);
+    }
 }
@@ -1,51 +1,1365 @@
-use crate::models::AumReport;
-
-pub fn render_table(report: &AumReport) {
-    println!("timestamp: {}", report.timestamp.to_rfc3339());
-    println!("aum_wbtc_u8: {}", report.calculation.aum_wbtc_u8);
-    println!("aum_wbtc: {}", report.calculation.aum_wbtc.round_dp(8));
-    println!("aum_btc: {}", report.calculation.aum_btc_18dp.round_dp(18));
-    println!(
-        "spot_total_btc: {}",
-        report.calculation.spot_total_btc.round_dp(18)
-    );
-    println!(
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::{self, Write};
+
+use rust_decimal::Decimal;
+use serde::ser::SerializeSeq;
+use serde::{Serialize, Serializer};
+
+use crate::config::{ContributionSort, DecimalJson, JsonShape, JsonStyle, NumberFormat};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    AumReport, BenchSummary, PriceBasis, PriceSource, SpotBalance, SpotContribution, UmPosition,
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TableOptions {
+    /// Uniform display precision; `None` keeps the per-field defaults.
+    pub decimal_places: Option<u32>,
+    pub sort_contributions: ContributionSort,
+    pub number_format: NumberFormat,
+    /// Prefixed to quote-currency values, e.g. `$`.
+    pub currency_symbol: Option<char>,
+    /// Highlights the AUM, changes and warnings with ANSI colors.
+    pub color: bool,
+}
+
+impl TableOptions {
+    fn round(&self, value: Decimal, default_dp: u32) -> String {
+        let rounded = value.round_dp(self.decimal_places.unwrap_or(default_dp));
+        self.group(rounded.to_string())
+    }
+
+    /// Like `round`, for values denominated in the quote currency.
+    fn quote(&self, value: Decimal, default_dp: u32) -> String {
+        let rounded = self.round(value, default_dp);
+        match self.currency_symbol {
+            Some(symbol) => match rounded.strip_prefix('-') {
+                Some(abs) => format!("-{symbol}{abs}"),
+                None => format!("{symbol}{rounded}"),
+            },
+            None => rounded,
+        }
+    }
+
+    fn int(&self, value: i128) -> String {
+        self.group(value.to_string())
+    }
+
+    /// Wraps `text` in the ANSI SGR `code` when color is on.
+    fn paint(&self, code: &str, text: impl fmt::Display) -> String {
+        if self.color {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn bold(&self, text: impl fmt::Display) -> String {
+        self.paint(BOLD, text)
+    }
+
+    /// Green for gains, red for losses.
+    fn change(&self, value: Decimal, text: impl fmt::Display) -> String {
+        match value.cmp(&Decimal::ZERO) {
+            Ordering::Greater => self.paint(GREEN, text),
+            Ordering::Less => self.paint(RED, text),
+            Ordering::Equal => text.to_string(),
+        }
+    }
+
+    fn group(&self, plain: String) -> String {
+        match self.number_format {
+            NumberFormat::Plain => plain,
+            NumberFormat::Grouped => group_thousands(&plain),
+        }
+    }
+}
+
+const BOLD: &str = "1";
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+
+/// Inserts `,` every three digits of the integer part: `-1234567.5` becomes
+/// `-1,234,567.5`.
+fn group_thousands(plain: &str) -> String {
+    let (sign, unsigned) = match plain.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", plain),
+    };
+    let (int_part, frac_part) = match unsigned.split_once('.') {
+        Some((int_part, frac)) => (int_part, Some(frac)),
+        None => (unsigned, None),
+    };
+
+    let mut grouped = String::with_capacity(plain.len() + int_part.len() / 3);
+    for (i, digit) in int_part.chars().enumerate() {
+        if i > 0 && (int_part.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+
+    match frac_part {
+        Some(frac) => format!("{sign}{grouped}.{frac}"),
+        None => format!("{sign}{grouped}"),
+    }
+}
+
+pub fn render_table(
+    report: &AumReport,
+    options: &TableOptions,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(out, "report_id: {}", report.report_id)?;
+    let b = report.calculation.base_asset.to_lowercase();
+    writeln!(out, "timestamp: {}", report.timestamp.to_rfc3339())?;
+    writeln!(
+        out,
+        "aum_w{b}_u8: {}",
+        options.bold(options.int(report.calculation.aum_base_u8.value()))
+    )?;
+    writeln!(
+        out,
+        "aum_w{b}: {}",
+        options.bold(options.round(report.calculation.aum_base.value(), 8))
+    )?;
+    writeln!(
+        out,
+        "aum_{b}: {}",
+        options.bold(options.round(report.calculation.aum_base_18dp.value(), 18))
+    )?;
+    if let Some(net) = report.calculation.aum_base_net {
+        writeln!(
+            out,
+            "aum_{b}_net: {} (haircut {})",
+            options.round(net.value(), 18),
+            options.round(report.calculation.haircut_base.value(), 18)
+        )?;
+    }
+    if !report.calculation.valuations.is_empty() {
+        writeln!(out, "valuations:")?;
+        let width = report
+            .calculation
+            .valuations
+            .iter()
+            .map(|v| v.currency.len())
+            .max()
+            .unwrap_or(0);
+        for valuation in &report.calculation.valuations {
+            writeln!(
+                out,
+                "  {:<width$} {} (rate {})",
+                valuation.currency,
+                options.round(valuation.aum, 8),
+                options.round(valuation.rate, 8)
+            )?;
+        }
+    }
+    writeln!(
+        out,
+        "spot_total_{b}: {}",
+        options.round(report.calculation.spot_total_base.value(), 18)
+    )?;
+    writeln!(
+        out,
         "pm_equity_usd: {}",
-        report.calculation.pm_equity_usd.round_dp(8)
-    );
-    println!(
-        "btc_usd_price: {}",
-        report.calculation.btc_usd_price.round_dp(8)
-    );
-
-    println!("spot_contributions:");
-    for spot in &report.calculation.spot_contributions {
-        println!(
-            "  - {} amount={} btc_to_asset={} amount_btc={}",
+        options.quote(report.calculation.pm_equity_usd, 8)
+    )?;
+    if report.data.classic_futures.is_some() {
+        writeln!(
+            out,
+            "classic_futures_equity_usd: {}",
+            options.quote(report.calculation.classic_futures_equity_usd, 8)
+        )?;
+    }
+    writeln!(
+        out,
+        "{b}_usd_price: {}{}",
+        options.quote(report.calculation.base_usd_price, 8),
+        if report.calculation.base_usd_price_overridden {
+            " (override)"
+        } else {
+            ""
+        }
+    )?;
+
+    writeln!(
+        out,
+        "um_notional_usd: {}",
+        options.quote(report.calculation.um_notional_usd, 8)
+    )?;
+    writeln!(
+        out,
+        "um_notional_{b}_included: {}",
+        options.round(report.calculation.um_notional_base_included.value(), 18)
+    )?;
+
+    writeln!(
+        out,
+        "liabilities_{b}: {}",
+        options.round(report.calculation.liabilities_base.value(), 18)
+    )?;
+    writeln!(out, "api_calls: {}", report.api_calls)?;
+
+    if let Some(delta) = &report.delta {
+        writeln!(
+            out,
+            "delta_since {}: aum_{b}={} aum_w{b}_u8={}",
+            delta.previous_timestamp.to_rfc3339(),
+            options.change(
+                delta.aum_base_change.value(),
+                options.round(delta.aum_base_change.value(), 18)
+            ),
+            options.int(delta.aum_base_u8_change.value())
+        )?;
+    }
+
+    if let Some(baseline) = &report.baseline {
+        let pct = baseline
+            .aum_base_change_pct
+            .map(|pct| format!(" ({}%)", pct.normalize()))
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "since_baseline {}: aum_{b}={}{pct} aum_w{b}_u8={}",
+            baseline.baseline_timestamp.to_rfc3339(),
+            options.change(
+                baseline.aum_base_change.value(),
+                options.round(baseline.aum_base_change.value(), 18)
+            ),
+            options.int(baseline.aum_base_u8_change.value())
+        )?;
+    }
+
+    if let Some(history) = &report.history {
+        writeln!(
+            out,
+            "history (last {}): aum_{b} min={} max={} mean={} | unimmr min={} max={} mean={}",
+            history.samples,
+            options.round(history.aum_base.min, 18),
+            options.round(history.aum_base.max, 18),
+            options.round(history.aum_base.mean, 18),
+            options.round(history.unimmr.min, 8),
+            options.round(history.unimmr.max, 8),
+            options.round(history.unimmr.mean, 8),
+        )?;
+    }
+
+    if let Some(income) = &report.income {
+        writeln!(
+            out,
+            "income {} to {} ({} records):",
+            income.from.to_rfc3339(),
+            income.to.to_rfc3339(),
+            income.records
+        )?;
+        for (asset, totals) in &income.by_asset {
+            writeln!(
+                out,
+                "  - {asset} realized_pnl={} funding_fee={} commission={} other={}",
+                options.change(totals.realized_pnl, options.round(totals.realized_pnl, 8)),
+                options.round(totals.funding_fee, 8),
+                options.round(totals.commission, 8),
+                options.round(totals.other, 8),
+            )?;
+        }
+    }
+
+    writeln!(out, "spot_contributions:")?;
+    let mut contributions = report.calculation.spot_contributions.clone();
+    sort_contributions(&mut contributions, options.sort_contributions);
+    for spot in &contributions {
+        let priced_as = spot
+            .priced_as
+            .as_ref()
+            .map(|a| format!(" priced_as={a}"))
+            .unwrap_or_default();
+        let source = match spot.price_source {
+            PriceSource::Exchange => String::new(),
+            source => format!(" source={}", source.as_str()),
+        };
+        let basis = match spot.price_basis {
+            Some(basis @ PriceBasis::Mid) => format!(" basis={}", basis.as_str()),
+            _ => String::new(),
+        };
+        let haircut = match (spot.haircut_pct, spot.amount_base_net) {
+            (Some(pct), Some(net)) => format!(
+                " haircut={}% net_{b}={}",
+                pct.normalize(),
+                options.round(net.value(), 18)
+            ),
+            _ => String::new(),
+        };
+        let split = report
+            .data
+            .spot_balances
+            .iter()
+            .find(|balance| balance.asset == spot.asset)
+            .and_then(|balance| Some((balance.free?, balance.locked?, balance.earn)))
+            .map(|(free, locked, earn)| {
+                let earn = earn
+                    .map(|earn| format!(" earn={}", options.round(earn, 18)))
+                    .unwrap_or_default();
+                format!(
+                    " free={} locked={}{earn}",
+                    options.round(free, 18),
+                    options.round(locked, 18)
+                )
+            })
+            .unwrap_or_default();
+        let price = match spot.price_source {
+            PriceSource::Unpriced => "n/a".to_string(),
+            _ => options.round(spot.base_to_asset_price, 18),
+        };
+        writeln!(
+            out,
+            "  - {} amount={}{split} {b}_to_asset={price} amount_{b}={}{}{}{}{}",
             spot.asset,
-            spot.amount.round_dp(18),
-            spot.btc_to_asset_price.round_dp(18),
-            spot.amount_btc.round_dp(18),
-        );
+            options.round(spot.amount, 18),
+            options.round(spot.amount_base.value(), 18),
+            priced_as,
+            source,
+            basis,
+            haircut,
+        )?;
+    }
+
+    if !report.calculation.dust_assets.is_empty() {
+        writeln!(
+            out,
+            "dust: {} {} excluded ({})",
+            options.round(report.calculation.dust_base.value(), 18),
+            report.calculation.base_asset,
+            report.calculation.dust_assets.join(", ")
+        )?;
+    }
+
+    if !report.calculation.fallback_assets.is_empty() {
+        writeln!(
+            out,
+            "fallback_priced: {}",
+            options.paint(YELLOW, report.calculation.fallback_assets.join(", "))
+        )?;
+    }
+
+    if !report.calculation.live_fallback_symbols.is_empty() {
+        writeln!(
+            out,
+            "live_priced: {} (no daily close for the date)",
+            options.paint(YELLOW, report.calculation.live_fallback_symbols.join(", "))
+        )?;
     }
 
-    println!("diagnostics:");
-    println!("  - unimmr={}", report.data.unimmr.round_dp(8));
-    println!(
-        "  - um_balance_usdt={}",
-        report.data.um_balance_usdt.round_dp(8)
-    );
-    println!(
-        "  - withdrawable_usdt={}",
-        report.data.withdrawable_usdt.round_dp(8)
-    );
-    println!("  - positions:");
-    for p in &report.data.positions {
-        println!(
-            "    * {} amount={} pnl={}",
+    if report.calculation.partial {
+        writeln!(
+            out,
+            "{}",
+            options.paint(
+                YELLOW,
+                "partial: true (some spot assets could not be priced)"
+            )
+        )?;
+        writeln!(out, "skipped_assets:")?;
+        for skipped in &report.calculation.skipped_assets {
+            writeln!(
+                out,
+                "  - {} amount={} reason={}",
+                skipped.asset,
+                options.round(skipped.amount, 18),
+                skipped.reason,
+            )?;
+        }
+    }
+
+    writeln!(out, "diagnostics:")?;
+    writeln!(out, "  - unimmr={}", options.round(report.data.unimmr, 8))?;
+    writeln!(
+        out,
+        "  - um_balance_usd={} ({})",
+        options.quote(report.data.um_balance_usd, 8),
+        report.data.settlement_asset
+    )?;
+    writeln!(
+        out,
+        "  - withdrawable_usd={}",
+        options.quote(report.data.withdrawable_usd, 8)
+    )?;
+    if let Some(account) = &report.data.classic_futures {
+        writeln!(
+            out,
+            "  - classic_futures: wallet_usd={} unrealized_pnl_usd={}",
+            options.quote(account.wallet_balance_usd, 8),
+            options.quote(account.unrealized_pnl_usd, 8)
+        )?;
+        for balance in &account.balances {
+            writeln!(
+                out,
+                "    * {} balance={}",
+                balance.asset,
+                options.round(balance.amount, 18)
+            )?;
+        }
+    }
+    if !report.data.margin_liabilities.is_empty() {
+        writeln!(out, "  - margin_liabilities:")?;
+        for l in &report.data.margin_liabilities {
+            writeln!(
+                out,
+                "    * {} amount={}",
+                l.asset,
+                options.round(l.amount, 18)
+            )?;
+        }
+    }
+    writeln!(out, "  - positions:")?;
+    print_positions(&report.data.positions, options, "    * ", out)
+}
+
+/// Four-line digest of `report`: timestamp, AUM in the base asset and in
+/// `quote_currency`, and uniMMR.
+pub fn render_summary(
+    report: &AumReport,
+    quote_currency: &str,
+    options: &TableOptions,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let calc = &report.calculation;
+    writeln!(out, "timestamp: {}", report.timestamp.to_rfc3339())?;
+    writeln!(
+        out,
+        "aum_{}: {}",
+        calc.base_asset.to_lowercase(),
+        options.bold(options.round(calc.aum_base.value(), 8))
+    )?;
+    writeln!(
+        out,
+        "aum_{}: {}",
+        quote_currency.to_lowercase(),
+        options.bold(options.quote(calc.aum_base_18dp.value() * calc.base_usd_price, 2))
+    )?;
+    writeln!(out, "unimmr: {}", options.round(report.data.unimmr, 8))?;
+    Ok(())
+}
+
+/// Writes the step-by-step derivation of the AUM, for `--explain`.
+pub fn render_explain(
+    report: &AumReport,
+    options: &TableOptions,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    write!(out, "{}", explain(report, options))
+}
+
+/// Formula-annotated derivation of the AUM from the report's own figures:
+/// each spot asset's value and price route, PM equity, then the total and
+/// its conversion to whole units.
+pub fn explain(report: &AumReport, options: &TableOptions) -> String {
+    use std::fmt::Write;
+
+    let calc = &report.calculation;
+    let base = calc.base_asset.as_str();
+    let b = base.to_lowercase();
+    let mut out = String::new();
+    let mut line = |text: String| {
+        let _ = writeln!(out, "{text}");
+    };
+
+    line(format!(
+        "AUM derivation for report {} at {}",
+        report.report_id,
+        report.timestamp.to_rfc3339()
+    ));
+    line(String::new());
+    line(format!(
+        "1. spot balances (amount ÷ {base}_to_asset = amount_{b})"
+    ));
+    let skip_reason = |asset: &str| {
+        calc.skipped_assets
+            .iter()
+            .find(|skipped| skipped.asset == asset)
+            .map_or("price unavailable", |skipped| skipped.reason.as_str())
+    };
+    for spot in &calc.spot_contributions {
+        if spot.price_source == PriceSource::Unpriced {
+            line(format!(
+                "   {}: {} unpriced = 0 {base}  [{}]",
+                spot.asset,
+                options.round(spot.amount, 18),
+                skip_reason(&spot.asset),
+            ));
+            continue;
+        }
+        line(format!(
+            "   {}: {} ÷ {} = {} {base}  [{}]",
+            spot.asset,
+            options.round(spot.amount, 18),
+            options.round(spot.base_to_asset_price, 18),
+            options.round(spot.amount_base.value(), 18),
+            price_route(report, spot),
+        ));
+    }
+    let valued_at_zero = |asset: &str| {
+        calc.spot_contributions
+            .iter()
+            .any(|spot| spot.asset == asset && spot.price_source == PriceSource::Unpriced)
+    };
+    for skipped in calc
+        .skipped_assets
+        .iter()
+        .filter(|skipped| !valued_at_zero(&skipped.asset))
+    {
+        line(format!(
+            "   {}: {} not valued ({})",
+            skipped.asset,
+            options.round(skipped.amount, 18),
+            skipped.reason
+        ));
+    }
+    if !calc.dust_assets.is_empty() {
+        line(format!(
+            "   dust below threshold, excluded: {} {base} ({})",
+            options.round(calc.dust_base.value(), 18),
+            calc.dust_assets.join(", ")
+        ));
+    }
+    line(format!(
+        "   spot_total_{b} = {}",
+        options.round(calc.spot_total_base.value(), 18)
+    ));
+    line(String::new());
+
+    let pm_equity_base = calc
+        .pm_equity_usd
+        .checked_div(calc.base_usd_price)
+        .unwrap_or_default();
+    let base_usd_symbol = calc
+        .prices
+        .keys()
+        .find(|symbol| {
+            symbol
+                .strip_prefix(base)
+                .is_some_and(|quote| quote.starts_with("USD"))
+        })
+        .map(|symbol| format!(" ({symbol})"))
+        .unwrap_or_default();
+    line("2. portfolio margin equity (USD ÷ base/USD price)".to_string());
+    line(format!(
+        "   pm_equity_{b} = {} ÷ {}{base_usd_symbol} = {}",
+        options.quote(calc.pm_equity_usd, 8),
+        options.quote(calc.base_usd_price, 8),
+        options.round(pm_equity_base, 18)
+    ));
+    let classic_base = calc
+        .classic_futures_equity_usd
+        .checked_div(calc.base_usd_price)
+        .unwrap_or_default();
+    if !calc.classic_futures_equity_usd.is_zero() {
+        line(format!(
+            "   classic_futures_equity_{b} = {} ÷ {} = {}",
+            options.quote(calc.classic_futures_equity_usd, 8),
+            options.quote(calc.base_usd_price, 8),
+            options.round(classic_base, 18)
+        ));
+    }
+    if !calc.um_notional_base_included.is_zero() {
+        line(format!(
+            "   um_notional_{b}_included = (notional − pnl) ÷ {} = {}",
+            options.quote(calc.base_usd_price, 8),
+            options.round(calc.um_notional_base_included.value(), 18)
+        ));
+    }
+    if !calc.liabilities_base.is_zero() {
+        line(format!(
+            "   liabilities_{b} = {}",
+            options.round(calc.liabilities_base.value(), 18)
+        ));
+    }
+    line(String::new());
+
+    line("3. total".to_string());
+    let mut terms = vec![
+        format!("pm {}", options.round(pm_equity_base, 18)),
+        format!("spot {}", options.round(calc.spot_total_base.value(), 18)),
+    ];
+    if !classic_base.is_zero() {
+        terms.push(format!("classic {}", options.round(classic_base, 18)));
+    }
+    if !calc.um_notional_base_included.is_zero() {
+        terms.push(format!(
+            "um {}",
+            options.round(calc.um_notional_base_included.value(), 18)
+        ));
+    }
+    let liabilities = if calc.liabilities_base.is_zero() {
+        String::new()
+    } else {
+        format!(
+            " − liabilities {}",
+            options.round(calc.liabilities_base.value(), 18)
+        )
+    };
+    line(format!(
+        "   aum_{b} = {}{liabilities} = {}",
+        terms.join(" + "),
+        options.round(calc.aum_base_18dp.value(), 18)
+    ));
+    line(format!(
+        "   aum_w{b}_u8 = aum_{b} × 10^8, rounded to whole units = {}",
+        options.int(calc.aum_base_u8.value())
+    ));
+    line(format!(
+        "   aum_w{b} = aum_w{b}_u8 ÷ 10^8 = {}",
+        options.round(calc.aum_base.value(), 8)
+    ));
+    if let Some(net) = calc.aum_base_net {
+        line(format!(
+            "   aum_{b}_net = aum_{b} − haircuts {} = {}",
+            options.round(calc.haircut_base.value(), 18),
+            options.round(net.value(), 18)
+        ));
+    }
+    out
+}
+
+/// How `spot` was priced, inferred from the symbols recorded on the report.
+fn price_route(report: &AumReport, spot: &SpotContribution) -> String {
+    let (asset, priced_as) = (spot.asset.as_str(), spot.priced_as.as_deref());
+    let calc = &report.calculation;
+    let base = calc.base_asset.as_str();
+    let pricing_asset = priced_as.unwrap_or(asset).to_uppercase();
+    let alias = priced_as
+        .map(|a| format!("priced as {a}, "))
+        .unwrap_or_default();
+
+    if spot.price_source == PriceSource::Override {
+        return format!("{alias}price override");
+    }
+    if pricing_asset == base {
+        return format!("{alias}base asset");
+    }
+    if pricing_asset.strip_prefix('W') == Some(base) {
+        return format!("{alias}wrapped base asset, pegged 1:1");
+    }
+    if calc.fallback_assets.iter().any(|a| a == asset) {
+        return format!("{alias}fallback route through the USD quote");
+    }
+    let direct = format!("{base}{pricing_asset}");
+    if let Some(price) = calc.prices.get(&direct) {
+        return format!("{alias}direct {direct} {}", price.normalize());
+    }
+    let inverse = format!("{pricing_asset}{base}");
+    if let Some(price) = calc.prices.get(&inverse) {
+        return format!("{alias}inverse 1 ÷ {inverse} {}", price.normalize());
+    }
+    format!("{alias}route not recorded")
+}
+
+/// Renders UM positions alone, for the `positions` subcommand.
+pub fn render_positions(
+    positions: &[UmPosition],
+    options: &TableOptions,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(out, "positions:")?;
+    print_positions(positions, options, "  - ", out)?;
+    let total_pnl: Decimal = positions.iter().map(|p| p.pnl).sum();
+    writeln!(out, "total_pnl: {}", options.quote(total_pnl, 8))?;
+    Ok(())
+}
+
+/// Lists spot balances and UM positions, then the matching config lines, for
+/// the `discover` subcommand.
+pub fn render_discover(
+    balances: &[SpotBalance],
+    positions: &[UmPosition],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(out, "spot_balances:")?;
+    for balance in balances {
+        writeln!(out, "  - {} {}", balance.asset, balance.amount.normalize())?;
+    }
+    writeln!(out, "um_positions:")?;
+    for position in positions {
+        writeln!(
+            out,
+            "  - {} {}",
+            position.symbol,
+            position.amount.normalize()
+        )?;
+    }
+    let assets: Vec<_> = balances.iter().map(|b| b.asset.as_str()).collect();
+    let symbols: Vec<_> = positions.iter().map(|p| p.symbol.as_str()).collect();
+    writeln!(out)?;
+    writeln!(out, "BINANCE_SPOT_ASSETS={}", assets.join(","))?;
+    writeln!(out, "BINANCE_UM_POSITIONS={}", symbols.join(","))
+}
+
+/// Renders the withdrawable amount alone, for the `liquidity` subcommand.
+pub fn render_liquidity(
+    withdrawable_usd: Decimal,
+    withdrawable_base: Decimal,
+    base_asset: &str,
+    options: &TableOptions,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "withdrawable_usd: {}",
+        options.quote(withdrawable_usd, 8)
+    )?;
+    writeln!(
+        out,
+        "withdrawable_{}: {}",
+        base_asset.to_lowercase(),
+        options.round(withdrawable_base, 8)
+    )?;
+    Ok(())
+}
+
+pub fn render_bench(summary: &BenchSummary, out: &mut impl Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "iterations: {} errors: {} ({:.1}%)",
+        summary.iterations,
+        summary.errors,
+        summary.error_rate() * 100.0
+    )?;
+    for pct in [50, 95, 99] {
+        match summary.percentile(pct) {
+            Some(latency) => writeln!(out, "p{pct}: {}ms", latency.as_millis())?,
+            None => writeln!(out, "p{pct}: n/a")?,
+        }
+    }
+    Ok(())
+}
+
+fn print_positions(
+    positions: &[UmPosition],
+    options: &TableOptions,
+    prefix: &str,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    for p in positions {
+        writeln!(
+            out,
+            "{prefix}{} amount={} pnl={} mark_price={} notional={}",
             p.symbol,
-            p.amount.round_dp(18),
-            p.pnl.round_dp(18)
+            options.round(p.amount, 18),
+            options.round(p.pnl, 18),
+            options.round(p.mark_price, 8),
+            options.quote(p.notional, 8)
+        )?;
+    }
+    Ok(())
+}
+
+/// Sorts contributions in place; ties are broken by asset name.
+pub fn sort_contributions(contributions: &mut [SpotContribution], order: ContributionSort) {
+    match order {
+        ContributionSort::Config => {}
+        ContributionSort::Value => contributions.sort_by(|a, b| {
+            b.amount_base
+                .cmp(&a.amount_base)
+                .then_with(|| a.asset.cmp(&b.asset))
+        }),
+        ContributionSort::Asset => contributions.sort_by(|a, b| a.asset.cmp(&b.asset)),
+    }
+}
+
+/// Renders `report` through a MiniJinja `template`; every report field is
+/// available by its JSON name.
+pub fn render_template(report: &AumReport, template: &str) -> AppResult<String> {
+    minijinja::Environment::new()
+        .render_str(template, report)
+        .map_err(|err| AppError::InvalidConfig {
+            field: "TEMPLATE",
+            reason: err.to_string(),
+        })
+}
+
+/// How `render_json` lays out a report.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonOptions {
+    pub sort: ContributionSort,
+    pub style: JsonStyle,
+    pub shape: JsonShape,
+    pub decimals: DecimalJson,
+}
+
+impl JsonOptions {
+    fn encode<T: Serialize>(&self, value: &T) -> serde_json::Result<String> {
+        match self.decimals {
+            DecimalJson::String => to_json(value, self.style),
+            DecimalJson::Number => {
+                let mut value = serde_json::to_value(value)?;
+                decimal_strings_to_numbers(&mut value);
+                to_json(&value, self.style)
+            }
+        }
+    }
+}
+
+/// Writes `report` as JSON, with spot contributions in `options.sort` order.
+///
+/// A `Flat` report is an array of rows; compact flat output puts one row per
+/// line (JSONL) instead.
+pub fn render_json(
+    report: &AumReport,
+    options: &JsonOptions,
+    out: &mut impl Write,
+) -> AppResult<()> {
+    let mut report = report.clone();
+    sort_contributions(&mut report.calculation.spot_contributions, options.sort);
+    match (options.shape, options.style) {
+        (JsonShape::Nested, _) => writeln!(out, "{}", options.encode(&report)?)?,
+        (JsonShape::Flat, JsonStyle::Pretty) => {
+            writeln!(out, "{}", options.encode(&FlatReport(&report))?)?
+        }
+        (JsonShape::Flat, JsonStyle::Compact) => {
+            let flat = FlatReport(&report);
+            for row in flat.spot_rows() {
+                writeln!(out, "{}", options.encode(&row)?)?;
+            }
+            for row in flat.position_rows() {
+                writeln!(out, "{}", options.encode(&row)?)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces every string holding a decimal number with a JSON number.
+/// `Decimal` serializes as a string, and no other report string is purely
+/// numeric: assets, symbols, ids and timestamps all contain other characters.
+/// The `raw` upstream bodies are left exactly as Binance sent them.
+fn decimal_strings_to_numbers(value: &mut serde_json::Value) {
+    use serde_json::Value;
+    match value {
+        Value::String(text) => {
+            let number = text
+                .parse::<Decimal>()
+                .ok()
+                .and_then(|d| d.to_string().parse::<f64>().ok())
+                .and_then(serde_json::Number::from_f64);
+            if let Some(number) = number {
+                *value = Value::Number(number);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(decimal_strings_to_numbers),
+        Value::Object(map) => map
+            .iter_mut()
+            .filter(|(key, _)| key.as_str() != "raw")
+            .for_each(|(_, value)| decimal_strings_to_numbers(value)),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// Serializes a report as a sequence of rows, one per spot contribution and
+/// UM position, instead of the derived nested object.
+pub struct FlatReport<'a>(pub &'a AumReport);
+
+/// One flat row: the report it belongs to, what kind of row it is, and the
+/// row's own fields.
+#[derive(Serialize)]
+struct FlatRow<'a, T: Serialize> {
+    report_id: uuid::Uuid,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    base_asset: &'a str,
+    kind: &'static str,
+    #[serde(flatten)]
+    row: &'a T,
+}
+
+impl<'a> FlatReport<'a> {
+    fn row<T: Serialize>(&self, kind: &'static str, row: &'a T) -> FlatRow<'a, T> {
+        FlatRow {
+            report_id: self.0.report_id,
+            timestamp: self.0.timestamp,
+            base_asset: &self.0.calculation.base_asset,
+            kind,
+            row,
+        }
+    }
+
+    fn spot_rows(&self) -> impl Iterator<Item = FlatRow<'a, SpotContribution>> + '_ {
+        let contributions = &self.0.calculation.spot_contributions;
+        contributions
+            .iter()
+            .map(|c| self.row("spot_contribution", c))
+    }
+
+    fn position_rows(&self) -> impl Iterator<Item = FlatRow<'a, UmPosition>> + '_ {
+        let positions = &self.0.data.positions;
+        positions.iter().map(|p| self.row("position", p))
+    }
+}
+
+impl Serialize for FlatReport<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = self.0.calculation.spot_contributions.len() + self.0.data.positions.len();
+        let mut seq = serializer.serialize_seq(Some(len))?;
+        for row in self.spot_rows() {
+            seq.serialize_element(&row)?;
+        }
+        for row in self.position_rows() {
+            seq.serialize_element(&row)?;
+        }
+        seq.end()
+    }
+}
+
+/// Writes `report` as MessagePack, with spot contributions in `sort` order.
+/// Structs are encoded as maps keyed by field name, so consumers decode it
+/// like the JSON report.
+pub fn render_msgpack(
+    report: &AumReport,
+    sort: ContributionSort,
+    out: &mut impl Write,
+) -> AppResult<()> {
+    let mut report = report.clone();
+    sort_contributions(&mut report.calculation.spot_contributions, sort);
+    rmp_serde::encode::write_named(out, &report)?;
+    out.flush()?;
+    Ok(())
+}
+
+pub fn to_json<T: Serialize>(value: &T, style: JsonStyle) -> serde_json::Result<String> {
+    match style {
+        JsonStyle::Pretty => serde_json::to_string_pretty(value),
+        JsonStyle::Compact => serde_json::to_string(value),
+    }
+}
+
+/// Renders one `key=value` line per report for log-based scrapers.
+pub fn render_logfmt(
+    report: &AumReport,
+    quote_currency: &str,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let calc = &report.calculation;
+    writeln!(
+        out,
+        "ts={} report_id={} aum_{}={} aum_{}={} unimmr={} withdrawable_usdt={}",
+        report.timestamp.to_rfc3339(),
+        report.report_id,
+        calc.base_asset.to_lowercase(),
+        calc.aum_base_18dp.value().normalize(),
+        quote_currency.to_lowercase(),
+        (calc.aum_base_18dp.value() * calc.base_usd_price)
+            .round_dp(8)
+            .normalize(),
+        report.data.unimmr.normalize(),
+        report.data.withdrawable_usd.normalize(),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::models::{BaseAmount, SkippedAsset};
+
+    fn contribution(asset: &str, amount_base: i64) -> SpotContribution {
+        SpotContribution {
+            asset: asset.to_string(),
+            amount: Decimal::ONE,
+            base_to_asset_price: Decimal::ONE,
+            amount_base: BaseAmount::new(Decimal::from(amount_base)),
+            priced_as: None,
+            price_source: PriceSource::Exchange,
+            price_basis: None,
+            haircut_pct: None,
+            amount_base_net: None,
+        }
+    }
+
+    /// 2 BTC of PM equity plus 20 ETH priced through ETHBTC.
+    fn sample_report() -> AumReport {
+        serde_json::from_str(
+            r#"{
+                "timestamp": "2024-01-01T00:00:00Z",
+                "data": {
+                    "unimmr": "5", "positions": [], "um_balance_usdt": "0",
+                    "spot_balances": [], "pm_account_actual_equity": "200000",
+                    "withdrawable_usdt": "0", "margin_liabilities": []
+                },
+                "calculation": {
+                    "aum_btc_18dp": "2.5", "aum_wbtc_u8": 250000000, "aum_wbtc": "2.5",
+                    "spot_total_btc": "0.5", "pm_equity_usd": "200000",
+                    "btc_usd_price": "100000", "um_notional_usd": "0",
+                    "um_notional_btc_included": "0", "liabilities_btc": "0",
+                    "spot_contributions": [{
+                        "asset": "ETH", "amount": "20",
+                        "btc_to_asset_price": "40", "amount_btc": "0.5"
+                    }],
+                    "partial": false, "skipped_assets": [],
+                    "prices": {"BTCUSDT": "100000", "ETHBTC": "0.025"}
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn grouped_numbers_keep_sign_and_fraction() {
+        assert_eq!(group_thousands("0.5"), "0.5");
+        assert_eq!(group_thousands("999"), "999");
+        assert_eq!(group_thousands("1234567"), "1,234,567");
+        assert_eq!(group_thousands("-1234.00001"), "-1,234.00001");
+
+        let options = TableOptions {
+            number_format: NumberFormat::Grouped,
+            currency_symbol: Some('$'),
+            ..TableOptions::default()
+        };
+        assert_eq!(options.quote(Decimal::new(-12345678, 2), 2), "-$123,456.78");
+        assert_eq!(options.int(200_000_000), "200,000,000");
+    }
+
+    #[test]
+    fn compact_json_is_a_single_line() {
+        let value = serde_json::json!({"aum": {"base": "1.5", "units": 150000000}});
+        let compact = to_json(&value, JsonStyle::Compact).unwrap();
+        assert_eq!(compact, r#"{"aum":{"base":"1.5","units":150000000}}"#);
+        assert!(to_json(&value, JsonStyle::Pretty).unwrap().contains('\n'));
+    }
+
+    #[test]
+    fn template_reads_report_fields() {
+        let report: AumReport = serde_json::from_str(
+            r#"{
+                "timestamp": "2024-01-01T00:00:00Z",
+                "api_calls": 3,
+                "data": {
+                    "unimmr": "5", "positions": [], "um_balance_usdt": "0",
+                    "spot_balances": [], "pm_account_actual_equity": "0",
+                    "withdrawable_usdt": "0", "margin_liabilities": []
+                },
+                "calculation": {
+                    "aum_btc_18dp": "2", "aum_wbtc_u8": 200000000, "aum_wbtc": "2",
+                    "spot_total_btc": "0", "pm_equity_usd": "0", "btc_usd_price": "1",
+                    "um_notional_usd": "0", "um_notional_btc_included": "0",
+                    "liabilities_btc": "0", "spot_contributions": [],
+                    "partial": false, "skipped_assets": []
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let rendered = render_template(
+            &report,
+            "{{ calculation.base_asset }} {{ calculation.aum_wbtc_u8 }} calls={{ api_calls }}",
+        )
+        .unwrap();
+        assert_eq!(rendered, "BTC 200000000 calls=3");
+
+        let err = render_template(&report, "{{ api_calls | nope }}").expect_err("unknown filter");
+        assert_eq!(err.kind(), "invalid_config");
+    }
+
+    #[test]
+    fn explain_names_the_price_route_and_totals() {
+        let report = sample_report();
+
+        let text = explain(&report, &TableOptions::default());
+        assert!(text.contains("ETH: 20 ÷ 40 = 0.5 BTC  [inverse 1 ÷ ETHBTC 0.025]"));
+        assert!(text.contains("pm_equity_btc = 200000 ÷ 100000 (BTCUSDT) = 2"));
+        assert!(text.contains("aum_btc = pm 2 + spot 0.5 = 2.5"));
+        assert!(text.contains("aum_wbtc_u8 = aum_btc × 10^8, rounded to whole units = 250000000"));
+    }
+
+    #[test]
+    fn unpriced_assets_are_not_shown_as_exchange_prices() {
+        let mut report = sample_report();
+        let calc = &mut report.calculation;
+        calc.spot_contributions.push(SpotContribution {
+            amount: Decimal::TEN,
+            base_to_asset_price: Decimal::ZERO,
+            price_source: PriceSource::Unpriced,
+            ..contribution("NEW", 0)
+        });
+        calc.skipped_assets.push(SkippedAsset {
+            asset: "NEW".to_string(),
+            amount: Decimal::TEN,
+            reason: "price unavailable; valued at zero".to_string(),
+        });
+        calc.partial = true;
+
+        let text = explain(&report, &TableOptions::default());
+        assert!(text.contains("NEW: 10 unpriced = 0 BTC  [price unavailable; valued at zero]"));
+        assert!(!text.contains("÷ 0"));
+        assert!(!text.contains("not valued"));
+
+        let table = rendered(|out| render_table(&report, &TableOptions::default(), out));
+        assert!(table.contains("  - NEW amount=10 btc_to_asset=n/a amount_btc=0 source=unpriced\n"));
+    }
+
+    fn rendered(render: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut out = Vec::new();
+        render(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn color_highlights_the_aum_only_when_enabled() {
+        let report = sample_report();
+        let plain = rendered(|out| render_summary(&report, "USD", &TableOptions::default(), out));
+        assert!(!plain.contains('\x1b'));
+
+        let options = TableOptions {
+            color: true,
+            ..TableOptions::default()
+        };
+        let colored = rendered(|out| render_summary(&report, "USD", &options, out));
+        assert!(colored.contains("aum_btc: \x1b[1m2.5\x1b[0m\n"));
+        assert_eq!(options.change(-Decimal::ONE, "-1"), "\x1b[31m-1\x1b[0m");
+        assert_eq!(options.change(Decimal::ZERO, "0"), "0");
+    }
+
+    #[test]
+    fn non_btc_base_relabels_the_table_but_keeps_json_keys() {
+        let mut report = sample_report();
+        report.calculation.base_asset = "ETH".to_string();
+
+        let table = rendered(|out| render_table(&report, &TableOptions::default(), out));
+        assert!(table.contains("aum_weth_u8: 250000000"));
+        assert!(table.contains("aum_eth: 2.5"));
+
+        let mut out = Vec::new();
+        render_json(&report, &JsonOptions::default(), &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let calculation = &json["calculation"];
+        assert_eq!(calculation["base_asset"], "ETH");
+        assert_eq!(calculation["aum_wbtc_u8"], 250_000_000);
+        assert_eq!(calculation["aum_btc_18dp"], "2.5");
+        assert!(calculation.get("aum_base_18dp").is_none());
+        assert_eq!(json["data"]["um_balance_usdt"], "0");
+        assert_eq!(json["data"]["withdrawable_usdt"], "0");
+        assert!(json["data"].get("withdrawable_usd").is_none());
+    }
+
+    #[test]
+    fn table_matches_golden_output() {
+        let report = sample_report();
+        let table = rendered(|out| render_table(&report, &TableOptions::default(), out));
+        let expected = [
+            "report_id: 00000000-0000-0000-0000-000000000000",
+            "timestamp: 2024-01-01T00:00:00+00:00",
+            "aum_wbtc_u8: 250000000",
+            "aum_wbtc: 2.5",
+            "aum_btc: 2.5",
+            "spot_total_btc: 0.5",
+            "pm_equity_usd: 200000",
+            "btc_usd_price: 100000",
+            "um_notional_usd: 0",
+            "um_notional_btc_included: 0",
+            "liabilities_btc: 0",
+            "api_calls: 0",
+            "spot_contributions:",
+            "  - ETH amount=20 btc_to_asset=40 amount_btc=0.5",
+            "diagnostics:",
+            "  - unimmr=5",
+            "  - um_balance_usd=0 (USDT)",
+            "  - withdrawable_usd=0",
+            "  - positions:",
+            "",
+        ]
+        .join("\n");
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn summary_and_logfmt_match_golden_output() {
+        let report = sample_report();
+        let options = TableOptions::default();
+        let summary = rendered(|out| render_summary(&report, "USD", &options, out));
+        assert_eq!(
+            summary,
+            "timestamp: 2024-01-01T00:00:00+00:00\naum_btc: 2.5\naum_usd: 250000.0\nunimmr: 5\n"
+        );
+        let logfmt = rendered(|out| render_logfmt(&report, "USD", out));
+        assert_eq!(
+            logfmt,
+            "ts=2024-01-01T00:00:00+00:00 report_id=00000000-0000-0000-0000-000000000000 \
+             aum_btc=2.5 aum_usd=250000 unimmr=5 withdrawable_usdt=0\n"
         );
     }
+
+    #[test]
+    fn json_follows_the_contribution_sort() {
+        let mut report = sample_report();
+        report.calculation.spot_contributions =
+            vec![contribution("ETH", 1), contribution("BTC", 2)];
+        let mut out = Vec::new();
+        let options = JsonOptions {
+            sort: ContributionSort::Asset,
+            style: JsonStyle::Compact,
+            ..JsonOptions::default()
+        };
+        render_json(&report, &options, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["calculation"]["spot_contributions"][0]["asset"], "BTC");
+        assert_eq!(out.iter().filter(|&&b| b == b'\n').count(), 1);
+    }
+
+    #[test]
+    fn decimal_json_number_turns_decimal_strings_into_numbers() {
+        let report = sample_report();
+        let render = |decimals| {
+            let options = JsonOptions {
+                style: JsonStyle::Compact,
+                decimals,
+                ..JsonOptions::default()
+            };
+            let mut out = Vec::new();
+            render_json(&report, &options, &mut out).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&out).unwrap()
+        };
+
+        let strings = render(DecimalJson::String);
+        assert_eq!(strings["calculation"]["aum_wbtc"], "2.5");
+
+        let numbers = render(DecimalJson::Number);
+        assert_eq!(numbers["calculation"]["aum_wbtc"], 2.5);
+        assert_eq!(numbers["calculation"]["prices"]["ETHBTC"], 0.025);
+        assert_eq!(numbers["calculation"]["aum_wbtc_u8"], 250_000_000);
+        assert_eq!(
+            numbers["calculation"]["spot_contributions"][0]["asset"],
+            "ETH"
+        );
+        assert_eq!(numbers["timestamp"], "2024-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn decimal_json_number_keeps_raw_bodies_verbatim() {
+        let mut report = sample_report();
+        report.raw = Some(BTreeMap::from([(
+            "/papi/v1/account".to_string(),
+            serde_json::json!({"uniMMR": "5.0", "actualEquity": "250000"}),
+        )]));
+        let options = JsonOptions {
+            decimals: DecimalJson::Number,
+            ..JsonOptions::default()
+        };
+        let mut out = Vec::new();
+        render_json(&report, &options, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(json["calculation"]["aum_wbtc"], 2.5);
+        assert_eq!(json["raw"]["/papi/v1/account"]["uniMMR"], "5.0");
+        assert_eq!(json["raw"]["/papi/v1/account"]["actualEquity"], "250000");
+    }
+
+    #[test]
+    fn flat_json_tags_each_row_with_the_report() {
+        let mut report = sample_report();
+        report.data.positions = vec![UmPosition {
+            symbol: "BTCUSDT".to_string(),
+            amount: Decimal::ONE,
+            pnl: Decimal::ZERO,
+            mark_price: Decimal::from(100_000),
+            notional: Decimal::from(100_000),
+        }];
+        let id = report.report_id.to_string();
+
+        let mut out = Vec::new();
+        let options = JsonOptions {
+            style: JsonStyle::Compact,
+            shape: JsonShape::Flat,
+            ..JsonOptions::default()
+        };
+        render_json(&report, &options, &mut out).unwrap();
+        let rows: Vec<serde_json::Value> = out
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["kind"], "spot_contribution");
+        assert_eq!(rows[0]["asset"], "ETH");
+        assert_eq!(rows[0]["amount_btc"], "0.5");
+        assert_eq!(rows[1]["kind"], "position");
+        assert_eq!(rows[1]["symbol"], "BTCUSDT");
+        for row in &rows {
+            assert_eq!(row["report_id"], id.as_str());
+            assert_eq!(row["timestamp"], "2024-01-01T00:00:00Z");
+        }
+
+        let mut pretty = Vec::new();
+        let options = JsonOptions {
+            shape: JsonShape::Flat,
+            ..JsonOptions::default()
+        };
+        render_json(&report, &options, &mut pretty).unwrap();
+        let array: serde_json::Value = serde_json::from_slice(&pretty).unwrap();
+        assert_eq!(array.as_array().map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn msgpack_round_trips_and_is_smaller_than_json() {
+        let report = sample_report();
+        let mut packed = Vec::new();
+        render_msgpack(&report, ContributionSort::Config, &mut packed).unwrap();
+        let decoded: AumReport = rmp_serde::from_slice(&packed).unwrap();
+        assert_eq!(decoded.report_id, report.report_id);
+        assert_eq!(
+            decoded.calculation.aum_base_u8,
+            report.calculation.aum_base_u8
+        );
+        assert_eq!(decoded.calculation.spot_contributions[0].asset, "ETH");
+
+        let json = to_json(&report, JsonStyle::Compact).unwrap();
+        assert!(packed.len() < json.len());
+    }
+
+    #[test]
+    fn discover_prints_copyable_config_lines() {
+        let balances = [
+            SpotBalance::new("BTC", Decimal::new(50, 2)),
+            SpotBalance::new("USDT", Decimal::from(1000)),
+        ];
+        let positions = [UmPosition {
+            symbol: "ETHUSDT".to_string(),
+            amount: Decimal::new(-15, 1),
+            pnl: Decimal::ZERO,
+            mark_price: Decimal::ZERO,
+            notional: Decimal::ZERO,
+        }];
+        let text = rendered(|out| render_discover(&balances, &positions, out));
+        let expected = [
+            "spot_balances:",
+            "  - BTC 0.5",
+            "  - USDT 1000",
+            "um_positions:",
+            "  - ETHUSDT -1.5",
+            "",
+            "BINANCE_SPOT_ASSETS=BTC,USDT",
+            "BINANCE_UM_POSITIONS=ETHUSDT",
+            "",
+        ]
+        .join("\n");
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn value_sort_is_descending_with_asset_tiebreak() {
+        let mut contributions = vec![
+            contribution("USDT", 1),
+            contribution("SOL", 2),
+            contribution("ETH", 2),
+            contribution("BTC", 5),
+        ];
+
+        sort_contributions(&mut contributions, ContributionSort::Value);
+        let assets: Vec<_> = contributions.iter().map(|c| c.asset.as_str()).collect();
+        assert_eq!(assets, ["BTC", "ETH", "SOL", "USDT"]);
+
+        sort_contributions(&mut contributions, ContributionSort::Asset);
+        assert_eq!(contributions[0].asset, "BTC");
+        assert_eq!(contributions[3].asset, "USDT");
+    }
 }
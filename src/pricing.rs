@@ -1,61 +1,219 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
 use async_trait::async_trait;
+use chrono::{DateTime, Days, NaiveDate, Utc};
 use rust_decimal::Decimal;
+use tracing::warn;
 
 use crate::binance_client::BinanceClient;
 use crate::error::{AppError, AppResult};
+use crate::models::{normalize_symbol, PriceBasis};
 
 #[async_trait]
 pub trait PriceProvider {
-    async fn btc_to_usd(&self) -> AppResult<Decimal>;
-    async fn btc_to_asset(&self, asset: &str) -> AppResult<Decimal>;
+    /// Asset the AUM is denominated in.
+    fn base_asset(&self) -> &str {
+        "BTC"
+    }
+
+    /// Which live ticker price exchange prices are read from; `None` when
+    /// they are not live tickers (e.g. daily closes).
+    fn price_basis(&self) -> Option<PriceBasis> {
+        Some(PriceBasis::Last)
+    }
+
+    async fn base_to_usd(&self) -> AppResult<Decimal>;
+    async fn base_to_asset(&self, asset: &str) -> AppResult<Decimal>;
+
+    /// Secondary, less direct route for assets `base_to_asset` cannot price.
+    /// Only used for assets on the `--fallback-assets` allowlist.
+    async fn base_to_asset_fallback(&self, asset: &str) -> AppResult<Decimal> {
+        Err(AppError::MissingPrice(asset.to_string()))
+    }
+
+    /// Drains the raw symbol prices looked up since the last call. Providers
+    /// that do not quote symbols return an empty map.
+    fn take_symbol_prices(&self) -> BTreeMap<String, Quote> {
+        BTreeMap::new()
+    }
+}
+
+/// One symbol price and the time it is valid for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quote {
+    pub price: Decimal,
+    /// When the price was observed: the request time for a live ticker, the
+    /// end of the day for a daily close.
+    pub as_of: DateTime<Utc>,
+    /// A live ticker standing in for a daily close the requested date does
+    /// not have.
+    pub live_fallback: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct BinancePriceProvider {
     client: BinanceClient,
     quote_currency: String,
+    /// Spot quote used in symbols when `quote_currency` is `USD`.
+    usd_quote: String,
+    base_asset: String,
+    /// Prices at this day's daily close instead of the current ticker.
+    price_date: Option<NaiveDate>,
+    price_basis: PriceBasis,
+    /// Every symbol price looked up, until drained by `take_symbol_prices`.
+    symbol_prices: Arc<Mutex<BTreeMap<String, Quote>>>,
 }
 
 impl BinancePriceProvider {
     pub fn new(client: BinanceClient, quote_currency: String) -> Self {
         Self {
             client,
-            quote_currency,
+            quote_currency: normalize_symbol(&quote_currency),
+            usd_quote: "USDT".to_string(),
+            base_asset: "BTC".to_string(),
+            price_date: None,
+            price_basis: PriceBasis::Last,
+            symbol_prices: Arc::default(),
+        }
+    }
+
+    /// Overrides the `USD` → `USDT` translation, e.g. with `USD` on Binance.US.
+    pub fn with_usd_quote(mut self, usd_quote: String) -> Self {
+        self.usd_quote = normalize_symbol(&usd_quote);
+        self
+    }
+
+    pub fn with_base_asset(mut self, base_asset: String) -> Self {
+        self.base_asset = normalize_symbol(&base_asset);
+        self
+    }
+
+    /// Uses the daily close of `date`, falling back to the current price for
+    /// symbols without a kline that day.
+    pub fn at_date(mut self, date: NaiveDate) -> Self {
+        self.price_date = Some(date);
+        self
+    }
+
+    /// Reads current prices from `basis`: the last trade or the book mid.
+    pub fn with_price_basis(mut self, basis: PriceBasis) -> Self {
+        self.price_basis = basis;
+        self
+    }
+
+    /// Spot symbol behind `base_to_usd`.
+    pub fn base_usd_symbol(&self) -> String {
+        format!("{}{}", self.base_asset, self.symbol_quote())
+    }
+
+    /// Quote currency as it appears in spot symbols. Quoting in the base
+    /// asset itself would make `base_to_usd` 1 and count USD equity as base
+    /// units, so the USD legs then go through `usd_quote` as for `USD`.
+    fn symbol_quote(&self) -> &str {
+        if self.quote_currency == "USD" || self.quote_currency == self.base_asset {
+            &self.usd_quote
+        } else {
+            &self.quote_currency
+        }
+    }
+
+    async fn price(&self, symbol: &str) -> AppResult<Decimal> {
+        let quote = self.lookup(symbol).await?;
+        self.symbol_prices
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(symbol.to_string(), quote);
+        Ok(quote.price)
+    }
+
+    async fn lookup(&self, symbol: &str) -> AppResult<Quote> {
+        let live = |live_fallback| {
+            move |price| Quote {
+                price,
+                as_of: Utc::now(),
+                live_fallback,
+            }
+        };
+        let Some(date) = self.price_date else {
+            return self.current_price(symbol).await.map(live(false));
+        };
+        match self.client.daily_close(symbol, date).await? {
+            Some(price) => {
+                let day_end = date
+                    .checked_add_days(Days::new(1))
+                    .map(|next| next.and_time(chrono::NaiveTime::MIN).and_utc())
+                    .unwrap_or(DateTime::<Utc>::MAX_UTC);
+                Ok(Quote {
+                    price,
+                    as_of: day_end.min(Utc::now()),
+                    live_fallback: false,
+                })
+            }
+            None => {
+                warn!(symbol, %date, "no daily close for date; using the current price");
+                self.current_price(symbol).await.map(live(true))
+            }
+        }
+    }
+
+    async fn current_price(&self, symbol: &str) -> AppResult<Decimal> {
+        match self.price_basis {
+            PriceBasis::Last => self.client.ticker_price(symbol).await,
+            PriceBasis::Mid => self.client.book_ticker_mid(symbol).await,
         }
     }
 
     async fn ticker_or_none(&self, symbol: &str) -> AppResult<Option<Decimal>> {
-        match self.client.ticker_price(symbol).await {
+        match self.price(symbol).await {
             Ok(price) => Ok(Some(price)),
-            Err(AppError::BinanceApiMessage { code, .. }) if code == -1121 => Ok(None),
+            // Unknown symbol, or an empty side of the book at the mid basis.
+            Err(AppError::BinanceApiMessage { code: -1121, .. } | AppError::MissingPrice(_)) => {
+                Ok(None)
+            }
             Err(err) => Err(err),
         }
     }
 }
 
+/// Absolute deviation of `price` from `reference`, in percent of `reference`.
+pub fn deviation_pct(price: Decimal, reference: Decimal) -> Option<Decimal> {
+    let diff = (price - reference).abs();
+    diff.checked_mul(Decimal::ONE_HUNDRED)?
+        .checked_div(reference)
+        .map(|pct| pct.round_dp(4))
+}
+
 #[async_trait]
 impl PriceProvider for BinancePriceProvider {
-    async fn btc_to_usd(&self) -> AppResult<Decimal> {
-        let symbol = format!("BTC{}", self.quote_currency);
-        self.client.ticker_price(&symbol).await
+    fn base_asset(&self) -> &str {
+        &self.base_asset
     }
 
-    async fn btc_to_asset(&self, asset: &str) -> AppResult<Decimal> {
-        let asset = asset.to_uppercase();
-        if asset == "BTC" {
+    fn price_basis(&self) -> Option<PriceBasis> {
+        self.price_date.is_none().then_some(self.price_basis)
+    }
+
+    async fn base_to_usd(&self) -> AppResult<Decimal> {
+        self.price(&self.base_usd_symbol()).await
+    }
+
+    async fn base_to_asset(&self, asset: &str) -> AppResult<Decimal> {
+        let asset = normalize_symbol(asset);
+        if asset == self.base_asset {
             return Ok(Decimal::ONE);
         }
 
-        if asset == self.quote_currency {
-            return self.btc_to_usd().await;
+        if asset == self.symbol_quote() {
+            return self.base_to_usd().await;
         }
 
-        let direct_symbol = format!("BTC{}", asset);
+        let direct_symbol = format!("{}{}", self.base_asset, asset);
         if let Some(price) = self.ticker_or_none(&direct_symbol).await? {
             return Ok(price);
         }
 
-        let inverse_symbol = format!("{}BTC", asset);
+        let inverse_symbol = format!("{}{}", asset, self.base_asset);
         if let Some(price) = self.ticker_or_none(&inverse_symbol).await? {
             if price.is_zero() {
                 return Err(AppError::MissingPrice(asset));
@@ -65,4 +223,227 @@ impl PriceProvider for BinancePriceProvider {
 
         Err(AppError::MissingPrice(asset))
     }
+
+    /// Two hops through the USD quote: `base/USD ÷ asset/USD`.
+    async fn base_to_asset_fallback(&self, asset: &str) -> AppResult<Decimal> {
+        let asset = normalize_symbol(asset);
+        let quote = self.symbol_quote();
+        if asset == quote || quote == self.base_asset {
+            return Err(AppError::MissingPrice(asset));
+        }
+        let Some(asset_usd) = self.ticker_or_none(&format!("{asset}{quote}")).await? else {
+            return Err(AppError::MissingPrice(asset));
+        };
+        let base_usd = self.base_to_usd().await?;
+        base_usd
+            .checked_div(asset_usd)
+            .ok_or(AppError::MissingPrice(asset))
+    }
+
+    fn take_symbol_prices(&self) -> BTreeMap<String, Quote> {
+        std::mem::take(&mut *self.symbol_prices.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deviation_is_relative_to_the_reference() {
+        let pct = |price, reference| deviation_pct(Decimal::from(price), Decimal::from(reference));
+        assert_eq!(pct(110, 100), Some(Decimal::TEN));
+        assert_eq!(pct(90, 100), Some(Decimal::TEN));
+        assert_eq!(pct(1, 3), Some(Decimal::new(666667, 4)));
+        assert_eq!(pct(1, 0), None);
+    }
+
+    #[tokio::test]
+    async fn quote_equal_to_base_prices_usd_legs_through_the_usd_quote() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/price"))
+            .and(query_param("symbol", "BTCUSDT"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"symbol":"BTCUSDT","price":"100000.0"}"#),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = BinanceClient::with_http_client(
+            reqwest::Client::new(),
+            "secret".to_string(),
+            server.uri(),
+            server.uri(),
+        );
+        let provider = BinancePriceProvider::new(client, "BTC".to_string());
+
+        assert_eq!(provider.base_usd_symbol(), "BTCUSDT");
+        assert_eq!(
+            provider.base_to_usd().await.unwrap(),
+            Decimal::from(100_000)
+        );
+        assert_eq!(provider.base_to_asset("BTC").await.unwrap(), Decimal::ONE);
+    }
+
+    #[tokio::test]
+    async fn usd_quote_is_looked_up_as_usdt() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/price"))
+            .and(query_param("symbol", "BTCUSDT"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"symbol":"BTCUSDT","price":"100000.0"}"#),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = BinanceClient::with_http_client(
+            reqwest::Client::new(),
+            "secret".to_string(),
+            server.uri(),
+            server.uri(),
+        );
+        let provider = BinancePriceProvider::new(client, "USD".to_string());
+        assert_eq!(
+            provider.base_to_usd().await.unwrap(),
+            Decimal::from(100_000)
+        );
+        let prices = provider.take_symbol_prices();
+        assert_eq!(prices.len(), 1);
+        assert_eq!(prices["BTCUSDT"].price, Decimal::from(100_000));
+        assert!(provider.take_symbol_prices().is_empty());
+    }
+
+    #[tokio::test]
+    async fn mid_basis_prices_off_the_book_ticker() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/bookTicker"))
+            .and(query_param("symbol", "BTCUSDT"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"symbol":"BTCUSDT","bidPrice":"99990.00","bidQty":"1","askPrice":"100010.00","askQty":"1"}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/bookTicker"))
+            .and(query_param("symbol", "BTCXYZ"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"symbol":"BTCXYZ","bidPrice":"0.00","bidQty":"0","askPrice":"5.00","askQty":"1"}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/bookTicker"))
+            .and(query_param("symbol", "XYZBTC"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"symbol":"XYZBTC","bidPrice":"0.00","bidQty":"0","askPrice":"0.00","askQty":"0"}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = BinanceClient::with_http_client(
+            reqwest::Client::new(),
+            "secret".to_string(),
+            server.uri(),
+            server.uri(),
+        );
+        let provider =
+            BinancePriceProvider::new(client, "USD".to_string()).with_price_basis(PriceBasis::Mid);
+        assert_eq!(provider.price_basis(), Some(PriceBasis::Mid));
+        let daily = provider
+            .clone()
+            .at_date(NaiveDate::from_ymd_opt(2024, 6, 30).unwrap());
+        assert_eq!(daily.price_basis(), None);
+        assert_eq!(
+            provider.base_to_usd().await.unwrap(),
+            Decimal::from(100_000)
+        );
+        assert!(matches!(
+            provider.base_to_asset("XYZ").await,
+            Err(AppError::MissingPrice(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn dated_provider_uses_daily_close() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/klines"))
+            .and(query_param("symbol", "BTCUSDT"))
+            .and(query_param("startTime", "1719705600000"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"[[1719705600000,"60900","63000","60700","62700","100",1719791999999,"0",1,"0","0","0"]]"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = BinanceClient::with_http_client(
+            reqwest::Client::new(),
+            "secret".to_string(),
+            server.uri(),
+            server.uri(),
+        );
+        let provider = BinancePriceProvider::new(client, "USD".to_string())
+            .at_date(NaiveDate::from_ymd_opt(2024, 6, 30).unwrap());
+        assert_eq!(provider.base_to_usd().await.unwrap(), Decimal::from(62_700));
+        let quote = provider.take_symbol_prices()["BTCUSDT"];
+        assert_eq!(quote.as_of.to_rfc3339(), "2024-07-01T00:00:00+00:00");
+        assert!(!quote.live_fallback);
+    }
+
+    #[tokio::test]
+    async fn dated_provider_flags_a_live_fallback() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/klines"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/ticker/price"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"symbol":"BTCUSDT","price":"100000"}"#),
+            )
+            .mount(&server)
+            .await;
+
+        let client = BinanceClient::with_http_client(
+            reqwest::Client::new(),
+            "secret".to_string(),
+            server.uri(),
+            server.uri(),
+        );
+        let provider = BinancePriceProvider::new(client, "USD".to_string())
+            .at_date(NaiveDate::from_ymd_opt(2024, 6, 30).unwrap());
+        assert_eq!(
+            provider.base_to_usd().await.unwrap(),
+            Decimal::from(100_000)
+        );
+        assert!(provider.take_symbol_prices()["BTCUSDT"].live_fallback);
+    }
 }
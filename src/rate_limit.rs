@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Timelike, Utc};
+use rand::Rng;
+
+/// Picks the next loop sleep: the configured interval, extended to the end of
+/// the current one-minute weight window while used weight is at or above
+/// `threshold_pct` of `weight_limit`.
+pub fn next_interval(
+    configured: Duration,
+    used_weight: u32,
+    weight_limit: u32,
+    threshold_pct: u8,
+    now: DateTime<Utc>,
+) -> Duration {
+    if weight_limit == 0 {
+        return configured;
+    }
+
+    let used_pct = u64::from(used_weight) * 100 / u64::from(weight_limit);
+    if used_pct < u64::from(threshold_pct) {
+        return configured;
+    }
+
+    let until_window_reset = Duration::from_secs(u64::from(60 - now.second()));
+    configured.max(until_window_reset)
+}
+
+/// Spreads `interval` by a random ± `jitter_pct` percent so that instances
+/// started together drift apart instead of polling in lockstep.
+pub fn jittered<R: Rng>(interval: Duration, jitter_pct: u8, rng: &mut R) -> Duration {
+    if jitter_pct == 0 {
+        return interval;
+    }
+    let factor = f64::from(jitter_pct.min(100)) / 100.0;
+    interval.mul_f64(rng.random_range(1.0 - factor..=1.0 + factor))
+}
+
+/// Random initial offset in `0..=max`.
+pub fn start_delay<R: Rng>(max: Duration, rng: &mut R) -> Duration {
+    if max.is_zero() {
+        return max;
+    }
+    Duration::from_millis(rng.random_range(0..=max.as_millis() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn backs_off_only_under_pressure() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 15).unwrap();
+        let configured = Duration::from_secs(5);
+
+        assert_eq!(next_interval(configured, 1000, 6000, 80, now), configured);
+        assert_eq!(
+            next_interval(configured, 5000, 6000, 80, now),
+            Duration::from_secs(45)
+        );
+        assert_eq!(
+            next_interval(Duration::from_secs(120), 5000, 6000, 80, now),
+            Duration::from_secs(120)
+        );
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let interval = Duration::from_secs(30);
+
+        assert_eq!(jittered(interval, 0, &mut rng), interval);
+        for _ in 0..1000 {
+            let slept = jittered(interval, 20, &mut rng);
+            assert!(slept >= Duration::from_secs(24) && slept <= Duration::from_secs(36));
+
+            let delay = start_delay(Duration::from_secs(5), &mut rng);
+            assert!(delay <= Duration::from_secs(5));
+        }
+    }
+}
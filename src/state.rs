@@ -0,0 +1,99 @@
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use tracing::{info, warn};
+
+use crate::error::AppResult;
+use crate::models::AumReport;
+
+pub type SharedReportState = Arc<RwLock<ReportState>>;
+
+#[derive(Debug, Default)]
+pub struct ReportState {
+    pub latest_report: Option<AumReport>,
+    pub has_succeeded: bool,
+    pub consecutive_failures: u32,
+}
+
+impl ReportState {
+    pub fn shared() -> SharedReportState {
+        Arc::new(RwLock::new(Self::default()))
+    }
+
+    pub fn record_success(&mut self, report: AumReport) {
+        self.latest_report = Some(report);
+        self.has_succeeded = true;
+        self.consecutive_failures = 0;
+    }
+
+    pub fn record_failure(&mut self) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+    }
+}
+
+/// Loads the report persisted by a previous run. A missing or unreadable file
+/// is not an error: the caller simply starts without a previous report.
+pub fn load_report(path: &Path) -> Option<AumReport> {
+    let raw = match std::fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            info!(path = %path.display(), "no state file yet; starting fresh");
+            return None;
+        }
+        Err(err) => {
+            warn!(path = %path.display(), error = %err, "failed to read state file; starting fresh");
+            return None;
+        }
+    };
+
+    match serde_json::from_str(&raw) {
+        Ok(report) => Some(report),
+        Err(err) => {
+            warn!(path = %path.display(), error = %err, "ignoring corrupt state file; starting fresh");
+            None
+        }
+    }
+}
+
+/// Loads a baseline report saved earlier. Unlike the state file the path is
+/// explicit, so a missing file is an error; a report that no longer parses
+/// only disables the comparison.
+pub fn load_baseline(path: &Path) -> AppResult<Option<AumReport>> {
+    let raw = std::fs::read_to_string(path)?;
+    match serde_json::from_str(&raw) {
+        Ok(report) => Ok(Some(report)),
+        Err(err) => {
+            warn!(path = %path.display(), error = %err, "ignoring unreadable baseline report");
+            Ok(None)
+        }
+    }
+}
+
+/// Writes `report` to `path` via a temporary file so a crash mid-write never
+/// leaves a truncated state file behind.
+pub fn save_report(path: &Path, report: &AumReport) -> AppResult<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec(report)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_or_corrupt_state_file_starts_fresh() {
+        let dir = std::env::temp_dir().join(format!("aum-state-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+
+        let path = dir.join("missing.json");
+        assert!(load_report(&path).is_none());
+
+        let path = dir.join("corrupt.json");
+        std::fs::write(&path, "{not json").expect("write corrupt file");
+        assert!(load_report(&path).is_none());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
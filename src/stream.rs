@@ -0,0 +1,700 @@
+use std::collections::{BTreeMap, HashMap};
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, StreamExt};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rust_decimal::Decimal;
+use tracing::{error, field, info, info_span, warn, Instrument, Span};
+use uuid::Uuid;
+
+use crate::aum::{add_valuations, calculate_aum_with_options, check_bounds, AumOptions};
+use crate::binance_client::{BinanceClient, ClientOptions, FetchOptions};
+use crate::clock::{MonotonicTimeSource, SystemTimeSource, TimeSource};
+use crate::config::{
+    AppConfig, AumBoundsAction, ContributionSort, IncomeWindow, PriceDeviationAction,
+    TimeSourceKind,
+};
+use crate::error::{AppError, AppResult};
+use crate::history::ReportHistory;
+use crate::models::{
+    normalize_symbol, AumCalculation, AumInputs, AumReport, BenchSummary, BinanceData,
+    IncomeSummary, SpotBalance,
+};
+use crate::pricing::{self, BinancePriceProvider};
+use crate::rate_limit;
+use crate::state;
+
+/// Builds the API client described by `config`.
+pub fn build_client(config: &AppConfig) -> AppResult<BinanceClient> {
+    let options = ClientOptions {
+        timeout: config.timeout,
+        user_agent: config.user_agent.clone(),
+        ca_cert: config.ca_cert.clone(),
+        insecure_skip_tls_verify: config.insecure_skip_tls_verify,
+        field_aliases: config.field_aliases.clone(),
+    };
+    let time_source: Arc<dyn TimeSource> = match config.time_source {
+        TimeSourceKind::System => Arc::new(SystemTimeSource),
+        TimeSourceKind::Monotonic => Arc::new(MonotonicTimeSource::new()?),
+    };
+    let client = BinanceClient::new_with_options(
+        config.api_key.clone(),
+        config.api_secret.clone(),
+        config.api_base_url.clone(),
+        config.papi_base_url.clone(),
+        &options,
+    )?
+    .with_fapi_base_url(config.fapi_base_url.clone())
+    .with_time_source(time_source);
+    let client = match config.max_rps {
+        Some(max_rps) => client.with_max_rps(max_rps),
+        None => client,
+    };
+    Ok(if config.include_raw {
+        client.with_raw_capture()
+    } else {
+        client
+    })
+}
+
+/// Price provider for the configured base asset and quote currency.
+pub fn price_provider(client: &BinanceClient, config: &AppConfig) -> BinancePriceProvider {
+    BinancePriceProvider::new(client.clone(), config.quote_currency.clone())
+        .with_usd_quote(config.usd_quote.clone())
+        .with_base_asset(config.base_asset.clone())
+        .with_price_basis(config.price_basis)
+}
+
+pub fn aum_options(config: &AppConfig) -> AumOptions {
+    let mut price_overrides = config.price_overrides.clone();
+    if let Some(price) = config.base_usd_override {
+        // Balances of the quote asset itself are priced through the same
+        // base/USD symbol, so they follow the override too.
+        price_overrides
+            .entry(config.spot_quote().to_string())
+            .or_insert(price);
+    }
+    AumOptions {
+        missing_price_policy: config.missing_price_policy,
+        include_um_notional: config.include_um_notional,
+        asset_aliases: config.asset_aliases.clone(),
+        dust_threshold: config.dust_threshold,
+        satoshi_rounding: config.satoshi_rounding,
+        fallback_assets: config.fallback_assets.clone(),
+        haircuts: config.haircuts.clone(),
+        price_overrides,
+        base_usd_override: config.base_usd_override,
+    }
+}
+
+pub fn fetch_options(config: &AppConfig) -> FetchOptions {
+    FetchOptions {
+        include_earn: config.include_earn,
+        include_margin_liabilities: config.include_margin_liabilities,
+        settlement_asset: config.settlement_asset.clone(),
+        show_zero_balances: config.show_zero_balances,
+        split_free_locked: config.split_free_locked,
+        include_classic_futures: config.include_classic_futures,
+        portfolio_margin: config.exchange.has_portfolio_margin(),
+    }
+}
+
+/// Fails with [`AppError::StalePrices`] when `report` used a price observed
+/// more than `max` before or after its balances were read.
+pub fn check_price_age(report: &AumReport, max: Option<Duration>) -> AppResult<()> {
+    let (Some(max), Some(age_secs)) = (max, report.price_age_secs) else {
+        return Ok(());
+    };
+    let age = Duration::from_secs(age_secs);
+    if age > max {
+        return Err(AppError::StalePrices { age, max });
+    }
+    Ok(())
+}
+
+/// Applies `--stable-order`. Under `--sort-contributions config` spot
+/// balances keep the order they were fetched in, which that sort renders.
+pub fn stable_order(data: &mut BinanceData, config: &AppConfig) {
+    if !config.stable_order {
+        return;
+    }
+    let fetched = (config.sort_contributions == ContributionSort::Config)
+        .then(|| std::mem::take(&mut data.spot_balances));
+    data.sort_by_name();
+    if let Some(spot_balances) = fetched {
+        data.spot_balances = spot_balances;
+    }
+}
+
+/// Drops the positions and spot balances named in the denylists, after the
+/// selections have been applied, and logs what was left out. Margin
+/// liabilities are kept: dropping one would overstate AUM.
+pub fn exclude(data: &mut BinanceData, positions: &[String], assets: &[String]) {
+    let mut excluded_positions = Vec::new();
+    data.positions.retain(|position| {
+        let keep = !positions.contains(&normalize_symbol(&position.symbol));
+        if !keep {
+            excluded_positions.push(position.symbol.clone());
+        }
+        keep
+    });
+    let mut excluded_assets = Vec::new();
+    data.spot_balances.retain(|balance| {
+        let keep = !assets.contains(&normalize_symbol(&balance.asset));
+        if !keep {
+            excluded_assets.push(balance.asset.clone());
+        }
+        keep
+    });
+    if !excluded_positions.is_empty() || !excluded_assets.is_empty() {
+        info!(
+            positions = ?excluded_positions,
+            assets = ?excluded_assets,
+            "excluded by denylist"
+        );
+    }
+}
+
+/// Enforces `--max-spot-assets` before any spot balance is priced. When
+/// truncating, the balances are ranked by their value in `usd_quote`, read
+/// from one request for every spot ticker.
+pub async fn enforce_max_spot_assets(
+    client: &BinanceClient,
+    config: &AppConfig,
+    data: &mut BinanceData,
+) -> AppResult<()> {
+    let over = data.spot_balances.len() > config.max_spot_assets;
+    let usd_prices = if over && config.truncate_spot_assets {
+        let quote = &config.usd_quote;
+        let tickers = client.ticker_prices(&[]).await?;
+        data.spot_balances
+            .iter()
+            .filter_map(|balance| {
+                let asset = normalize_symbol(&balance.asset);
+                let price = if &asset == quote {
+                    Some(Decimal::ONE)
+                } else {
+                    tickers.get(&format!("{asset}{quote}")).copied()
+                };
+                price.map(|price| (balance.asset.clone(), price))
+            })
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    limit_spot_assets(
+        data,
+        config.max_spot_assets,
+        config.truncate_spot_assets,
+        &usd_prices,
+    )
+}
+
+/// Enforces `max` on the number of spot balances. With `truncate`, keeps
+/// the `max` most valuable at `usd_prices` (per unit, by asset; unpriced
+/// assets rank last) in their original order, and warns instead of failing.
+pub fn limit_spot_assets(
+    data: &mut BinanceData,
+    max: usize,
+    truncate: bool,
+    usd_prices: &HashMap<String, Decimal>,
+) -> AppResult<()> {
+    let count = data.spot_balances.len();
+    if count <= max {
+        return Ok(());
+    }
+    if !truncate {
+        return Err(AppError::TooManySpotAssets { count, max });
+    }
+    let value = |balance: &SpotBalance| {
+        usd_prices
+            .get(&balance.asset)
+            .and_then(|price| price.checked_mul(balance.amount))
+            .unwrap_or_default()
+    };
+    let mut ranked: Vec<usize> = (0..count).collect();
+    ranked.sort_by_key(|&i| std::cmp::Reverse(value(&data.spot_balances[i])));
+    let mut kept = vec![false; count];
+    for &i in &ranked[..max] {
+        kept[i] = true;
+    }
+    let mut kept = kept.into_iter();
+    let mut dropped = Vec::new();
+    data.spot_balances.retain(|balance| {
+        let keep = kept.next() == Some(true);
+        if !keep {
+            dropped.push(balance.asset.clone());
+        }
+        keep
+    });
+    warn!(
+        count,
+        max,
+        dropped = ?dropped,
+        "too many spot balances; keeping the most valuable"
+    );
+    Ok(())
+}
+
+/// Fails with [`AppError::PriceJump`] for the symbol whose price moved the
+/// most since `previous`, when that is more than `max_pct`. Symbols only in
+/// one snapshot are ignored.
+pub fn check_price_jump(
+    previous: Option<&BTreeMap<String, Decimal>>,
+    current: &BTreeMap<String, Decimal>,
+    max_pct: Option<Decimal>,
+) -> AppResult<()> {
+    let (Some(previous), Some(max_pct)) = (previous, max_pct) else {
+        return Ok(());
+    };
+    let largest = current
+        .iter()
+        .filter_map(|(symbol, &price)| {
+            let old = *previous.get(symbol)?;
+            Some((symbol, old, price, pricing::deviation_pct(price, old)?))
+        })
+        .max_by_key(|&(.., deviation_pct)| deviation_pct);
+    match largest {
+        Some((symbol, previous, price, deviation_pct)) if deviation_pct > max_pct => {
+            Err(AppError::PriceJump {
+                symbol: symbol.clone(),
+                previous,
+                price,
+                deviation_pct,
+                max_pct,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Yields one report per loop interval, or a single report with `once`.
+///
+/// The next fetch only starts once the consumer polls for it, after the
+/// interval (extended by weight and error backoff, then jittered) has
+/// elapsed. Fetch errors are yielded and the stream carries on; a setup
+/// error (client construction, unreadable baseline, Binance still
+/// unreachable after `wait_for_api`) is yielded last.
+pub fn report_stream(config: AppConfig) -> impl Stream<Item = AppResult<AumReport>> {
+    stream::unfold(Step::Start(Box::new(config)), |step| async move {
+        match step {
+            Step::Start(config) => match Reporter::start(*config).await {
+                Ok(mut reporter) => {
+                    let result = reporter.next_report().await;
+                    Some((result, reporter.into_step()))
+                }
+                Err(err) => Some((Err(err), Step::Done)),
+            },
+            Step::Running(mut reporter) => {
+                reporter.wait().await;
+                let result = reporter.next_report().await;
+                Some((result, Step::Running(reporter)))
+            }
+            Step::Done => None,
+        }
+    })
+}
+
+/// Drives [`report_stream`] to completion, handing every iteration to
+/// `on_report`: the report, or the error the fetch failed with. The next
+/// iteration only starts once `on_report` has finished.
+///
+/// Fetch errors are logged, then `--loop-error-policy` decides whether the
+/// loop carries on or returns the error. Otherwise the stream only ends
+/// after a setup error (or after the single iteration with `once`), and
+/// that last result is returned.
+pub async fn run_loop<F>(config: AppConfig, mut on_report: F) -> AppResult<()>
+where
+    F: AsyncFnMut(Result<&AumReport, &AppError>),
+{
+    let policy = config.loop_error_policy;
+    let mut reports = pin!(report_stream(config));
+    let mut outcome = Ok(());
+    while let Some(result) = reports.next().await {
+        if let Err(err) = &result {
+            match err {
+                AppError::Maintenance { .. } => warn!(error = %err, "binance is under maintenance"),
+                _ => error!(error = %err, "failed to fetch/compute report"),
+            }
+        }
+        on_report(result.as_ref()).await;
+        outcome = result.map(drop);
+        if let Err(err) = &outcome {
+            if policy.stops_on(err) {
+                error!(error = %err, ?policy, "stopping the loop");
+                break;
+            }
+        }
+    }
+    outcome
+}
+
+/// Runs fetch and compute `iterations` times back to back, without the loop
+/// interval or rendering, and times each run. Failed runs are counted and
+/// logged; only a setup error fails the bench.
+pub async fn bench(config: AppConfig, iterations: u32) -> AppResult<BenchSummary> {
+    let reporter = Reporter::new(config)?;
+    reporter.wait_for_api().await?;
+
+    let mut errors = 0;
+    let mut latencies = Vec::with_capacity(iterations as usize);
+    for iteration in 0..iterations {
+        let started = Instant::now();
+        match reporter.fetch_and_compute(Uuid::new_v4()).await {
+            Ok(_) => latencies.push(started.elapsed()),
+            Err(err) => {
+                errors += 1;
+                warn!(iteration, error = %err, "bench iteration failed");
+            }
+        }
+    }
+    latencies.sort();
+    Ok(BenchSummary {
+        iterations,
+        errors,
+        latencies,
+    })
+}
+
+enum Step {
+    Start(Box<AppConfig>),
+    Running(Box<Reporter>),
+    Done,
+}
+
+struct Reporter {
+    client: BinanceClient,
+    prices: BinancePriceProvider,
+    config: AppConfig,
+    previous: Option<AumReport>,
+    /// Symbol prices of the last report this loop produced.
+    last_prices: Option<BTreeMap<String, Decimal>>,
+    baseline: Option<AumReport>,
+    history: Option<ReportHistory>,
+    rng: StdRng,
+    backoff: Option<(Duration, &'static str)>,
+}
+
+impl Reporter {
+    fn new(config: AppConfig) -> AppResult<Self> {
+        let client = build_client(&config)?;
+        let prices = price_provider(&client, &config);
+        let previous = config.state_file.as_deref().and_then(state::load_report);
+        let baseline = match &config.baseline {
+            Some(path) => state::load_baseline(path)?,
+            None => None,
+        };
+        let history = config
+            .history_window
+            .filter(|_| !config.once)
+            .map(ReportHistory::new);
+
+        Ok(Self {
+            client,
+            prices,
+            config,
+            previous,
+            last_prices: None,
+            baseline,
+            history,
+            rng: StdRng::from_os_rng(),
+            backoff: None,
+        })
+    }
+
+    /// Builds the reporter, then waits for the API and the start delay.
+    async fn start(config: AppConfig) -> AppResult<Self> {
+        let mut reporter = Self::new(config)?;
+        reporter.wait_for_api().await?;
+        reporter.delay_start().await;
+        Ok(reporter)
+    }
+
+    async fn wait_for_api(&self) -> AppResult<()> {
+        if self.config.wait_for_api.is_zero() {
+            return Ok(());
+        }
+        self.client.wait_for_api(self.config.wait_for_api).await
+    }
+
+    fn into_step(self) -> Step {
+        if self.config.once {
+            Step::Done
+        } else {
+            Step::Running(Box::new(self))
+        }
+    }
+
+    async fn delay_start(&mut self) {
+        if self.config.once {
+            return;
+        }
+        let delay = rate_limit::start_delay(self.config.start_delay, &mut self.rng);
+        if !delay.is_zero() {
+            info!(delay_ms = delay.as_millis() as u64, "delaying first fetch");
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn next_report(&mut self) -> AppResult<AumReport> {
+        let report_id = Uuid::new_v4();
+        let result = self
+            .fetch_and_compute(report_id)
+            .instrument(info_span!(
+                "report",
+                %report_id,
+                positions = field::Empty,
+                spot_assets = field::Empty,
+            ))
+            .await;
+
+        self.backoff = match &result {
+            Err(AppError::Maintenance { .. }) => {
+                Some((self.config.maintenance_backoff, "binance maintenance"))
+            }
+            Err(AppError::RateLimited {
+                retry_after: Some(wait),
+                ..
+            }) => Some((*wait, "rate limited by binance")),
+            _ => None,
+        };
+
+        let mut report = result?;
+        // Replaced even when the check trips, so a price that holds at its
+        // new level is accepted on the next run.
+        let last_prices = self.last_prices.replace(report.calculation.prices.clone());
+        if let Err(err) = check_price_jump(
+            last_prices.as_ref(),
+            &report.calculation.prices,
+            self.config.max_price_deviation_between_runs,
+        ) {
+            match self.config.price_deviation_action {
+                PriceDeviationAction::Skip => return Err(err),
+                PriceDeviationAction::Warn => {
+                    warn!(error = %err, "price jumped since the last run")
+                }
+            }
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.push(&report);
+            report.history = history.summary();
+        }
+        self.previous = Some(report.clone());
+        Ok(report)
+    }
+
+    async fn fetch_and_compute(&self, report_id: Uuid) -> AppResult<AumReport> {
+        if self.config.include_raw {
+            // Drops bodies a failed fetch left behind.
+            self.client.take_raw_responses();
+        }
+        let inputs = self.fetch_inputs().await?;
+        let mut report =
+            inputs.into_report(report_id, self.previous.as_ref(), self.baseline.as_ref());
+        if self.baseline.is_some() && report.baseline.is_none() {
+            warn!("baseline report uses a different base asset; skipping comparison");
+        }
+        check_price_age(&report, self.config.max_price_age)?;
+        if self.config.include_raw {
+            report.raw = Some(self.client.take_raw_responses());
+        }
+        if let Some(window) = self.config.income {
+            let calls_before = self.client.api_calls();
+            report.income = Some(self.fetch_income(window, report.timestamp).await?);
+            report.api_calls += self.client.api_calls().wrapping_sub(calls_before);
+        }
+        Ok(report)
+    }
+
+    /// Sums UM income over `window`, ending at `until` unless the window
+    /// has a fixed end.
+    async fn fetch_income(
+        &self,
+        window: IncomeWindow,
+        until: DateTime<Utc>,
+    ) -> AppResult<IncomeSummary> {
+        let (from, to) = match window {
+            IncomeWindow::SincePrevious => {
+                let from = self.previous.as_ref().map_or_else(
+                    || until - chrono::Duration::from_std(self.config.interval).unwrap_or_default(),
+                    |previous| previous.timestamp,
+                );
+                (from, until)
+            }
+            IncomeWindow::Range { from, to } => (from, to.unwrap_or(until)),
+        };
+        let records = self.client.um_income(from, to).await?;
+        Ok(IncomeSummary::from_records(from, to, &records))
+    }
+
+    async fn fetch_inputs(&self) -> AppResult<AumInputs> {
+        let config = &self.config;
+        let calls_before = self.client.api_calls();
+        let mut data = self
+            .client
+            .fetch_aum_data_with_options(
+                &config.um_positions,
+                &config.spot_assets,
+                &fetch_options(config),
+            )
+            .await?;
+        let fetched_at = Utc::now();
+        stable_order(&mut data, config);
+        exclude(&mut data, &config.exclude_positions, &config.exclude_assets);
+        enforce_max_spot_assets(&self.client, config, &mut data).await?;
+        Span::current()
+            .record("positions", data.positions.len())
+            .record("spot_assets", data.spot_balances.len());
+        let options = aum_options(config);
+        let mut calculation = calculate_aum_with_options(&data, &self.prices, &options).await?;
+        add_valuations(
+            &mut calculation,
+            &config.report_currencies,
+            &config.usd_quote,
+            &self.prices,
+            &options,
+        )
+        .await?;
+        if !calculation.base_usd_price_overridden {
+            self.check_price_sanity(calculation.base_usd_price).await?;
+        }
+        self.check_aum_bounds(&data, &calculation)?;
+
+        Ok(AumInputs {
+            fetched_at,
+            data,
+            calculation,
+            api_calls: self.client.api_calls().wrapping_sub(calls_before),
+        })
+    }
+
+    /// Compares the base/USD price against its 24h weighted average for
+    /// `--price-sanity-pct`; warns, or fails under `--strict-price-sanity`.
+    async fn check_price_sanity(&self, price: Decimal) -> AppResult<()> {
+        let Some(max_pct) = self.config.price_sanity_pct else {
+            return Ok(());
+        };
+        let symbol = self.prices.base_usd_symbol();
+        let average = self.client.weighted_avg_price_24h(&symbol).await?;
+        let Some(deviation_pct) = pricing::deviation_pct(price, average) else {
+            return Ok(());
+        };
+        if deviation_pct <= max_pct {
+            return Ok(());
+        }
+        let err = AppError::PriceAnomaly {
+            symbol,
+            price,
+            average,
+            deviation_pct,
+            max_pct,
+        };
+        if self.config.strict_price_sanity {
+            return Err(err);
+        }
+        warn!(error = %err, "price sanity check tripped");
+        Ok(())
+    }
+
+    /// Runs the `--aum-bounds-tolerance-pct` self-check; warns, or fails
+    /// under `--aum-bounds-action fail`.
+    fn check_aum_bounds(&self, data: &BinanceData, calculation: &AumCalculation) -> AppResult<()> {
+        let Some(tolerance_pct) = self.config.aum_bounds_tolerance_pct else {
+            return Ok(());
+        };
+        match (
+            check_bounds(data, calculation, tolerance_pct),
+            self.config.aum_bounds_action,
+        ) {
+            (Err(err), AumBoundsAction::Fail) => Err(err),
+            (Err(err), AumBoundsAction::Warn) => {
+                warn!(error = %err, "AUM is outside the expected bounds");
+                Ok(())
+            }
+            (Ok(()), _) => Ok(()),
+        }
+    }
+
+    /// Sleeps until the next fetch is due.
+    async fn wait(&mut self) {
+        let config = &self.config;
+        let used_weight = self.client.used_weight_1m();
+        let mut interval = rate_limit::next_interval(
+            config.interval,
+            used_weight,
+            config.weight_limit,
+            config.weight_backoff_pct,
+            Utc::now(),
+        );
+        if let Some((wait, reason)) = self.backoff.filter(|(wait, _)| *wait > interval) {
+            warn!(
+                wait_secs = wait.as_secs(),
+                reason, "backing off before the next fetch"
+            );
+            interval = wait;
+        } else if interval > config.interval {
+            warn!(
+                used_weight,
+                weight_limit = config.weight_limit,
+                interval_secs = interval.as_secs(),
+                "request weight is high; extending loop interval"
+            );
+        }
+        tokio::time::sleep(rate_limit::jittered(
+            interval,
+            config.interval_jitter_pct,
+            &mut self.rng,
+        ))
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spot_asset_limit_fails_or_keeps_the_most_valuable() {
+        let balance = |asset: &str, amount: i64| SpotBalance::new(asset, Decimal::from(amount));
+        let mut data = BinanceData {
+            unimmr: Decimal::ZERO,
+            positions: vec![],
+            settlement_asset: "USDT".to_string(),
+            um_balance_usd: Decimal::ZERO,
+            spot_balances: vec![
+                balance("BTC", 1),
+                balance("USDT", 30),
+                balance("ETH", 2),
+                balance("DOGE", 20),
+            ],
+            classic_futures: None,
+            pm_account_actual_equity: Decimal::ZERO,
+            withdrawable_usd: Decimal::ZERO,
+            margin_liabilities: vec![],
+        };
+        let usd_prices = HashMap::from([
+            ("BTC".to_string(), Decimal::from(100_000)),
+            ("USDT".to_string(), Decimal::ONE),
+            ("ETH".to_string(), Decimal::from(4_000)),
+        ]);
+
+        assert!(limit_spot_assets(&mut data, 4, false, &usd_prices).is_ok());
+        let err =
+            limit_spot_assets(&mut data, 2, false, &usd_prices).expect_err("4 balances exceed 2");
+        assert_eq!(err.kind(), "too_many_spot_assets");
+        assert_eq!(data.spot_balances.len(), 4);
+
+        limit_spot_assets(&mut data, 2, true, &usd_prices).expect("truncation never fails");
+        let assets: Vec<_> = data
+            .spot_balances
+            .iter()
+            .map(|b| b.asset.as_str())
+            .collect();
+        // The largest amounts, USDT and unpriced DOGE, are worth the least.
+        assert_eq!(assets, ["BTC", "ETH"]);
+    }
+}
@@ -0,0 +1,821 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use binance_aum_fetch::binance_client::{BinanceClient, ClientOptions, FetchOptions};
+use binance_aum_fetch::clock::FixedTimeSource;
+use binance_aum_fetch::config::{Selection, SnapshotType};
+use binance_aum_fetch::error::AppError;
+use hmac::{Hmac, Mac};
+use rust_decimal::Decimal;
+use sha2::Sha256;
+use wiremock::matchers::{header, method, path, query_param, query_param_is_missing};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+const SECRET: &str = "test-secret";
+
+fn client_for(server: &MockServer) -> BinanceClient {
+    BinanceClient::with_http_client(
+        reqwest::Client::new(),
+        SECRET.to_string(),
+        server.uri(),
+        server.uri(),
+    )
+}
+
+fn is_correctly_signed(req: &Request) -> bool {
+    let Some(query) = req.url.query() else {
+        return false;
+    };
+    let Some((payload, signature)) = query.rsplit_once("&signature=") else {
+        return false;
+    };
+    if !payload.contains("timestamp=") {
+        return false;
+    }
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).expect("hmac key");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes()) == signature
+}
+
+#[tokio::test]
+async fn invalid_symbol_maps_to_api_message() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .and(query_param("symbol", "BTCXYZ"))
+        .respond_with(
+            ResponseTemplate::new(400).set_body_string(r#"{"code":-1121,"msg":"Invalid symbol."}"#),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let err = client_for(&server)
+        .ticker_price("BTCXYZ")
+        .await
+        .expect_err("invalid symbol must fail");
+    assert!(matches!(
+        err,
+        AppError::BinanceApiMessage { code: -1121, .. }
+    ));
+}
+
+#[tokio::test]
+async fn signed_requests_carry_valid_signature_and_map_timestamp_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/time"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"serverTime":1700000000000}"#))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(is_correctly_signed)
+        .respond_with(ResponseTemplate::new(400).set_body_string(
+            r#"{"code":-1021,"msg":"Timestamp for this request is outside of the recvWindow."}"#,
+        ))
+        .mount(&server)
+        .await;
+
+    let err = client_for(&server)
+        .fetch_aum_data(&["BTCUSDT".to_string()], &["BTC".to_string()])
+        .await
+        .expect_err("timestamp error must fail");
+    assert!(matches!(
+        err,
+        AppError::BinanceApiMessage { code: -1021, .. }
+    ));
+}
+
+#[tokio::test]
+async fn fixed_time_source_makes_signed_query_deterministic() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/account"))
+        .and(query_param("timestamp", "1700000000000"))
+        .and(query_param(
+            "signature",
+            "dccf2651b1d8329665bfddb0798eccd4650d986a9cfe5547b2f5822131e7620b",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"uniMMR":"5.0","actualEquity":"1000.5","virtualMaxWithdrawAmount":"250"}"#,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let withdrawable = client_for(&server)
+        .with_time_source(Arc::new(FixedTimeSource(1_700_000_000_000)))
+        .fetch_withdrawable_usd()
+        .await
+        .expect("signed request should match the fixed timestamp");
+    assert_eq!(withdrawable, Decimal::from(250));
+}
+
+#[tokio::test]
+async fn server_error_without_json_maps_to_status() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .respond_with(ResponseTemplate::new(503).set_body_string("<html>unavailable</html>"))
+        .mount(&server)
+        .await;
+
+    let err = client_for(&server)
+        .ticker_price("BTCUSDT")
+        .await
+        .expect_err("5xx must fail");
+    assert!(matches!(err, AppError::BinanceApi { status: 503, .. }));
+}
+
+#[tokio::test]
+async fn weight_error_in_body_maps_to_rate_limited() {
+    let server = MockServer::start().await;
+    let banned_until = chrono::Utc::now().timestamp_millis() + 30_000;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .respond_with(ResponseTemplate::new(400).set_body_string(format!(
+            r#"{{"code":-1003,"msg":"Way too much request weight used; IP banned until {banned_until}. Please use WebSocket Streams for live updates to avoid bans."}}"#
+        )))
+        .mount(&server)
+        .await;
+
+    let err = client_for(&server)
+        .ticker_price("BTCUSDT")
+        .await
+        .expect_err("weight error must fail");
+    let AppError::RateLimited { retry_after, .. } = err else {
+        panic!("expected RateLimited, got {err:?}");
+    };
+    let retry_after = retry_after.expect("ban expiry is parsed as a retry hint");
+    assert!(retry_after > Duration::from_secs(20) && retry_after <= Duration::from_secs(30));
+}
+
+#[tokio::test]
+async fn maintenance_response_maps_to_maintenance() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .respond_with(
+            ResponseTemplate::new(503)
+                .set_body_string(r#"{"code":-1016,"msg":"This service is no longer available."}"#),
+        )
+        .mount(&server)
+        .await;
+
+    let err = client_for(&server)
+        .ticker_price("BTCUSDT")
+        .await
+        .expect_err("maintenance must fail");
+    assert!(matches!(err, AppError::Maintenance { .. }));
+}
+
+#[tokio::test]
+async fn timestamp_error_resyncs_clock_and_retries_once() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/time"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"serverTime":1700000000000}"#))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/account"))
+        .respond_with(ResponseTemplate::new(400).set_body_string(
+            r#"{"code":-1021,"msg":"Timestamp for this request is outside of the recvWindow."}"#,
+        ))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/account"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"uniMMR":"5.0","actualEquity":"1000.5","virtualMaxWithdrawAmount":"250"}"#,
+        ))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/um/positionRisk"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/balance"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/account"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"balances":[]}"#))
+        .mount(&server)
+        .await;
+
+    let data = client_for(&server)
+        .fetch_aum_data(&["BTCUSDT".to_string()], &["BTC".to_string()])
+        .await
+        .expect("retry after re-sync should succeed");
+    assert_eq!(data.pm_account_actual_equity.to_string(), "1000.5");
+}
+
+#[tokio::test]
+async fn wait_for_api_retries_ping_until_it_answers() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ping"))
+        .respond_with(ResponseTemplate::new(503).set_body_string("unavailable"))
+        .up_to_n_times(2)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ping"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = client_for(&server);
+    client
+        .wait_for_api(Duration::from_secs(10))
+        .await
+        .expect("ping should succeed on the third attempt");
+    assert_eq!(client.api_calls(), 3);
+}
+
+#[tokio::test]
+async fn wait_for_api_gives_up_with_the_last_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ping"))
+        .respond_with(ResponseTemplate::new(503).set_body_string("unavailable"))
+        .mount(&server)
+        .await;
+
+    let err = client_for(&server)
+        .wait_for_api(Duration::from_millis(100))
+        .await
+        .expect_err("api never answers");
+    assert!(matches!(err, AppError::BinanceApi { status: 503, .. }));
+}
+
+#[tokio::test]
+async fn empty_selections_skip_spot_and_um_endpoints() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/account"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"uniMMR":"5.0","actualEquity":"1000.5","virtualMaxWithdrawAmount":"250"}"#,
+        ))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/balance"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/um/positionRisk"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .expect(0)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/account"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"balances":[]}"#))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let data = client_for(&server)
+        .fetch_aum_data(&[], &[])
+        .await
+        .expect("PM-only fetch should succeed");
+    assert!(data.positions.is_empty());
+    assert!(data.spot_balances.is_empty());
+}
+
+#[tokio::test]
+async fn spot_only_fetch_skips_portfolio_margin() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/account"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string(r#"{"balances":[{"asset":"BTC","free":"0.5","locked":"0"}]}"#),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(wiremock::matchers::path_regex("^/papi/"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let options = FetchOptions {
+        portfolio_margin: false,
+        ..FetchOptions::default()
+    };
+    let data = client_for(&server)
+        .fetch_aum_data_with_options(
+            &Selection::from(&["BTCUSDT".to_string()][..]),
+            &Selection::from(&["BTC".to_string()][..]),
+            &options,
+        )
+        .await
+        .expect("spot-only fetch should succeed");
+    assert!(data.positions.is_empty());
+    assert_eq!(data.spot_balances.len(), 1);
+    assert_eq!(data.pm_account_actual_equity, Decimal::ZERO);
+    assert_eq!(data.withdrawable_usd, Decimal::ZERO);
+}
+
+#[tokio::test]
+async fn spot_permission_error_names_the_permission() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/account"))
+        .respond_with(ResponseTemplate::new(401).set_body_string(
+            r#"{"code":-2015,"msg":"Invalid API-key, IP, or permissions for action."}"#,
+        ))
+        .mount(&server)
+        .await;
+
+    let options = FetchOptions {
+        portfolio_margin: false,
+        ..FetchOptions::default()
+    };
+    let err = client_for(&server)
+        .fetch_aum_data_with_options(
+            &Selection::from(&[][..]),
+            &Selection::from(&["BTC".to_string()][..]),
+            &options,
+        )
+        .await
+        .expect_err("spot account should be rejected");
+    assert!(matches!(
+        err,
+        AppError::MissingPermission {
+            endpoint: "/api/v3/account",
+            code: -2015,
+            ..
+        }
+    ));
+    assert_eq!(err.kind(), "missing_permission");
+    assert!(err.to_string().contains("Enable Reading"));
+}
+
+#[tokio::test]
+async fn empty_position_risk_means_no_positions() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/um/positionRisk"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .mount(&server)
+        .await;
+
+    let positions = client_for(&server)
+        .fetch_positions(&Selection::All)
+        .await
+        .expect("an empty array is a valid response");
+    assert!(positions.is_empty());
+}
+
+#[tokio::test]
+async fn malformed_position_risk_reports_the_body() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/um/positionRisk"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"positions":null}"#))
+        .mount(&server)
+        .await;
+
+    let err = client_for(&server)
+        .fetch_positions(&Selection::All)
+        .await
+        .expect_err("an object is not a position list");
+    assert_eq!(err.kind(), "unexpected_response");
+    assert!(err.to_string().contains(r#"{"positions":null}"#));
+}
+
+#[tokio::test]
+async fn classic_futures_account_is_fetched_alongside_pm() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/account"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"uniMMR":"5.0","actualEquity":"1000","virtualMaxWithdrawAmount":"250"}"#,
+        ))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/balance"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fapi/v2/account"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"totalMarginBalance":"550.5","totalWalletBalance":"500","totalUnrealizedProfit":"50.5"}"#,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fapi/v2/balance"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(
+                r#"[{"asset":"USDT","balance":"500"},{"asset":"BNB","balance":"0"}]"#,
+            ),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = FetchOptions {
+        include_classic_futures: true,
+        ..FetchOptions::default()
+    };
+    let data = client_for(&server)
+        .with_fapi_base_url(server.uri())
+        .fetch_aum_data_with_options(
+            &Selection::from(&[][..]),
+            &Selection::from(&[][..]),
+            &options,
+        )
+        .await
+        .expect("fetch should succeed");
+
+    assert_eq!(data.pm_account_actual_equity, Decimal::from(1000));
+    let classic = data.classic_futures.expect("classic futures requested");
+    assert_eq!(classic.equity_usd, Decimal::new(5505, 1));
+    assert_eq!(classic.balances.len(), 1);
+    assert_eq!(classic.balances[0].asset, "USDT");
+}
+
+#[tokio::test]
+async fn verify_reports_rejected_key_with_hint() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ping"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/ping"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/account"))
+        .respond_with(ResponseTemplate::new(401).set_body_string(
+            r#"{"code":-2015,"msg":"Invalid API-key, IP, or permissions for action."}"#,
+        ))
+        .mount(&server)
+        .await;
+
+    let outcomes = client_for(&server).verify().await;
+    let failed: Vec<_> = outcomes
+        .iter()
+        .filter_map(|(endpoint, outcome)| outcome.as_ref().err().map(|err| (*endpoint, err)))
+        .collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].0, "/papi/v1/account");
+    assert!(failed[0].1.hint().is_some());
+}
+
+#[tokio::test]
+async fn sends_default_user_agent() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .and(header(
+            "user-agent",
+            concat!("binance-aum-fetch/", env!("CARGO_PKG_VERSION")),
+        ))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(r#"{"symbol":"BTCUSDT","price":"1"}"#),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = BinanceClient::new_with_options(
+        "key".to_string(),
+        SECRET.to_string(),
+        server.uri(),
+        server.uri(),
+        &ClientOptions::default(),
+    )
+    .expect("client should build");
+    client
+        .ticker_price("BTCUSDT")
+        .await
+        .expect("request should match the user agent");
+}
+
+#[tokio::test]
+async fn egress_ip_is_read_from_echo_service() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/ip"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("203.0.113.7\n"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let ip = client_for(&server)
+        .egress_ip(&format!("{}/ip", server.uri()))
+        .await
+        .expect("echo service should answer");
+    assert_eq!(ip, "203.0.113.7");
+}
+
+#[tokio::test]
+async fn futures_snapshot_is_rebuilt_as_aum_inputs() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/sapi/v1/accountSnapshot"))
+        .and(query_param("type", "FUTURES"))
+        .and(query_param("startTime", "1719705600000"))
+        .and(is_correctly_signed)
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"code":200,"msg":"","snapshotVos":[{"type":"futures","updateTime":1719791999000,"data":{
+                "assets":[
+                    {"asset":"USDT","marginBalance":"1200.5","walletBalance":"1180"},
+                    {"asset":"BNB","marginBalance":"2","walletBalance":"2"},
+                    {"asset":"ETH","marginBalance":"0.5","walletBalance":"0.5"}
+                ],
+                "position":[{"symbol":"BTCUSDT","positionAmt":"0.01","markPrice":"61000","unRealizedProfit":"20.5","entryPrice":"59000"}]
+            }}]}"#,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let date = chrono::NaiveDate::from_ymd_opt(2024, 6, 30).unwrap();
+    let (taken_at, data) = client_for(&server)
+        .fetch_snapshot_data(
+            SnapshotType::Futures,
+            date,
+            &Selection::All,
+            &Selection::Only(vec!["ETH".to_string()]),
+            "USDT",
+        )
+        .await
+        .expect("snapshot should decode");
+
+    assert_eq!(taken_at.timestamp_millis(), 1719791999000);
+    assert_eq!(data.pm_account_actual_equity, Decimal::new(12005, 1));
+    assert_eq!(data.um_balance_usd, Decimal::from(1180));
+    assert_eq!(data.positions.len(), 1);
+    assert_eq!(data.positions[0].pnl, Decimal::new(205, 1));
+    assert_eq!(data.spot_balances.len(), 1);
+    assert_eq!(data.spot_balances[0].asset, "ETH");
+}
+
+#[tokio::test]
+async fn empty_snapshot_list_is_reported_as_no_snapshot() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/sapi/v1/accountSnapshot"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(r#"{"code":200,"msg":"","snapshotVos":[]}"#),
+        )
+        .mount(&server)
+        .await;
+
+    let date = chrono::NaiveDate::from_ymd_opt(2024, 6, 30).unwrap();
+    let err = client_for(&server)
+        .fetch_snapshot_data(
+            SnapshotType::Spot,
+            date,
+            &Selection::All,
+            &Selection::All,
+            "USDT",
+        )
+        .await
+        .expect_err("an empty snapshot list has nothing to report");
+
+    assert!(matches!(
+        err,
+        AppError::NoSnapshot { kind: "SPOT", date: d } if d == date
+    ));
+}
+
+#[tokio::test]
+async fn withdrawable_amount_only_reads_pm_account() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/account"))
+        .and(is_correctly_signed)
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"uniMMR":"5.0","actualEquity":"1000.5","virtualMaxWithdrawAmount":"250.75"}"#,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let withdrawable = client_for(&server)
+        .fetch_withdrawable_usd()
+        .await
+        .expect("account should decode");
+    assert_eq!(withdrawable, Decimal::new(25075, 2));
+}
+
+#[test]
+fn unreadable_ca_cert_is_a_config_error() {
+    let path = std::env::temp_dir().join("binance_aum_fetch_bad_ca.pem");
+    std::fs::write(&path, "not a certificate").expect("temp file");
+
+    let options = ClientOptions {
+        ca_cert: Some(path.clone()),
+        ..ClientOptions::default()
+    };
+    let err = BinanceClient::new_with_options(
+        "key".to_string(),
+        SECRET.to_string(),
+        "http://127.0.0.1:9".to_string(),
+        "http://127.0.0.1:9".to_string(),
+        &options,
+    )
+    .expect_err("garbage PEM must be rejected");
+    std::fs::remove_file(path).ok();
+    assert!(matches!(
+        err,
+        AppError::InvalidConfig {
+            field: "CA_CERT",
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn ticker_prices_sends_symbols_as_an_array() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .and(query_param("symbols", r#"["BTCUSDT","ETHBTC"]"#))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"[{"symbol":"BTCUSDT","price":"100000"},{"symbol":"ETHBTC","price":"0.025"}]"#,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let prices = client_for(&server)
+        .ticker_prices(&["BTCUSDT".to_string(), "ETHBTC".to_string()])
+        .await
+        .expect("multi-symbol ticker should decode");
+    assert_eq!(prices["BTCUSDT"], Decimal::from(100_000));
+    assert_eq!(prices["ETHBTC"], Decimal::new(25, 3));
+}
+
+#[tokio::test]
+async fn ticker_prices_without_symbols_lists_every_ticker() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .and(query_param_is_missing("symbols"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"[{"symbol":"BTCUSDT","price":"100000"},{"symbol":"ETHUSDT","price":"4000"}]"#,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let prices = client_for(&server)
+        .ticker_prices(&[])
+        .await
+        .expect("full ticker list should decode");
+    assert_eq!(prices.len(), 2);
+    assert_eq!(prices["ETHUSDT"], Decimal::from(4_000));
+}
+
+#[tokio::test]
+async fn um_income_queries_the_window_and_parses_records() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/um/income"))
+        .and(query_param("startTime", "1700000000000"))
+        .and(query_param("endTime", "1700003599999"))
+        .and(query_param("limit", "1000"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"[
+                {"symbol":"BTCUSDT","incomeType":"REALIZED_PNL","income":"12.5","asset":"USDT","info":"","time":1700000100000,"tranId":1,"tradeId":"7"},
+                {"symbol":"BTCUSDT","incomeType":"COMMISSION","income":"-0.01","asset":"BNB","info":"","time":1700000100000,"tranId":2,"tradeId":"7"},
+                {"symbol":"","incomeType":"TRANSFER","income":"50","asset":"USDT","info":"","time":1700000200000,"tranId":3,"tradeId":""}
+            ]"#,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let from = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let to = chrono::DateTime::from_timestamp(1_700_003_600, 0).unwrap();
+    let records = client_for(&server)
+        .um_income(from, to)
+        .await
+        .expect("income should decode");
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].income_type, "REALIZED_PNL");
+    assert_eq!(records[0].amount, Decimal::new(125, 1));
+    assert_eq!(records[1].asset, "BNB");
+    assert_eq!(records[2].time.timestamp(), 1_700_000_200);
+}
+
+#[tokio::test]
+async fn um_income_pages_without_losing_or_repeating_a_shared_millisecond() {
+    const START: i64 = 1_700_000_000_000;
+    let record = |tran_id: i64, time: i64| {
+        serde_json::json!({
+            "symbol": "BTCUSDT", "incomeType": "FUNDING_FEE", "income": "1",
+            "asset": "USDT", "info": "", "time": time, "tranId": tran_id, "tradeId": ""
+        })
+    };
+    // Two records per millisecond; the page ends halfway through the last one.
+    let first: Vec<_> = (0..1000).map(|i| record(i, START + i / 2)).collect();
+    let boundary = START + 499;
+    let second = vec![
+        record(999, boundary),
+        record(1000, boundary),
+        record(1001, boundary + 1),
+    ];
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/um/income"))
+        .and(query_param("startTime", START.to_string()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(first))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/um/income"))
+        .and(query_param("startTime", boundary.to_string()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(second))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let from = chrono::DateTime::from_timestamp_millis(START).unwrap();
+    let to = chrono::DateTime::from_timestamp_millis(START + 3_600_000).unwrap();
+    let records = client_for(&server)
+        .um_income(from, to)
+        .await
+        .expect("both pages should decode");
+    assert_eq!(records.len(), 1002);
+    let at_boundary = records
+        .iter()
+        .filter(|r| r.time.timestamp_millis() == boundary)
+        .count();
+    assert_eq!(at_boundary, 3);
+}
+
+#[tokio::test]
+async fn um_income_fails_on_a_full_page_within_one_millisecond() {
+    let record = |tran_id: i64| {
+        serde_json::json!({
+            "symbol": "BTCUSDT", "incomeType": "FUNDING_FEE", "income": "1",
+            "asset": "USDT", "info": "", "time": 1_700_000_000_000i64, "tranId": tran_id
+        })
+    };
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/um/income"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json((0..1000).map(record).collect::<Vec<_>>()),
+        )
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let from = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let to = chrono::DateTime::from_timestamp(1_700_003_600, 0).unwrap();
+    let err = client_for(&server)
+        .um_income(from, to)
+        .await
+        .expect_err("paging cannot move past the millisecond");
+    assert_eq!(err.kind(), "unexpected_response");
+}
+
+#[tokio::test]
+async fn max_rps_makes_requests_wait_for_a_slot() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(r#"{"symbol":"BTCUSDT","price":"100000"}"#),
+        )
+        .expect(4)
+        .mount(&server)
+        .await;
+
+    let client = client_for(&server).with_max_rps(std::num::NonZeroU32::new(2).unwrap());
+    let started = std::time::Instant::now();
+    let prices = futures::future::join_all((0..4).map(|_| client.ticker_price("BTCUSDT"))).await;
+    assert!(prices.iter().all(Result::is_ok));
+    // Two requests go out at once; the other two wait 0.5s each.
+    assert!(started.elapsed() >= Duration::from_millis(900));
+}
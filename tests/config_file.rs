@@ -0,0 +1,45 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+
+use binance_aum_fetch::config::Cli;
+use binance_aum_fetch::config_file;
+use clap::Parser;
+
+#[test]
+fn profile_overrides_dotenv_but_not_the_real_environment() {
+    let dir = std::env::temp_dir().join(format!("aum-config-file-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("temp dir");
+    let dotenv = dir.join(".env");
+    std::fs::write(
+        &dotenv,
+        "BINANCE_UM_POSITIONS=BTCUSDT,ETHUSDT,SOLUSDT\nBINANCE_PAPI_BASE_URL=https://papi.binance.com\n",
+    )
+    .unwrap();
+    let file = dir.join("binance_aum_fetch.toml");
+    std::fs::write(
+        &file,
+        r#"
+            quote_currency = "EUR"
+
+            [profiles.staging]
+            binance_um_positions = "BTCUSDT"
+            binance_papi_base_url = "https://testnet.binancefuture.com"
+        "#,
+    )
+    .unwrap();
+
+    std::env::set_var("QUOTE_CURRENCY", "USDT");
+    let process_env: HashSet<OsString> = std::env::vars_os().map(|(key, _)| key).collect();
+    dotenvy::from_path(&dotenv).expect(".env should load");
+    config_file::export(&file, Some("staging"), &process_env).expect("config should export");
+
+    let cli = Cli::parse_from(["binance_aum_fetch"]);
+    assert_eq!(cli.binance_um_positions, "BTCUSDT");
+    assert_eq!(
+        cli.binance_papi_base_url,
+        "https://testnet.binancefuture.com"
+    );
+    assert_eq!(cli.quote_currency, "USDT");
+
+    std::fs::remove_dir_all(&dir).ok();
+}
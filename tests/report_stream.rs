@@ -0,0 +1,324 @@
+use std::pin::pin;
+
+use binance_aum_fetch::config::{AppConfig, Cli};
+use binance_aum_fetch::models::{BaseAmount, BaseUnits, BinanceData, SpotBalance, UmPosition};
+use binance_aum_fetch::stream::{bench, exclude, report_stream, run_loop, stable_order};
+use clap::Parser;
+use futures::StreamExt;
+use rust_decimal::Decimal;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// `/papi/v1/account` reporting `actual_equity` USDT of equity.
+fn pm_account(actual_equity: &str) -> Mock {
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/account"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+            r#"{{"uniMMR":"5.0","actualEquity":"{actual_equity}","virtualMaxWithdrawAmount":"1"}}"#
+        )))
+}
+
+/// `/papi/v1/balance` answering with `body`.
+fn pm_balance(body: &str) -> Mock {
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/balance"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(body))
+}
+
+/// Config reading only the portfolio margin account from `server`, plus
+/// `flags`.
+fn config(server: &MockServer, flags: &[&str]) -> AppConfig {
+    let api_base_url = format!("--binance-api-base-url={}", server.uri());
+    let papi_base_url = format!("--binance-papi-base-url={}", server.uri());
+    let cli = Cli::parse_from(
+        [
+            "binance_aum_fetch",
+            "--binance-api-key=key",
+            "--binance-api-secret=secret",
+            "--no-um",
+            "--no-spot",
+            &api_base_url,
+            &papi_base_url,
+        ]
+        .into_iter()
+        .chain(flags.iter().copied()),
+    );
+    AppConfig::from_cli(cli).expect("config should be valid")
+}
+
+/// Data holding one unit of each position and spot asset.
+fn data(positions: &[&str], spot: &[&str]) -> BinanceData {
+    BinanceData {
+        unimmr: Decimal::ZERO,
+        positions: positions
+            .iter()
+            .map(|symbol| UmPosition {
+                symbol: symbol.to_string(),
+                amount: Decimal::ONE,
+                pnl: Decimal::ZERO,
+                mark_price: Decimal::ONE,
+                notional: Decimal::ONE,
+            })
+            .collect(),
+        settlement_asset: "USDT".to_string(),
+        um_balance_usd: Decimal::ZERO,
+        spot_balances: spot
+            .iter()
+            .map(|&asset| SpotBalance::new(asset, Decimal::ONE))
+            .collect(),
+        classic_futures: None,
+        pm_account_actual_equity: Decimal::ZERO,
+        withdrawable_usd: Decimal::ZERO,
+        margin_liabilities: vec![],
+    }
+}
+
+/// Prices BTC at 50000 USDT, so an `actualEquity` of 125000 is 2.5 BTC.
+async fn mount_btc_usdt(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(r#"{"symbol":"BTCUSDT","price":"50000"}"#),
+        )
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn once_stream_yields_a_single_report() {
+    let server = MockServer::start().await;
+    pm_account("125000").expect(1).mount(&server).await;
+    pm_balance("[]").mount(&server).await;
+    mount_btc_usdt(&server).await;
+
+    let config = config(&server, &["--once"]);
+
+    let mut reports = pin!(report_stream(config));
+    let report = reports
+        .next()
+        .await
+        .expect("stream yields a report")
+        .expect("report should compute");
+    assert_eq!(
+        report.calculation.aum_base_18dp,
+        BaseAmount::new(Decimal::new(25, 1))
+    );
+    assert_eq!(report.calculation.aum_base_u8, BaseUnits::new(250_000_000));
+    // `/papi/v1/account`, `/papi/v1/balance` and the BTCUSDT ticker.
+    assert_eq!(report.api_calls, 3);
+    assert!(reports.next().await.is_none());
+}
+
+#[tokio::test]
+async fn run_loop_hands_each_report_to_the_callback() {
+    let server = MockServer::start().await;
+    pm_account("125000").mount(&server).await;
+    pm_balance("[]").mount(&server).await;
+    mount_btc_usdt(&server).await;
+
+    let config = config(&server, &["--once"]);
+
+    let mut seen = Vec::new();
+    run_loop(config, async |result| {
+        seen.push(result.map(|report| report.calculation.aum_base_u8).ok())
+    })
+    .await
+    .expect("the single iteration should succeed");
+    assert_eq!(seen, [Some(BaseUnits::new(250_000_000))]);
+}
+
+#[tokio::test]
+async fn run_loop_stops_on_a_rejected_api_key() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/account"))
+        .respond_with(
+            ResponseTemplate::new(401)
+                .set_body_string(r#"{"code":-2015,"msg":"Invalid API-key, IP, or permissions"}"#),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    pm_balance("[]").mount(&server).await;
+    mount_btc_usdt(&server).await;
+
+    let config = config(&server, &["--once=false", "--interval=1"]);
+
+    let mut iterations = 0;
+    let err = run_loop(config, async |_| iterations += 1)
+        .await
+        .expect_err("a rejected key must end the loop");
+    assert!(err.is_permanent(), "unexpected error: {err}");
+    assert_eq!(iterations, 1);
+}
+
+#[tokio::test]
+async fn include_raw_attaches_the_upstream_bodies() {
+    let server = MockServer::start().await;
+    pm_account("125000").mount(&server).await;
+    pm_balance(r#"[{"asset":"USDT","umWalletBalance":"10"}]"#)
+        .mount(&server)
+        .await;
+    mount_btc_usdt(&server).await;
+
+    let config = config(&server, &["--once", "--include-raw"]);
+
+    let mut reports = pin!(report_stream(config));
+    let report = reports
+        .next()
+        .await
+        .expect("stream yields a report")
+        .expect("report should compute");
+    let raw = report.raw.expect("raw bodies are attached");
+    assert_eq!(raw["/papi/v1/account"]["uniMMR"], "5.0");
+    assert_eq!(raw["/papi/v1/balance"][0]["umWalletBalance"], "10");
+    // Skipped by `--no-um`/`--no-spot`, so never fetched.
+    assert_eq!(raw.len(), 2);
+}
+
+#[tokio::test]
+async fn bench_counts_failed_iterations() {
+    let server = MockServer::start().await;
+    pm_account("125000").up_to_n_times(2).mount(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/papi/v1/account"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    pm_balance("[]").mount(&server).await;
+    mount_btc_usdt(&server).await;
+
+    let config = config(&server, &[]);
+
+    let summary = bench(config, 3).await.expect("bench should run");
+    assert_eq!(summary.iterations, 3);
+    assert_eq!(summary.errors, 1);
+    assert_eq!(summary.latencies.len(), 2);
+    assert_eq!(summary.percentile(50), summary.latencies.first().copied());
+    assert_eq!(summary.percentile(99), summary.latencies.last().copied());
+}
+
+#[tokio::test]
+async fn strict_price_sanity_fails_a_report_far_from_the_24h_average() {
+    let server = MockServer::start().await;
+    pm_account("100000").mount(&server).await;
+    pm_balance("[]").mount(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(r#"{"symbol":"BTCUSDT","price":"50000"}"#),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/24hr"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string(r#"{"symbol":"BTCUSDT","weightedAvgPrice":"100000"}"#),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let config = config(
+        &server,
+        &["--once", "--price-sanity-pct=10", "--strict-price-sanity"],
+    );
+
+    let mut reports = pin!(report_stream(config));
+    let err = reports
+        .next()
+        .await
+        .expect("stream yields a result")
+        .expect_err("a 50% deviation should trip the check");
+    assert_eq!(err.kind(), "price_anomaly");
+}
+
+#[tokio::test]
+async fn price_jump_between_runs_skips_the_report() {
+    let server = MockServer::start().await;
+    pm_account("100000").mount(&server).await;
+    pm_balance("[]").mount(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(r#"{"symbol":"BTCUSDT","price":"50000"}"#),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/price"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(r#"{"symbol":"BTCUSDT","price":"60000"}"#),
+        )
+        .mount(&server)
+        .await;
+
+    let config = config(
+        &server,
+        &[
+            "--once=false",
+            "--interval=0",
+            "--min-interval=0",
+            "--max-price-deviation-between-runs=10",
+        ],
+    );
+
+    let mut reports = pin!(report_stream(config));
+    let first = reports.next().await.expect("first result");
+    assert_eq!(
+        first
+            .expect("first run has nothing to compare with")
+            .calculation
+            .aum_base_u8,
+        BaseUnits::new(200_000_000)
+    );
+    let err = reports
+        .next()
+        .await
+        .expect("second result")
+        .expect_err("a 20% move should trip the breaker");
+    assert_eq!(err.kind(), "price_jump");
+    // The new price holds, so the next run is accepted.
+    assert!(reports.next().await.expect("third result").is_ok());
+}
+
+#[test]
+fn denylists_drop_positions_and_assets() {
+    let mut data = data(&["BTCUSDT", "LUNAUSDT"], &["BTC", "lunc", "ETH"]);
+
+    exclude(&mut data, &["LUNAUSDT".to_string()], &["LUNC".to_string()]);
+    let symbols: Vec<_> = data.positions.iter().map(|p| p.symbol.as_str()).collect();
+    assert_eq!(symbols, ["BTCUSDT"]);
+    let assets: Vec<_> = data
+        .spot_balances
+        .iter()
+        .map(|b| b.asset.as_str())
+        .collect();
+    assert_eq!(assets, ["BTC", "ETH"]);
+}
+
+#[test]
+fn config_sort_keeps_the_fetched_spot_order() {
+    let fetched = data(&["SOLUSDT", "BTCUSDT"], &["USDT", "BTC", "ETH"]);
+    let spot_order = |sort: &str| {
+        let cli = Cli::parse_from([
+            "binance_aum_fetch",
+            "--binance-api-key=key",
+            "--binance-api-secret=secret",
+            &format!("--sort-contributions={sort}"),
+        ]);
+        let config = AppConfig::from_cli(cli).expect("config should be valid");
+        let mut data = fetched.clone();
+        stable_order(&mut data, &config);
+        assert_eq!(data.positions[0].symbol, "BTCUSDT");
+        data.spot_balances
+            .into_iter()
+            .map(|b| b.asset)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(spot_order("config"), ["USDT", "BTC", "ETH"]);
+    assert_eq!(spot_order("value"), ["BTC", "ETH", "USDT"]);
+}